@@ -69,45 +69,6 @@ vec3 fresnelSchlick(float cosTheta, vec3 F0)
 // ----------------------------------------------------------------------------
 
 
-// https://github.com/dmnsgn/glsl-tone-map/blob/master/aces.glsl
-vec3 aces(vec3 x) {
-	const float a = 2.51;
-	const float b = 0.03;
-	const float c = 2.43;
-	const float d = 0.59;
-	const float e = 0.14;
-	return clamp((x * (a * x + b)) / (x * (c * x + d) + e), 0.0, 1.0);
-}
-
-// can be optimized into lut (compute can gen it)
-float GTTonemap(float x) {
-	float m = 0.22; // linear section start
-	float a = 1.0;  // contrast
-	float c = 1.33; // black brightness
-	float P = 1.0;  // maximum brightness
-	float l = 0.4;  // linear section length
-	float l0 = ((P-m)*l) / a; // 0.312
-	float S0 = m + l0; // 0.532
-	float S1 = m + a * l0; // 0.532
-	float C2 = (a*P) / (P - S1); // 2.13675213675
-	float L = m + a * (x - m);
-	float T = m * pow(x/m, c);
-	float S = P - (P - S1) * exp(-C2*(x - S0)/P);
-	float w0 = 1 - smoothstep(0.0, m, x);
-	float w2 = (x < m+l)?0:1;
-	float w1 = 1 - w0 - w2;
-	return float(T * w0 + L * w1 + S * w2);
-}
-
-// this costs about 0.2-0.3ms more than aces, as-is
-vec3 GTTonemap(vec3 x) {
-	return vec3(
-		GTTonemap(x.r),
-		GTTonemap(x.g),
-		GTTonemap(x.b)
-	);
-}
-
 void main()
 {
 	vec3 N = normalize(inNormal);
@@ -148,11 +109,6 @@ void main()
 	vec3 ambient = vec3(0.03) * parameters.albedo * parameters.ao;
 	vec3 color = ambient + Lo;
 
-	// vec3 tone_mapped_color = color;
-
-	// vec3 tone_mapped_color = aces(color);
-	// vec3 tone_mapped_color = GTTonemap(color);
-	// tone_mapped_color = pow(tone_mapped_color, vec3(1.0/2.2));
-
+	// Tonemapping is done by the apply pass of bloom.comp
 	outColor = vec4(color + (parameters.emissive_color * parameters.emissive_intensity), 1.0);
 }
\ No newline at end of file
@@ -1,312 +1,1832 @@
 extern crate ultraviolet as uv;
 
-use crate::vulkan_engine::{self, image, push_constant};
+use std::mem::size_of;
+
+use crate::bloom_fft;
+use crate::bloom_spd;
+use crate::blue_noise;
+use crate::color_lut;
+use crate::exposure;
+use crate::post_process;
+use crate::vulkan_engine::{
+	self, buffer, descriptor, image, pipeline, push_constant, render_graph, shader_module,
+};
 
 use ash::vk;
+use gpu_alloc::UsageFlags;
 
-pub const BLOOM_MIP_COUNT: usize = 7usize;
+pub const DEFAULT_BLOOM_MIP_COUNT: u32 = 7;
+/// Output indices are packed on 7 bits in the push constant (3 * mip count + 1 < 128).
+pub const MAX_BLOOM_MIP_COUNT: usize = 42;
+/// Size of the per-mip tint array of `bloom.comp`, deeper mips share the last tint.
+pub const BLOOM_MIP_TINT_COUNT: usize = 16;
 pub const MODE_PREFILTER: u32 = 0;
 pub const MODE_DOWNSAMPLE: u32 = 1;
 pub const MODE_UPSAMPLE_FIRST: u32 = 2;
 pub const MODE_UPSAMPLE: u32 = 3;
 pub const MODE_APPLY: u32 = 4;
+pub const MODE_BLUR_HORIZONTAL: u32 = 5;
+pub const MODE_BLUR_VERTICAL: u32 = 6;
+pub const MODE_DEBUG: u32 = 7;
 
 #[derive(Default, Clone, Copy)]
 pub struct BloomConstant {
 	pub mode_lod_in_out_bloom: u32,
+	/// Incremented every frame by `BloomPass::passes`, moves the dither pattern.
+	pub frame_index: u32,
 }
 
-fn update_descriptor(
-	engine: &vulkan_engine::VulkanEngine,
-	current_image: usize,
-	bloom_images: &mut Vec<image::Image>,
-) {
-	let mut output_image_descr_info =
-		Vec::<vk::DescriptorImageInfo>::with_capacity(3 * BLOOM_MIP_COUNT + 1);
-	for i in 0..3 {
-		for j in 0..BLOOM_MIP_COUNT {
-			output_image_descr_info.push(
-				vk::DescriptorImageInfo::builder()
-					.image_layout(vk::ImageLayout::GENERAL)
-					.image_view(bloom_images[i].mip_image_views[j])
+const MODE_SHIFT: u32 = 28;
+const LOD_SHIFT: u32 = 21;
+const INPUT_SHIFT: u32 = 14;
+const OUTPUT_SHIFT: u32 = 7;
+const BLOOM_SHIFT: u32 = 0;
+const MODE_MAX: u32 = 15;
+const FIELD_MAX: u32 = 127;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::enum_variant_names)]
+pub enum BloomDispatchError {
+	ModeOutOfRange(u32),
+	LodOutOfRange(u32),
+	InputOutOfRange(u32),
+	OutputOutOfRange(u32),
+	BloomOutOfRange(u32),
+}
+
+impl std::fmt::Display for BloomDispatchError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			BloomDispatchError::ModeOutOfRange(v) => {
+				write!(f, "bloom mode {} doesn't fit in 4 bits", v)
+			}
+			BloomDispatchError::LodOutOfRange(v) => {
+				write!(f, "bloom lod {} doesn't fit in 7 bits", v)
+			}
+			BloomDispatchError::InputOutOfRange(v) => {
+				write!(f, "bloom input index {} doesn't fit in 7 bits", v)
+			}
+			BloomDispatchError::OutputOutOfRange(v) => {
+				write!(f, "bloom output index {} doesn't fit in 7 bits", v)
+			}
+			BloomDispatchError::BloomOutOfRange(v) => {
+				write!(f, "bloom texture index {} doesn't fit in 7 bits", v)
+			}
+		}
+	}
+}
+
+impl std::error::Error for BloomDispatchError {}
+
+/// One dispatch of `bloom.comp`, packed in `BloomConstant::mode_lod_in_out_bloom` as
+/// `mode << 28 | lod << 21 | input << 14 | output << 7 | bloom`.
+///
+/// `input` and `bloom` index the `input_texture` and `bloom_texture` arrays, `output`
+/// indexes `output_image`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BloomDispatch {
+	pub mode: u32,
+	pub lod: u32,
+	pub input: u32,
+	pub output: u32,
+	pub bloom: u32,
+}
+
+impl BloomDispatch {
+	/// Fails when a field overflows its bits instead of corrupting its neighbours.
+	/// `BloomPass` panics on the error: its steps come from a clamped mip count, so an
+	/// overflow is a bug, not a runtime condition.
+	pub fn encode(&self) -> Result<u32, BloomDispatchError> {
+		if self.mode > MODE_MAX {
+			return Err(BloomDispatchError::ModeOutOfRange(self.mode));
+		}
+		if self.lod > FIELD_MAX {
+			return Err(BloomDispatchError::LodOutOfRange(self.lod));
+		}
+		if self.input > FIELD_MAX {
+			return Err(BloomDispatchError::InputOutOfRange(self.input));
+		}
+		if self.output > FIELD_MAX {
+			return Err(BloomDispatchError::OutputOutOfRange(self.output));
+		}
+		if self.bloom > FIELD_MAX {
+			return Err(BloomDispatchError::BloomOutOfRange(self.bloom));
+		}
+		Ok(self.pack())
+	}
+
+	const fn pack(&self) -> u32 {
+		self.mode << MODE_SHIFT
+			| self.lod << LOD_SHIFT
+			| self.input << INPUT_SHIFT
+			| self.output << OUTPUT_SHIFT
+			| self.bloom << BLOOM_SHIFT
+	}
+
+	/// Unpacks the fields the same way `bloom.comp` does.
+	pub const fn decode(mode_lod_in_out_bloom: u32) -> BloomDispatch {
+		BloomDispatch {
+			mode: mode_lod_in_out_bloom >> MODE_SHIFT,
+			lod: mode_lod_in_out_bloom >> LOD_SHIFT & FIELD_MAX,
+			input: mode_lod_in_out_bloom >> INPUT_SHIFT & FIELD_MAX,
+			output: mode_lod_in_out_bloom >> OUTPUT_SHIFT & FIELD_MAX,
+			bloom: mode_lod_in_out_bloom >> BLOOM_SHIFT & FIELD_MAX,
+		}
+	}
+}
+
+// Pin the bit layout shared with `bloom.comp`.
+const _: () = {
+	let apply = BloomDispatch {
+		mode: MODE_APPLY,
+		lod: 0,
+		input: 3,
+		output: 21,
+		bloom: 2,
+	};
+	assert!(apply.pack() == 0x4000_CA82);
+	let full = BloomDispatch {
+		mode: MODE_MAX,
+		lod: FIELD_MAX,
+		input: FIELD_MAX,
+		output: FIELD_MAX,
+		bloom: FIELD_MAX,
+	};
+	assert!(full.pack() == u32::MAX);
+	let decoded = BloomDispatch::decode(0x4000_CA82);
+	assert!(decoded.mode == MODE_APPLY);
+	assert!(decoded.lod == 0);
+	assert!(decoded.input == 3);
+	assert!(decoded.output == 21);
+	assert!(decoded.bloom == 2);
+	let decoded = BloomDispatch::decode(0x1234_5678);
+	assert!(BloomDispatch::pack(&decoded) == 0x1234_5678);
+};
+
+#[derive(Clone, Copy)]
+pub struct BloomSettings {
+	pub threshold: f32,
+	pub knee: f32,
+	pub intensity: f32,
+	pub combine_constant: f32,
+	/// Requested number of mips, clamped by `clamp_mip_count`.
+	pub mip_count: u32,
+	pub tonemapper: Tonemapper,
+	/// Luminance mapped to white by `Tonemapper::ReinhardExtended`.
+	pub white_point: f32,
+	/// Scale of the lens dirt added on top of the bloom.
+	pub lens_dirt_intensity: f32,
+	pub shape: BloomShape,
+	/// Horizontal upsample radius, in texels, of `BloomShape::Anamorphic`.
+	pub stretch: f32,
+	pub algorithm: BloomAlgorithm,
+	/// Upper bound of the prefiltered color.
+	pub clamp_value: f32,
+	/// Weights the prefilter downsample by luma to suppress fireflies.
+	pub karis_average: bool,
+	/// Scale of each mip of the downsample chain in the upsample chain, unused by
+	/// `BloomAlgorithm::Fft`.
+	pub mip_weights: [f32; BLOOM_MIP_TINT_COUNT],
+	pub mip_tints: [[f32; 3]; BLOOM_MIP_TINT_COUNT],
+	/// Builds the downsample chain with `bloom_spd::SinglePassDownsampler` when the
+	/// resolution allows it, see `BloomSettings::uses_single_pass_downsample`.
+	pub single_pass_downsample: bool,
+	pub debug_view: BloomDebugView,
+	pub lens_flare: LensFlareSettings,
+	/// Blend between the graded and the original color, only used once a LUT is
+	/// loaded with `BloomPass::set_color_lut`.
+	pub lut_strength: f32,
+	pub dither: DitherMode,
+}
+
+impl Default for BloomSettings {
+	fn default() -> Self {
+		BloomSettings {
+			threshold: 1.0,
+			knee: 0.2,
+			intensity: 1.0,
+			combine_constant: 0.68,
+			mip_count: DEFAULT_BLOOM_MIP_COUNT,
+			tonemapper: Tonemapper::GranTurismo,
+			white_point: 4.0,
+			lens_dirt_intensity: 1.0,
+			shape: BloomShape::Symmetric,
+			stretch: 4.0,
+			algorithm: BloomAlgorithm::MipChain,
+			clamp_value: 20.0,
+			karis_average: false,
+			mip_weights: [1.0; BLOOM_MIP_TINT_COUNT],
+			mip_tints: [[1.0; 3]; BLOOM_MIP_TINT_COUNT],
+			single_pass_downsample: false,
+			debug_view: BloomDebugView::Off,
+			lens_flare: LensFlareSettings::default(),
+			lut_strength: 1.0,
+			dither: DitherMode::Off,
+		}
+	}
+}
+
+impl BloomSettings {
+	/// Whether `single_pass_downsample` applies: the downsampler reproduces the
+	/// `DownsampleBox13` chain of `BloomShape::Symmetric`, the anamorphic shape and the
+	/// dual Kawase and gaussian algorithms fall back to the downsample chain.
+	pub fn uses_single_pass_downsample(&self) -> bool {
+		self.single_pass_downsample
+			&& self.shape == BloomShape::Symmetric
+			&& matches!(
+				self.algorithm,
+				BloomAlgorithm::MipChain | BloomAlgorithm::Fft
+			)
+	}
+}
+
+/// Pseudo lens flare added to the bloom in `MODE_APPLY`, built from a mip of the
+/// downsample chain.
+#[derive(Clone, Copy)]
+pub struct LensFlareSettings {
+	/// 0 disables the lens flare.
+	pub intensity: f32,
+	pub ghost_count: u32,
+	/// Distance between two ghosts, as a fraction of the distance to the screen center.
+	pub ghost_spacing: f32,
+	/// Radius of the halo ring, in uv.
+	pub halo_width: f32,
+	/// Offset of the red and blue channels, in texels of the flare mip.
+	pub chromatic_distortion: f32,
+	/// Mip of the downsample chain the flare is built from, clamped to the chain.
+	pub mip: u32,
+}
+
+impl Default for LensFlareSettings {
+	fn default() -> Self {
+		LensFlareSettings {
+			intensity: 0.0,
+			ghost_count: 4,
+			ghost_spacing: 0.35,
+			halo_width: 0.45,
+			chromatic_distortion: 2.0,
+			mip: 2,
+		}
+	}
+}
+
+/// What `MODE_APPLY` is replaced with on screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BloomDebugView {
+	/// The bloom applied to the HDR image.
+	Off,
+	/// The exposed HDR input, without bloom.
+	Hdr,
+	/// A mip of the pong (0), ping (1) or upsample (2) chain. Mip 0 of the pong chain
+	/// is the prefilter output.
+	Mip { image: u32, mip: u32 },
+}
+
+impl BloomDebugView {
+	/// Every view of a chain of `mip_count` mips.
+	pub fn all(mip_count: usize) -> Vec<BloomDebugView> {
+		let mut views = vec![BloomDebugView::Off, BloomDebugView::Hdr];
+		for image in 0..3 {
+			for mip in 0..mip_count as u32 {
+				views.push(BloomDebugView::Mip { image, mip });
+			}
+		}
+		views
+	}
+
+	pub fn name(self) -> String {
+		match self {
+			BloomDebugView::Off => "Off".to_string(),
+			BloomDebugView::Hdr => "HDR input".to_string(),
+			BloomDebugView::Mip { image: 0, mip: 0 } => "Prefilter".to_string(),
+			BloomDebugView::Mip { image: 0, mip } => format!("Downsample mip {}", mip),
+			BloomDebugView::Mip { image: 1, mip } => format!("Downsample ping mip {}", mip),
+			BloomDebugView::Mip { mip, .. } => format!("Upsample mip {}", mip),
+		}
+	}
+
+	/// The dispatch replacing `MODE_APPLY`, `None` when off or out of the chain.
+	pub fn dispatch(self, mip_count: usize) -> Option<BloomDispatch> {
+		let (input, lod) = match self {
+			BloomDebugView::Off => return None,
+			BloomDebugView::Hdr => (3, 0),
+			BloomDebugView::Mip { image, mip } => {
+				if image > 2 || mip as usize >= mip_count {
+					return None;
+				}
+				(image, mip)
+			}
+		};
+		Some(BloomDispatch {
+			mode: MODE_DEBUG,
+			lod,
+			input,
+			output: (3 * mip_count) as u32,
+			bloom: 0,
+		})
+	}
+}
+
+/// How the prefiltered image is spread, the values match the `ALGORITHM_*` defines
+/// of `bloom.comp`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BloomAlgorithm {
+	/// Downsample then upsample through the mip chain, filtered by the `BloomShape`.
+	MipChain = 0,
+	/// Convolution with a kernel image through an FFT, see `bloom_fft::FftBloom`.
+	Fft = 1,
+	/// The mip chain with the dual filters of Marius Bjørge's "Bandwidth-Efficient
+	/// Rendering" (Siggraph 2015), one half resolution pass per mip.
+	DualKawase = 2,
+	/// Bilinear downsample, a separable 9 tap gaussian on every mip, then bilinear
+	/// upsample.
+	Gaussian = 3,
+}
+
+impl BloomAlgorithm {
+	pub const ALL: [BloomAlgorithm; 4] = [
+		BloomAlgorithm::MipChain,
+		BloomAlgorithm::Fft,
+		BloomAlgorithm::DualKawase,
+		BloomAlgorithm::Gaussian,
+	];
+
+	pub fn name(self) -> &'static str {
+		match self {
+			BloomAlgorithm::MipChain => "Mip chain",
+			BloomAlgorithm::Fft => "FFT convolution",
+			BloomAlgorithm::DualKawase => "Dual Kawase",
+			BloomAlgorithm::Gaussian => "Separable gaussian",
+		}
+	}
+}
+
+/// Filters of the downsample and upsample chain, the values match the `SHAPE_*`
+/// defines of `bloom.comp`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BloomShape {
+	/// `DownsampleBox13` and `UpsampleTent9`.
+	Symmetric = 0,
+	/// Horizontal streaks: `DownsampleStreak` and `UpsampleStreak`, the prefilter
+	/// stays symmetric.
+	Anamorphic = 1,
+}
+
+/// Noise added before the 8-bit output of `MODE_APPLY` to break the banding of dark
+/// gradients, the values match the `DITHER_*` defines of `bloom.comp`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DitherMode {
+	Off = 0,
+	/// The 8x8 Bayer matrix.
+	Ordered = 1,
+	/// The texture set by `BloomPass::set_blue_noise`.
+	BlueNoise = 2,
+}
+
+impl DitherMode {
+	pub const ALL: [DitherMode; 3] = [DitherMode::Off, DitherMode::Ordered, DitherMode::BlueNoise];
+
+	pub fn name(self) -> &'static str {
+		match self {
+			DitherMode::Off => "Off",
+			DitherMode::Ordered => "Ordered",
+			DitherMode::BlueNoise => "Blue noise",
+		}
+	}
+}
+
+/// Tonemapping operator of `MODE_APPLY`, the values match the `TONEMAP_*` defines of
+/// `bloom.comp`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tonemapper {
+	None = 0,
+	Reinhard = 1,
+	ReinhardExtended = 2,
+	AcesFitted = 3,
+	GranTurismo = 4,
+	AgX = 5,
+}
+
+impl Tonemapper {
+	pub const ALL: [Tonemapper; 6] = [
+		Tonemapper::None,
+		Tonemapper::Reinhard,
+		Tonemapper::ReinhardExtended,
+		Tonemapper::AcesFitted,
+		Tonemapper::GranTurismo,
+		Tonemapper::AgX,
+	];
+
+	pub fn name(self) -> &'static str {
+		match self {
+			Tonemapper::None => "None",
+			Tonemapper::Reinhard => "Reinhard",
+			Tonemapper::ReinhardExtended => "Reinhard extended",
+			Tonemapper::AcesFitted => "ACES fitted",
+			Tonemapper::GranTurismo => "Gran Turismo",
+			Tonemapper::AgX => "AgX",
+		}
+	}
+}
+
+/// Mirror of the `parameters` uniform block of `bloom.comp`.
+#[derive(Default, Clone, Copy)]
+#[repr(C)]
+pub(crate) struct BloomParameters {
+	pub(crate) params: uv::Vec4,
+	pub(crate) intensity: f32,
+	pub(crate) combine_constant: f32,
+	pub(crate) tonemapper: u32,
+	pub(crate) white_point: f32,
+	pub(crate) lens_dirt_intensity: f32,
+	pub(crate) shape: u32,
+	pub(crate) stretch: f32,
+	pub(crate) clamp_value: f32,
+	pub(crate) karis_average: u32,
+	pub(crate) algorithm: u32,
+	padding: [f32; 2],
+	/// (rgb) tint, (a) weight
+	pub(crate) mip_tints: [uv::Vec4; BLOOM_MIP_TINT_COUNT],
+	pub(crate) flare_intensity: f32,
+	pub(crate) ghost_count: u32,
+	pub(crate) ghost_spacing: f32,
+	pub(crate) halo_width: f32,
+	pub(crate) chromatic_distortion: f32,
+	pub(crate) flare_mip: u32,
+	flare_padding: [f32; 2],
+	pub(crate) lut_domain_min: uv::Vec4,
+	pub(crate) lut_domain_max: uv::Vec4,
+	pub(crate) lut_strength: f32,
+	pub(crate) dither: u32,
+	lut_padding: [f32; 2],
+}
+
+impl BloomParameters {
+	pub(crate) fn new(settings: &BloomSettings) -> BloomParameters {
+		let mut mip_tints = [uv::Vec4::zero(); BLOOM_MIP_TINT_COUNT];
+		for (i, tint) in mip_tints.iter_mut().enumerate() {
+			let [r, g, b] = settings.mip_tints[i];
+			*tint = uv::Vec4::new(r, g, b, settings.mip_weights[i]);
+		}
+		BloomParameters {
+			params: uv::Vec4::new(
+				settings.threshold,
+				settings.threshold - settings.knee,
+				settings.knee * 2.0f32,
+				0.25f32 / settings.knee,
+			),
+			intensity: settings.intensity * 2.0,
+			combine_constant: settings.combine_constant,
+			tonemapper: settings.tonemapper as u32,
+			white_point: settings.white_point,
+			lens_dirt_intensity: settings.lens_dirt_intensity,
+			shape: settings.shape as u32,
+			stretch: settings.stretch,
+			clamp_value: settings.clamp_value,
+			karis_average: settings.karis_average as u32,
+			algorithm: settings.algorithm as u32,
+			mip_tints,
+			flare_intensity: settings.lens_flare.intensity,
+			ghost_count: settings.lens_flare.ghost_count,
+			ghost_spacing: settings.lens_flare.ghost_spacing,
+			halo_width: settings.lens_flare.halo_width,
+			chromatic_distortion: settings.lens_flare.chromatic_distortion,
+			flare_mip: settings.lens_flare.mip,
+			lut_domain_min: uv::Vec4::zero(),
+			lut_domain_max: uv::Vec4::one(),
+			lut_strength: settings.lut_strength,
+			dither: settings.dither as u32,
+			..Default::default()
+		}
+	}
+}
+
+/// Names of the pong, ping and upsample chains, in the order of their indices in
+/// `BloomDispatch`.
+const CHAIN_NAMES: [&str; 3] = ["pong", "ping", "upsample"];
+
+/// A pass of `BloomPass` in the render graph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BloomGraphPass {
+	Histogram,
+	Average,
+	Dispatch(BloomDispatch),
+	/// One of `bloom_fft::FFT_MODES`.
+	Fft(u32),
+	SinglePassDownsample,
+}
+
+/// What `BloomPass::record_pass` needs of the graph of the frame.
+struct BloomFrameGraph {
+	input: render_graph::ImageHandle,
+	output: render_graph::ImageHandle,
+	passes: Vec<BloomGraphPass>,
+	/// The descriptor set of the frame can't be updated once bound.
+	descriptors_updated: bool,
+}
+
+/// The whole bloom post process: the ping, pong and upsample mip chains,
+/// the descriptor sets, the parameters uniforms and the compute pipeline.
+pub struct BloomPass {
+	pub settings: BloomSettings,
+	/// Exposure applied to the HDR input, before the prefilter and the apply pass.
+	pub exposure: exposure::Exposure,
+	pipeline: pipeline::ComputePipeline,
+	push_constant: push_constant::PushConstant,
+	/// One per frame in flight, bound to the parameters buffer of the frame.
+	descriptors: Vec<descriptor::DescriptorSet>,
+	/// One per frame in flight, `parameters` is uploaded to it when the frame is
+	/// recorded.
+	parameters_buffers: Vec<buffer::Buffer>,
+	parameters: BloomParameters,
+	/// The ping, pong and upsample chains of each frame in flight, so a frame never
+	/// writes the mips an older frame still reads.
+	images: Vec<Vec<image::Image>>,
+	lens_dirt: image::Image,
+	/// 3D LUT of the color grading, an identity placeholder until one is loaded.
+	color_lut: image::Image,
+	/// `DOMAIN_MIN` and `DOMAIN_MAX` of the loaded LUT.
+	color_lut_domain: Option<([f32; 3], [f32; 3])>,
+	/// Thresholds of `DitherMode::BlueNoise`.
+	blue_noise: image::Image,
+	/// Created the first time `BloomAlgorithm::Fft` is selected.
+	fft: Option<bloom_fft::FftBloom>,
+	/// Created when `BloomSettings::single_pass_downsample` is set and supported.
+	spd: Option<bloom_spd::SinglePassDownsampler>,
+	extent: vk::Extent2D,
+	mip_count: usize,
+	bloom_data: BloomConstant,
+	/// Set by `passes`.
+	frame_graph: Option<BloomFrameGraph>,
+}
+
+impl BloomPass {
+	pub fn new(engine: &vulkan_engine::VulkanEngine, settings: BloomSettings) -> BloomPass {
+		let extent = engine.surface.surface_resolution;
+		let mip_count = clamp_mip_count(settings.mip_count, extent);
+		let images = (0..engine.frames_in_flight)
+			.map(|_| BloomPass::create_images(engine, extent, mip_count))
+			.collect();
+
+		let parameters_buffers: Vec<buffer::Buffer> = (0..engine.frames_in_flight)
+			.map(|_| {
+				buffer::Buffer::new(
+					&engine.device,
+					vk::BufferCreateFlags::empty(),
+					buffer::aligne_offset(size_of::<BloomParameters>() as u64),
+					vk::BufferUsageFlags::UNIFORM_BUFFER
+						| vk::BufferUsageFlags::TRANSFER_DST
+						| vk::BufferUsageFlags::TRANSFER_SRC,
+					vk::SharingMode::EXCLUSIVE,
+					UsageFlags::DOWNLOAD | UsageFlags::UPLOAD | UsageFlags::HOST_ACCESS,
+				)
+			})
+			.collect();
+
+		let exposure = exposure::Exposure::new(engine, exposure::ExposureSettings::default());
+
+		let lens_dirt = BloomPass::create_lens_dirt(engine, 1, 1, vec![0u8, 0, 0, 255]);
+
+		let color_lut = color_lut::CubeLut::identity(color_lut::MIN_LUT_SIZE).create_image(engine);
+
+		let blue_noise =
+			blue_noise::BlueNoise::generate(blue_noise::BLUE_NOISE_SIZE).create_image(engine);
+
+		let descriptors: Vec<descriptor::DescriptorSet> = parameters_buffers
+			.iter()
+			.map(|parameters_buffer| {
+				BloomPass::create_descriptor(
+					engine,
+					mip_count,
+					parameters_buffer,
+					&exposure,
+					&lens_dirt,
+					&color_lut,
+					&blue_noise,
+				)
+			})
+			.collect();
+
+		let bloom_data = BloomConstant {
+			mode_lod_in_out_bloom: 0,
+			frame_index: 0,
+		};
+
+		let push_constant = push_constant::PushConstant::new(
+			0,
+			size_of::<BloomConstant>() as u32,
+			vk::ShaderStageFlags::COMPUTE,
+			vec![bloom_data],
+		);
+
+		let pipeline =
+			BloomPass::create_pipeline(engine, mip_count, &descriptors[0], &push_constant);
+
+		let mut bloom_pass = BloomPass {
+			settings,
+			exposure,
+			pipeline,
+			push_constant,
+			descriptors,
+			parameters_buffers,
+			parameters: BloomParameters::default(),
+			images,
+			lens_dirt,
+			color_lut,
+			color_lut_domain: None,
+			blue_noise,
+			fft: None,
+			spd: None,
+			extent,
+			mip_count,
+			bloom_data,
+			frame_graph: None,
+		};
+		bloom_pass.update_parameters();
+		if settings.algorithm == BloomAlgorithm::Fft {
+			bloom_pass.fft = Some(bloom_fft::FftBloom::new(engine));
+		}
+		bloom_pass.update_spd(engine);
+		bloom_pass
+	}
+
+	fn create_descriptor(
+		engine: &vulkan_engine::VulkanEngine,
+		mip_count: usize,
+		parameters_buffer: &buffer::Buffer,
+		exposure: &exposure::Exposure,
+		lens_dirt: &image::Image,
+		color_lut: &image::Image,
+		blue_noise: &image::Image,
+	) -> descriptor::DescriptorSet {
+		let descriptor = descriptor::DescriptorSet::new(
+			&engine.device,
+			vec![
+				(
+					vk::DescriptorType::STORAGE_IMAGE,
+					(3 * mip_count + 1) as u32,
+				),
+				(vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 11),
+				(vk::DescriptorType::UNIFORM_BUFFER, 1),
+				(vk::DescriptorType::STORAGE_BUFFER, 1),
+			],
+			1,
+			vec![
+				vk::DescriptorSetLayoutBinding::builder()
+					.binding(0)
+					.descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+					.descriptor_count((3 * mip_count + 1) as u32)
+					.stage_flags(vk::ShaderStageFlags::COMPUTE)
+					.build(),
+				vk::DescriptorSetLayoutBinding::builder()
+					.binding(1)
+					.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+					.descriptor_count(4)
+					.stage_flags(vk::ShaderStageFlags::COMPUTE)
+					.build(),
+				vk::DescriptorSetLayoutBinding::builder()
+					.binding(2)
+					.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+					.descriptor_count(4)
+					.stage_flags(vk::ShaderStageFlags::COMPUTE)
+					.build(),
+				vk::DescriptorSetLayoutBinding::builder()
+					.binding(3)
+					.descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+					.descriptor_count(1)
+					.stage_flags(vk::ShaderStageFlags::COMPUTE)
 					.build(),
+				vk::DescriptorSetLayoutBinding::builder()
+					.binding(4)
+					.descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+					.descriptor_count(1)
+					.stage_flags(vk::ShaderStageFlags::COMPUTE)
+					.build(),
+				vk::DescriptorSetLayoutBinding::builder()
+					.binding(5)
+					.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+					.descriptor_count(1)
+					.stage_flags(vk::ShaderStageFlags::COMPUTE)
+					.build(),
+				vk::DescriptorSetLayoutBinding::builder()
+					.binding(6)
+					.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+					.descriptor_count(1)
+					.stage_flags(vk::ShaderStageFlags::COMPUTE)
+					.build(),
+				vk::DescriptorSetLayoutBinding::builder()
+					.binding(7)
+					.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+					.descriptor_count(1)
+					.stage_flags(vk::ShaderStageFlags::COMPUTE)
+					.build(),
+			],
+		);
+		descriptor.update_descriptor_set(
+			0,
+			3,
+			Some(vec![vk::DescriptorBufferInfo::builder()
+				.buffer(*parameters_buffer.buffer)
+				.offset(0)
+				.range(size_of::<BloomParameters>() as u64)
+				.build()]),
+			None,
+		);
+		descriptor.update_descriptor_set(0, 4, Some(vec![exposure.descriptor_buffer_info()]), None);
+		descriptor.update_descriptor_set(0, 5, None, Some(vec![sampled_image_info(lens_dirt)]));
+		descriptor.update_descriptor_set(0, 6, None, Some(vec![sampled_image_info(color_lut)]));
+		descriptor.update_descriptor_set(0, 7, None, Some(vec![sampled_image_info(blue_noise)]));
+		descriptor
+	}
+
+	/// The mip count is passed to `bloom.comp` as the specialization constant 0,
+	/// which sizes the `output_image` array.
+	fn create_pipeline(
+		engine: &vulkan_engine::VulkanEngine,
+		mip_count: usize,
+		descriptor: &descriptor::DescriptorSet,
+		push_constant: &push_constant::PushConstant,
+	) -> pipeline::ComputePipeline {
+		let compute_module =
+			shader_module::ShaderModule::new(&engine.device, "shaders/spv/bloom.spv", "main");
+
+		pipeline::ComputePipeline::builder()
+			.add_push_constant(push_constant)
+			.add_descriptor_set(descriptor, 0)
+			.compute_module(&compute_module, vk::PipelineShaderStageCreateFlags::empty())
+			.add_specialization_constant(0, mip_count as u32)
+			.build(&engine.device)
+	}
+
+	/// Recreates everything that depends on the base resolution or the mip count.
+	/// The device must be idle.
+	fn rebuild(&mut self, engine: &vulkan_engine::VulkanEngine) {
+		let mip_count = clamp_mip_count(self.settings.mip_count, self.extent);
+		self.images.clear();
+		self.images = (0..engine.frames_in_flight)
+			.map(|_| BloomPass::create_images(engine, self.extent, mip_count))
+			.collect();
+		if mip_count != self.mip_count {
+			let descriptors: Vec<descriptor::DescriptorSet> = self
+				.parameters_buffers
+				.iter()
+				.map(|parameters_buffer| {
+					BloomPass::create_descriptor(
+						engine,
+						mip_count,
+						parameters_buffer,
+						&self.exposure,
+						&self.lens_dirt,
+						&self.color_lut,
+						&self.blue_noise,
+					)
+				})
+				.collect();
+			let pipeline =
+				BloomPass::create_pipeline(engine, mip_count, &descriptors[0], &self.push_constant);
+			self.pipeline = pipeline;
+			self.descriptors = descriptors;
+			self.mip_count = mip_count;
+		}
+		self.update_spd(engine);
+	}
+
+	/// Creates, recreates or drops the single pass downsampler to follow the settings
+	/// and the mip chain. The device must be idle.
+	#[allow(clippy::unnecessary_map_or)]
+	fn update_spd(&mut self, engine: &vulkan_engine::VulkanEngine) {
+		let extent = vk::Extent2D::builder()
+			.width(self.images[0][0].extent.width)
+			.height(self.images[0][0].extent.height)
+			.build();
+		if !self.settings.uses_single_pass_downsample()
+			|| !bloom_spd::SinglePassDownsampler::supports(extent, self.mip_count)
+		{
+			self.spd = None;
+		} else if self
+			.spd
+			.as_ref()
+			.map_or(true, |spd| spd.mip_count() != self.mip_count)
+		{
+			self.spd = Some(bloom_spd::SinglePassDownsampler::new(
+				engine,
+				self.mip_count,
+			));
+		}
+	}
+
+	/// Loads the kernel of `BloomAlgorithm::Fft` from disk, `None` restores the default one.
+	pub fn set_fft_kernel(
+		&mut self,
+		engine: &vulkan_engine::VulkanEngine,
+		path: Option<&std::path::Path>,
+	) -> Result<(), ::image::ImageError> {
+		self.fft
+			.get_or_insert_with(|| bloom_fft::FftBloom::new(engine))
+			.set_kernel(engine, path)
+	}
+
+	/// Loads the lens dirt texture from disk, `None` restores the neutral black texture.
+	pub fn set_lens_dirt(
+		&mut self,
+		engine: &vulkan_engine::VulkanEngine,
+		path: Option<&std::path::Path>,
+	) -> Result<(), ::image::ImageError> {
+		let lens_dirt = match path {
+			Some(path) => {
+				let dirt = ::image::open(path)?.into_rgba8();
+				BloomPass::create_lens_dirt(engine, dirt.width(), dirt.height(), dirt.into_raw())
+			}
+			None => BloomPass::create_lens_dirt(engine, 1, 1, vec![0u8, 0, 0, 255]),
+		};
+		unsafe {
+			engine
+				.device
+				.device
+				.device_wait_idle()
+				.expect("Failed to wait for the device to be idle.");
+		};
+		self.lens_dirt = lens_dirt;
+		for descriptor in &self.descriptors {
+			descriptor.update_descriptor_set(
+				0,
+				5,
+				None,
+				Some(vec![sampled_image_info(&self.lens_dirt)]),
 			);
 		}
+		Ok(())
 	}
-	output_image_descr_info.push(
-		vk::DescriptorImageInfo::builder()
-			.image_layout(vk::ImageLayout::GENERAL)
-			.image_view(engine.swapchain.swapchain_image_views[current_image])
-			.build(),
-	);
 
-	let mut input_image_descr_info = Vec::<vk::DescriptorImageInfo>::with_capacity(4);
-	for i in 0..3 {
-		input_image_descr_info.push(
-			vk::DescriptorImageInfo::builder()
-				.image_layout(vk::ImageLayout::GENERAL)
-				.image_view(bloom_images[i].image_view)
-				.sampler(bloom_images[i].image_sampler.unwrap())
+	/// Loads the color grading LUT from a `.cube` file, `None` disables the grading.
+	pub fn set_color_lut(
+		&mut self,
+		engine: &vulkan_engine::VulkanEngine,
+		path: Option<&std::path::Path>,
+	) -> Result<(), color_lut::CubeError> {
+		let lut = match path {
+			Some(path) => Some(color_lut::CubeLut::load(path)?),
+			None => None,
+		};
+		let color_lut = match &lut {
+			Some(lut) => lut.create_image(engine),
+			None => color_lut::CubeLut::identity(color_lut::MIN_LUT_SIZE).create_image(engine),
+		};
+		unsafe {
+			engine
+				.device
+				.device
+				.device_wait_idle()
+				.expect("Failed to wait for the device to be idle.");
+		};
+		self.color_lut = color_lut;
+		self.color_lut_domain = lut.map(|lut| (lut.domain_min, lut.domain_max));
+		for descriptor in &self.descriptors {
+			descriptor.update_descriptor_set(
+				0,
+				6,
+				None,
+				Some(vec![sampled_image_info(&self.color_lut)]),
+			);
+		}
+		self.update_parameters();
+		Ok(())
+	}
+
+	/// Loads the thresholds of `DitherMode::BlueNoise` from an image, `None` restores the
+	/// generated texture.
+	pub fn set_blue_noise(
+		&mut self,
+		engine: &vulkan_engine::VulkanEngine,
+		path: Option<&std::path::Path>,
+	) -> Result<(), ::image::ImageError> {
+		let noise = match path {
+			Some(path) => blue_noise::BlueNoise::load(path)?,
+			None => blue_noise::BlueNoise::generate(blue_noise::BLUE_NOISE_SIZE),
+		};
+		let blue_noise = noise.create_image(engine);
+		unsafe {
+			engine
+				.device
+				.device
+				.device_wait_idle()
+				.expect("Failed to wait for the device to be idle.");
+		};
+		self.blue_noise = blue_noise;
+		for descriptor in &self.descriptors {
+			descriptor.update_descriptor_set(
+				0,
+				7,
+				None,
+				Some(vec![sampled_image_info(&self.blue_noise)]),
+			);
+		}
+		Ok(())
+	}
+
+	/// Computes the parameters of the settings, with the domain of the loaded LUT.
+	/// Without a LUT the grading is skipped whatever `BloomSettings::lut_strength` is.
+	fn update_parameters(&mut self) {
+		let mut parameters = BloomParameters::new(&self.settings);
+		match self.color_lut_domain {
+			Some(([min_r, min_g, min_b], [max_r, max_g, max_b])) => {
+				parameters.lut_domain_min = uv::Vec4::new(min_r, min_g, min_b, 0.0);
+				parameters.lut_domain_max = uv::Vec4::new(max_r, max_g, max_b, 1.0);
+			}
+			None => parameters.lut_strength = 0.0,
+		}
+		self.parameters = parameters;
+	}
+
+	fn create_lens_dirt(
+		engine: &vulkan_engine::VulkanEngine,
+		width: u32,
+		height: u32,
+		pixels: Vec<u8>,
+	) -> image::Image {
+		let mut lens_dirt = image::Image::new(
+			&engine.device,
+			vk::ImageCreateFlags::empty(),
+			vk::ImageType::TYPE_2D,
+			vk::Format::R8G8B8A8_SRGB,
+			vk::Extent3D::builder()
+				.width(width)
+				.height(height)
+				.depth(1)
 				.build(),
+			1,
+			1,
+			vk::ImageTiling::OPTIMAL,
+			vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+			engine.device.queue_family_index,
+			vk::ImageLayout::UNDEFINED,
+			vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+			vk::ImageViewType::TYPE_2D,
+			vk::ImageAspectFlags::COLOR,
+			UsageFlags::FAST_DEVICE_ACCESS,
+		);
+		lens_dirt.set_sampler(
+			vk::Filter::LINEAR,
+			vk::Filter::LINEAR,
+			vk::SamplerMipmapMode::LINEAR,
+			vk::SamplerAddressMode::CLAMP_TO_EDGE,
+			vk::SamplerAddressMode::CLAMP_TO_EDGE,
+			vk::SamplerAddressMode::CLAMP_TO_EDGE,
+			0.0,
+			false,
+			1.0,
+			false,
+			vk::CompareOp::ALWAYS,
+			0.0,
+			1.0,
+			vk::BorderColor::FLOAT_OPAQUE_BLACK,
 		);
+		lens_dirt.write_to_vram(&engine.device, &engine.command_builder, pixels);
+		lens_dirt
 	}
-	input_image_descr_info.push(
-		vk::DescriptorImageInfo::builder()
-			.image_layout(vk::ImageLayout::GENERAL)
-			.image_view(engine.swapchain.swapchain_hdr_images[current_image].image_view)
-			.sampler(engine.swapchain.swapchain_image_sampler)
-			.build(),
-	);
 
-	engine.descriptors[1].update_descriptor_set(0, 0, None, Some(output_image_descr_info));
-	engine.descriptors[1].update_descriptor_set(0, 1, None, Some(input_image_descr_info.clone()));
-	engine.descriptors[1].update_descriptor_set(0, 2, None, Some(input_image_descr_info));
-}
-
-fn get_mip_size(current_mip: usize, image: &vulkan_engine::image::Image) -> vk::Extent2D {
-	let mut width = image.extent.width;
-	let mut height = image.extent.height;
-	for _ in 0..current_mip {
-		width /= 2;
-		height /= 2;
+	pub fn mip_count(&self) -> usize {
+		self.mip_count
 	}
-	vk::Extent2D::builder().width(width).height(height).build()
-}
 
-unsafe fn dispach(
-	engine: &vulkan_engine::VulkanEngine,
-	command_buffer: &vk::CommandBuffer,
-	image_size: vk::Extent2D,
-	memory_barrier: vk::MemoryBarrier,
-) {
-	let mut group_x = image_size.width / 8;
-	let mut group_y = image_size.height / 4;
-	if image_size.width % 8 != 0 {
-		group_x += 1;
+	fn create_images(
+		engine: &vulkan_engine::VulkanEngine,
+		extent: vk::Extent2D,
+		mip_count: usize,
+	) -> Vec<image::Image> {
+		let mut images = Vec::<image::Image>::with_capacity(3);
+
+		let image_width = extent.width / 2;
+		let image_height = extent.height / 2;
+
+		for _ in 0..3 {
+			let mut image = image::Image::new(
+				&engine.device,
+				vk::ImageCreateFlags::empty(),
+				vk::ImageType::TYPE_2D,
+				vk::Format::R16G16B16A16_SFLOAT,
+				vk::Extent3D::builder()
+					.width(image_width)
+					.height(image_height)
+					.depth(1)
+					.build(),
+				mip_count as u32,
+				1,
+				vk::ImageTiling::OPTIMAL,
+				vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+				engine.device.queue_family_index,
+				vk::ImageLayout::UNDEFINED,
+				vk::ImageLayout::GENERAL,
+				vk::ImageViewType::TYPE_2D,
+				vk::ImageAspectFlags::COLOR,
+				UsageFlags::FAST_DEVICE_ACCESS,
+			);
+			image.set_sampler(
+				vk::Filter::LINEAR,
+				vk::Filter::LINEAR,
+				vk::SamplerMipmapMode::LINEAR,
+				vk::SamplerAddressMode::CLAMP_TO_EDGE,
+				vk::SamplerAddressMode::CLAMP_TO_EDGE,
+				vk::SamplerAddressMode::CLAMP_TO_EDGE,
+				0.0,
+				false,
+				1.0,
+				false,
+				vk::CompareOp::ALWAYS,
+				-1000.0,
+				1000.0,
+				vk::BorderColor::FLOAT_OPAQUE_BLACK,
+			);
+			image.change_layout(
+				&engine.device,
+				&engine.command_builder,
+				image.initial_layout,
+				image.final_layout,
+			);
+			images.push(image);
+		}
+		images
 	}
-	if image_size.height % 4 != 0 {
-		group_y += 1;
+
+	/// Uploads the new parameters, the chains are rebuilt if the mip count or the use of
+	/// the single pass downsampler changed.
+	pub fn set_settings(&mut self, engine: &vulkan_engine::VulkanEngine, settings: BloomSettings) {
+		let rebuild = settings.mip_count != self.settings.mip_count
+			|| settings.uses_single_pass_downsample()
+				!= self.settings.uses_single_pass_downsample();
+		if settings.algorithm == BloomAlgorithm::Fft && self.fft.is_none() {
+			self.fft = Some(bloom_fft::FftBloom::new(engine));
+		}
+		self.settings = settings;
+		self.update_parameters();
+		if rebuild {
+			unsafe {
+				engine
+					.device
+					.device
+					.device_wait_idle()
+					.expect("Failed to wait for the device to be idle.");
+			};
+			self.rebuild(engine);
+		}
 	}
-	engine
-		.device
-		.device
-		.cmd_dispatch(*command_buffer, group_x, group_y, 1);
-	engine.device.device.cmd_pipeline_barrier(
-		*command_buffer,
-		vk::PipelineStageFlags::COMPUTE_SHADER,
-		vk::PipelineStageFlags::COMPUTE_SHADER,
-		vk::DependencyFlags::empty(),
-		&[memory_barrier],
-		&[],
-		&[],
-	);
-}
-
-pub fn bloom(
-	engine: &vulkan_engine::VulkanEngine,
-	command_buffer: &mut vk::CommandBuffer,
-	current_image: usize,
-	bloom_images: &mut Vec<image::Image>,
-	bloom_data: &mut BloomConstant,
-) {
-	let mut push = push_constant::PushConstant::new(
-		0,
-		std::mem::size_of::<BloomConstant>() as u32,
-		vk::ShaderStageFlags::COMPUTE,
-		vec![bloom_data.clone()],
-	);
-
-	let subresource_range = vk::ImageSubresourceRange::builder()
-		.aspect_mask(vk::ImageAspectFlags::COLOR)
-		.layer_count(1)
-		.level_count(1)
-		.build();
-	let image_memory_barrier = [
-		vk::ImageMemoryBarrier::builder()
-			.src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
-			.dst_access_mask(vk::AccessFlags::MEMORY_WRITE | vk::AccessFlags::MEMORY_READ)
-			.subresource_range(subresource_range)
-			.image(engine.swapchain.swapchain_hdr_images[current_image].image)
-			.src_queue_family_index(engine.device.queue_family_index)
-			.dst_queue_family_index(engine.device.queue_family_index)
-			.old_layout(vk::ImageLayout::GENERAL)
-			.new_layout(vk::ImageLayout::GENERAL)
-			.build(),
-		vk::ImageMemoryBarrier::builder()
-			.src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
-			.dst_access_mask(vk::AccessFlags::MEMORY_WRITE | vk::AccessFlags::MEMORY_READ)
-			.subresource_range(subresource_range)
-			.image(engine.swapchain.swapchain_images[current_image])
-			.src_queue_family_index(engine.device.queue_family_index)
-			.dst_queue_family_index(engine.device.queue_family_index)
-			.old_layout(vk::ImageLayout::UNDEFINED)
-			.new_layout(vk::ImageLayout::GENERAL)
-			.build()
-		];
 
-	let memory_barrier = vk::MemoryBarrier::builder()
-		.dst_access_mask(vk::AccessFlags::MEMORY_WRITE)
-		.src_access_mask(vk::AccessFlags::MEMORY_READ)
-		.build();
+	fn update_descriptor(
+		&self,
+		engine: &vulkan_engine::VulkanEngine,
+		input_hdr: post_process::PostProcessTarget,
+		output_view: vk::ImageView,
+	) {
+		let images = &self.images[engine.current_frame];
+		let mut output_image_descr_info =
+			Vec::<vk::DescriptorImageInfo>::with_capacity(3 * self.mip_count + 1);
+		for image in images.iter() {
+			for view in image.mip_image_views[..self.mip_count].iter() {
+				output_image_descr_info.push(
+					vk::DescriptorImageInfo::builder()
+						.image_layout(vk::ImageLayout::GENERAL)
+						.image_view(*view)
+						.build(),
+				);
+			}
+		}
+		output_image_descr_info.push(
+			vk::DescriptorImageInfo::builder()
+				.image_layout(vk::ImageLayout::GENERAL)
+				.image_view(output_view)
+				.build(),
+		);
 
-	// sync graphic --> compute
-	unsafe {
-		engine.device.device.cmd_pipeline_barrier(
-			*command_buffer,
-			vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
-			vk::PipelineStageFlags::COMPUTE_SHADER,
-			vk::DependencyFlags::empty(),
-			&[],
-			&[],
-			&image_memory_barrier,
+		let mut input_image_descr_info = Vec::<vk::DescriptorImageInfo>::with_capacity(4);
+		for image in images.iter() {
+			input_image_descr_info.push(
+				vk::DescriptorImageInfo::builder()
+					.image_layout(vk::ImageLayout::GENERAL)
+					.image_view(image.image_view)
+					.sampler(image.image_sampler.unwrap())
+					.build(),
+			);
+		}
+		input_image_descr_info.push(
+			vk::DescriptorImageInfo::builder()
+				.image_layout(vk::ImageLayout::GENERAL)
+				.image_view(input_hdr.view)
+				.sampler(engine.swapchain.swapchain_image_sampler)
+				.build(),
 		);
-	};
 
-	update_descriptor(engine, current_image, bloom_images);
+		let descriptor = &self.descriptors[engine.current_frame];
+		descriptor.update_descriptor_set(0, 0, None, Some(output_image_descr_info));
+		descriptor.update_descriptor_set(0, 1, None, Some(input_image_descr_info.clone()));
+		descriptor.update_descriptor_set(0, 2, None, Some(input_image_descr_info));
+	}
 
-	//preFilter
-	bloom_data.mode_lod_in_out_bloom = MODE_PREFILTER << 28 | 0 << 21 | 3 << 14 | 0 << 7 | 0;
-	push.set_data(vec![bloom_data.clone()]);
-	unsafe {
+	unsafe fn bind(
+		&self,
+		engine: &vulkan_engine::VulkanEngine,
+		command_buffer: &vk::CommandBuffer,
+	) {
 		engine.device.device.cmd_bind_pipeline(
 			*command_buffer,
 			vk::PipelineBindPoint::COMPUTE,
-			engine.compute_pipelines[0].pipeline,
-		);
-		engine.device.device.cmd_push_constants(
-			*command_buffer,
-			engine.compute_pipelines[0].pipeline_layout,
-			vk::ShaderStageFlags::COMPUTE,
-			0,
-			&push.data,
+			self.pipeline.pipeline,
 		);
 		engine.device.device.cmd_bind_descriptor_sets(
 			*command_buffer,
 			vk::PipelineBindPoint::COMPUTE,
-			engine.compute_pipelines[0].pipeline_layout,
+			self.pipeline.pipeline_layout,
 			0,
-			&engine.descriptors[1].descriptor_set,
+			&self.descriptors[engine.current_frame].descriptor_set,
 			&[],
 		);
-		let image_extent = vk::Extent2D::builder()
-			.width(bloom_images[0].extent.width)
-			.height(bloom_images[0].extent.height)
-			.build();
-		dispach(engine, command_buffer, image_extent, memory_barrier);
-	};
+	}
 
-	//DownSample
-	for i in 1..BLOOM_MIP_COUNT {
-		let mip_size = get_mip_size(i, &bloom_images[0]);
-		unsafe {
-			// Ping
-			bloom_data.mode_lod_in_out_bloom = MODE_DOWNSAMPLE << 28
-				| ((i - 1) as u32) << 21
-				| 0 << 14 | ((1 * BLOOM_MIP_COUNT + i) as u32) << 7
-				| 0;
-			push.set_data(vec![bloom_data.clone()]);
-			engine.device.device.cmd_push_constants(
-				*command_buffer,
-				engine.compute_pipelines[0].pipeline_layout,
-				vk::ShaderStageFlags::COMPUTE,
-				0,
-				&push.data,
-			);
-			dispach(engine, command_buffer, mip_size, memory_barrier);
-
-			// Pong
-			bloom_data.mode_lod_in_out_bloom = MODE_DOWNSAMPLE << 28
-				| (i as u32) << 21
-				| 1 << 14 | ((0 * BLOOM_MIP_COUNT + i) as u32) << 7
-				| 0;
-			push.set_data(vec![bloom_data.clone()]);
-			engine.device.device.cmd_push_constants(
-				*command_buffer,
-				engine.compute_pipelines[0].pipeline_layout,
-				vk::ShaderStageFlags::COMPUTE,
-				0,
-				&push.data,
-			);
-			dispach(engine, command_buffer, mip_size, memory_barrier);
-		};
+	/// Size of the output image of `bloom_dispatch`.
+	fn dispatch_size(
+		&self,
+		bloom_dispatch: BloomDispatch,
+		input_hdr: post_process::PostProcessTarget,
+	) -> vk::Extent2D {
+		let output = bloom_dispatch.output as usize;
+		if output == 3 * self.mip_count {
+			vk::Extent2D::builder()
+				.width(input_hdr.extent.width)
+				.height(input_hdr.extent.height)
+				.build()
+		} else {
+			let image_extent = vk::Extent2D::builder()
+				.width(self.images[0][0].extent.width)
+				.height(self.images[0][0].extent.height)
+				.build();
+			get_mip_size(output % self.mip_count, image_extent)
+		}
 	}
 
-	// First Upsample
-	unsafe {
-		bloom_data.mode_lod_in_out_bloom = MODE_UPSAMPLE_FIRST << 28
-			| ((BLOOM_MIP_COUNT - 2) as u32) << 21
-			| 0 << 14 | ((3 * BLOOM_MIP_COUNT - 1) as u32) << 7
-			| 0;
-		push.set_data(vec![bloom_data.clone()]);
+	unsafe fn dispatch(
+		&mut self,
+		engine: &vulkan_engine::VulkanEngine,
+		command_buffer: &vk::CommandBuffer,
+		bloom_dispatch: BloomDispatch,
+		image_size: vk::Extent2D,
+	) {
+		self.bloom_data.mode_lod_in_out_bloom = bloom_dispatch
+			.encode()
+			.unwrap_or_else(|e| panic!("Invalid bloom dispatch {:?}: {}.", bloom_dispatch, e));
+		self.push_constant.set_data(vec![self.bloom_data]);
 		engine.device.device.cmd_push_constants(
 			*command_buffer,
-			engine.compute_pipelines[0].pipeline_layout,
+			self.pipeline.pipeline_layout,
 			vk::ShaderStageFlags::COMPUTE,
 			0,
-			&push.data,
+			&self.push_constant.data,
 		);
+		dispach(engine, command_buffer, image_size);
+	}
+}
 
-		let mip_size = get_mip_size(BLOOM_MIP_COUNT - 1, &bloom_images[2]);
-		dispach(engine, command_buffer, mip_size, memory_barrier);
+impl post_process::PostProcessEffect for BloomPass {
+	fn name(&self) -> &str {
+		"Bloom"
 	}
 
-	//Upsample
-	for i in (0..=BLOOM_MIP_COUNT - 2).rev() {
-		unsafe {
-			let mip_size = get_mip_size(i, &bloom_images[2]);
-			bloom_data.mode_lod_in_out_bloom = MODE_UPSAMPLE << 28
-				| (i as u32) << 21
-				| 0 << 14 | ((2 * BLOOM_MIP_COUNT + i) as u32) << 7
-				| 2;
-			push.set_data(vec![bloom_data.clone()]);
-			engine.device.device.cmd_push_constants(
-				*command_buffer,
-				engine.compute_pipelines[0].pipeline_layout,
-				vk::ShaderStageFlags::COMPUTE,
-				0,
-				&push.data,
-			);
-			dispach(engine, command_buffer, mip_size, memory_barrier);
+	fn input(&self) -> post_process::PostProcessFormat {
+		post_process::PostProcessFormat::Hdr
+	}
+
+	/// Tonemapped and gamma corrected by `MODE_APPLY`.
+	fn output(&self) -> post_process::PostProcessFormat {
+		post_process::PostProcessFormat::Ldr
+	}
+
+	/// Imports the mip chains of the frame, one graph image per mip, and the buffers
+	/// of the exposure, the FFT and the single pass downsampler. The exposure passes come
+	/// first, then a pass per dispatch of the bloom steps.
+	fn passes(
+		&mut self,
+		engine: &vulkan_engine::VulkanEngine,
+		graph: &mut render_graph::RenderGraph,
+		input: render_graph::ImageHandle,
+		output: render_graph::ImageHandle,
+	) -> Vec<post_process::PostProcessPass> {
+		self.bloom_data.frame_index = self.bloom_data.frame_index.wrapping_add(1);
+		let frame = engine.current_frame;
+		let mip_count = self.mip_count;
+
+		// Indexed like `BloomDispatch::output`
+		let mut mips = Vec::with_capacity(3 * mip_count);
+		for (chain, image) in self.images[frame].iter().enumerate() {
+			let base_extent = vk::Extent2D::builder()
+				.width(image.extent.width)
+				.height(image.extent.height)
+				.build();
+			for mip in 0..mip_count {
+				mips.push(graph.import_image(
+					&format!("Bloom {} mip {}", CHAIN_NAMES[chain], mip),
+					render_graph::GraphImage {
+						image: image.image,
+						view: image.mip_image_views[mip],
+						extent: get_mip_size(mip, base_extent),
+						mip_level: mip as u32,
+					},
+					// Only used by this frame, whose fence was waited
+					render_graph::ImageState::idle(vk::ImageLayout::GENERAL),
+				));
+			}
+		}
+		let read_image = |(chain, lod): (u32, u32)| {
+			if chain == 3 {
+				input
+			} else {
+				mips[chain as usize * mip_count + lod as usize]
+			}
 		};
+		let written_image = |index: u32| {
+			if index as usize == 3 * mip_count {
+				output
+			} else {
+				mips[index as usize]
+			}
+		};
+
+		// Last written by the average pass of the previous frame
+		let exposure_buffer = graph.import_buffer(
+			"Exposure",
+			self.exposure.graph_buffer(),
+			render_graph::AccessState::written(
+				vk::PipelineStageFlags::COMPUTE_SHADER,
+				vk::AccessFlags::SHADER_WRITE,
+			),
+		);
+		// Read back by the CPU and adapted by the next frames
+		graph.export_buffer(exposure_buffer);
+
+		let mut graph_passes = vec![BloomGraphPass::Histogram, BloomGraphPass::Average];
+		let mut passes = vec![
+			post_process::PostProcessPass {
+				name: "Exposure",
+				images: vec![(input, render_graph::ImageUsage::ComputeRead)],
+				buffers: vec![(exposure_buffer, render_graph::BufferUsage::ReadWrite)],
+			},
+			post_process::PostProcessPass {
+				name: "Exposure",
+				images: Vec::new(),
+				buffers: vec![(exposure_buffer, render_graph::BufferUsage::ReadWrite)],
+			},
+		];
+
+		let flare_mip =
+			(self.settings.lens_flare.intensity > 0.0).then_some(self.settings.lens_flare.mip);
+		let mut steps = bloom_steps(mip_count, self.settings.algorithm, self.spd.is_some());
+		if let Some(debug_dispatch) = self.settings.debug_view.dispatch(mip_count) {
+			*steps.last_mut().unwrap() = BloomStep::Dispatch(debug_dispatch);
+		}
+		for step in steps {
+			let reads: Vec<(render_graph::ImageHandle, render_graph::ImageUsage)> = step
+				.reads(mip_count, flare_mip)
+				.into_iter()
+				.map(|mip| (read_image(mip), render_graph::ImageUsage::ComputeRead))
+				.collect();
+			let writes: Vec<(render_graph::ImageHandle, render_graph::ImageUsage)> = step
+				.writes(mip_count)
+				.into_iter()
+				.map(|index| (written_image(index), render_graph::ImageUsage::ComputeWrite))
+				.collect();
+			match step {
+				BloomStep::Dispatch(bloom_dispatch) => {
+					let buffers = if step.reads_exposure() {
+						vec![(exposure_buffer, render_graph::BufferUsage::Read)]
+					} else {
+						Vec::new()
+					};
+					graph_passes.push(BloomGraphPass::Dispatch(bloom_dispatch));
+					passes.push(post_process::PostProcessPass {
+						name: step.stage_name(),
+						images: [reads, writes].concat(),
+						buffers,
+					});
+				}
+				BloomStep::FftConvolution => {
+					let signal_buffer = graph.import_buffer(
+						"FFT signal",
+						self.fft
+							.as_ref()
+							.expect("The FFT bloom isn't initialized.")
+							.signal_buffer(frame),
+						render_graph::AccessState::NONE,
+					);
+					for mode in bloom_fft::FFT_MODES {
+						// Loaded from the pong chain, stored to the upsample chain
+						let (images, usage) = match mode {
+							bloom_fft::FFT_MODE_LOAD => {
+								(reads.clone(), render_graph::BufferUsage::Write)
+							}
+							bloom_fft::FFT_MODE_STORE => {
+								(writes.clone(), render_graph::BufferUsage::Read)
+							}
+							_ => (Vec::new(), render_graph::BufferUsage::ReadWrite),
+						};
+						graph_passes.push(BloomGraphPass::Fft(mode));
+						passes.push(post_process::PostProcessPass {
+							name: step.stage_name(),
+							images,
+							buffers: vec![(signal_buffer, usage)],
+						});
+					}
+				}
+				BloomStep::SinglePassDownsample => {
+					let counter_buffer = graph.import_buffer(
+						"Single pass downsample counter",
+						self.spd
+							.as_ref()
+							.expect("The single pass downsampler isn't initialized.")
+							.counter_buffer(frame),
+						render_graph::AccessState::NONE,
+					);
+					graph_passes.push(BloomGraphPass::SinglePassDownsample);
+					passes.push(post_process::PostProcessPass {
+						name: step.stage_name(),
+						images: [reads, writes].concat(),
+						buffers: vec![(counter_buffer, render_graph::BufferUsage::ReadWrite)],
+					});
+				}
+			}
+		}
+
+		self.frame_graph = Some(BloomFrameGraph {
+			input,
+			output,
+			passes: graph_passes,
+			descriptors_updated: false,
+		});
+		passes
+	}
+
+	fn record_pass(
+		&mut self,
+		engine: &vulkan_engine::VulkanEngine,
+		command_buffer: &vk::CommandBuffer,
+		index: usize,
+		resources: &render_graph::PassResources,
+	) {
+		let frame_graph = self
+			.frame_graph
+			.as_mut()
+			.expect("The bloom passes weren't declared.");
+		let pass = frame_graph.passes[index];
+		let input_hdr = resources.image(frame_graph.input);
+		let output = frame_graph.output;
+		let update_descriptors = !frame_graph.descriptors_updated;
+		match pass {
+			BloomGraphPass::Histogram => {
+				self.exposure
+					.record_histogram(engine, command_buffer, input_hdr)
+			}
+			BloomGraphPass::Average => self.exposure.record_average(engine, command_buffer),
+			BloomGraphPass::Dispatch(bloom_dispatch) => {
+				if update_descriptors {
+					frame_graph.descriptors_updated = true;
+					self.parameters_buffers[engine.current_frame].write(0, vec![self.parameters]);
+					self.update_descriptor(engine, input_hdr, resources.image(output).view);
+				}
+				let size = self.dispatch_size(bloom_dispatch, input_hdr);
+				unsafe {
+					self.bind(engine, command_buffer);
+					self.dispatch(engine, command_buffer, bloom_dispatch, size);
+				};
+			}
+			BloomGraphPass::Fft(mode) => {
+				let images = &self.images[engine.current_frame];
+				self.fft
+					.as_mut()
+					.expect("The FFT bloom isn't initialized.")
+					.record(engine, command_buffer, mode, &images[0], &images[2]);
+			}
+			BloomGraphPass::SinglePassDownsample => {
+				let images = &self.images[engine.current_frame];
+				self.spd
+					.as_mut()
+					.expect("The single pass downsampler isn't initialized.")
+					.record(engine, command_buffer, &images[0], &images[1]);
+			}
+		}
+	}
+}
+
+impl vulkan_engine::Resizable for BloomPass {
+	/// Rebuilds the mip chains for the new base resolution.
+	fn resize(&mut self, engine: &vulkan_engine::VulkanEngine, extent: vk::Extent2D) {
+		self.extent = extent;
+		self.rebuild(engine);
+	}
+}
+
+fn sampled_image_info(image: &image::Image) -> vk::DescriptorImageInfo {
+	vk::DescriptorImageInfo::builder()
+		.image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+		.image_view(image.image_view)
+		.sampler(image.image_sampler.unwrap())
+		.build()
+}
+
+/// One step of the bloom, a pass of the render graph, or one per dispatch for the
+/// FFT convolution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BloomStep {
+	/// A dispatch of `bloom.comp`, over its whole output image: a mip of the ping,
+	/// pong or upsample chain, or the output of the effect for `MODE_APPLY`.
+	Dispatch(BloomDispatch),
+	/// The convolution of `bloom_fft.comp`, from the pong chain to mip 0 of the
+	/// upsample chain.
+	FftConvolution,
+	/// `bloom_spd.comp`, from mip 0 of the pong chain to the last mips of the ping and
+	/// pong chains, replacing their dispatches.
+	SinglePassDownsample,
+}
+
+impl BloomStep {
+	/// The mips the step samples, as `(chain, lod)` with the chain indices of
+	/// `BloomDispatch::input`: 3 is the HDR input. `flare_mip` is the pong mip the lens
+	/// flare of `MODE_APPLY` samples, `None` if it isn't drawn.
+	pub fn reads(self, mip_count: usize, flare_mip: Option<u32>) -> Vec<(u32, u32)> {
+		match self {
+			BloomStep::Dispatch(bloom_dispatch) => {
+				let BloomDispatch {
+					mode,
+					lod,
+					input,
+					bloom,
+					..
+				} = bloom_dispatch;
+				match mode {
+					MODE_UPSAMPLE_FIRST => vec![(input, lod), (input, lod + 1)],
+					MODE_UPSAMPLE => vec![(input, lod), (bloom, lod + 1)],
+					MODE_APPLY => {
+						let mut reads = vec![(input, 0), (bloom, lod)];
+						reads.extend(flare_mip.map(|mip| (0, mip.min(mip_count as u32 - 1))));
+						reads
+					}
+					_ => vec![(input, lod)],
+				}
+			}
+			// The mip the load samples depends on the size of the chain
+			BloomStep::FftConvolution => (0..mip_count as u32).map(|lod| (0, lod)).collect(),
+			BloomStep::SinglePassDownsample => vec![(0, 0)],
+		}
+	}
+
+	/// The images the step writes, indexed like `BloomDispatch::output`.
+	pub fn writes(self, mip_count: usize) -> Vec<u32> {
+		match self {
+			BloomStep::Dispatch(bloom_dispatch) => vec![bloom_dispatch.output],
+			BloomStep::FftConvolution => vec![2 * mip_count as u32],
+			BloomStep::SinglePassDownsample => {
+				let mip_count = mip_count as u32;
+				(1..mip_count).chain(mip_count + 1..2 * mip_count).collect()
+			}
+		}
+	}
+
+	/// Whether the step reads the exposure buffer.
+	pub fn reads_exposure(self) -> bool {
+		match self {
+			BloomStep::Dispatch(bloom_dispatch) => match bloom_dispatch.mode {
+				MODE_PREFILTER | MODE_APPLY => true,
+				MODE_DEBUG => bloom_dispatch.input == 3,
+				_ => false,
+			},
+			BloomStep::FftConvolution | BloomStep::SinglePassDownsample => false,
+		}
+	}
+
+	/// Name of the timestamp scope of the step.
+	pub fn stage_name(self) -> &'static str {
+		match self {
+			BloomStep::Dispatch(bloom_dispatch) => match bloom_dispatch.mode {
+				MODE_PREFILTER => "Prefilter",
+				MODE_DOWNSAMPLE | MODE_BLUR_HORIZONTAL | MODE_BLUR_VERTICAL => "Downsample",
+				MODE_UPSAMPLE_FIRST | MODE_UPSAMPLE => "Upsample",
+				MODE_APPLY => "Apply",
+				_ => "Debug view",
+			},
+			BloomStep::FftConvolution => "FFT convolution",
+			BloomStep::SinglePassDownsample => "Single pass downsample",
+		}
+	}
+}
+
+/// Every step of the bloom, in order.
+pub fn bloom_steps(
+	mip_count: usize,
+	algorithm: BloomAlgorithm,
+	single_pass_downsample: bool,
+) -> Vec<BloomStep> {
+	let mut steps = Vec::with_capacity(3 * mip_count);
+
+	if algorithm == BloomAlgorithm::Gaussian {
+		push_blurred_downsample_steps(&mut steps, mip_count);
+	} else if algorithm == BloomAlgorithm::DualKawase {
+		push_dual_kawase_downsample_steps(&mut steps, mip_count);
+	} else if single_pass_downsample {
+		//preFilter
+		steps.push(BloomStep::Dispatch(BloomDispatch {
+			mode: MODE_PREFILTER,
+			lod: 0,
+			input: 3,
+			output: 0,
+			bloom: 0,
+		}));
+		steps.push(BloomStep::SinglePassDownsample);
+	} else {
+		push_downsample_steps(&mut steps, mip_count);
+	}
+
+	match algorithm {
+		BloomAlgorithm::Fft => steps.push(BloomStep::FftConvolution),
+		_ => {
+			// First Upsample
+			steps.push(BloomStep::Dispatch(BloomDispatch {
+				mode: MODE_UPSAMPLE_FIRST,
+				lod: (mip_count - 2) as u32,
+				input: 0,
+				output: (3 * mip_count - 1) as u32,
+				bloom: 0,
+			}));
+
+			//Upsample
+			for i in (0..=mip_count - 2).rev() {
+				steps.push(BloomStep::Dispatch(BloomDispatch {
+					mode: MODE_UPSAMPLE,
+					lod: i as u32,
+					input: 0,
+					output: (2 * mip_count + i) as u32,
+					bloom: 2,
+				}));
+			}
+		}
 	}
 
 	// Apply the bloom to the render texture
-	unsafe {
-		let mip_size = engine.surface.surface_resolution;
-		bloom_data.mode_lod_in_out_bloom =
-			MODE_APPLY << 28 | 0 << 21 | 3 << 14 | (3 * BLOOM_MIP_COUNT as u32) << 7 | 2;
-		push.set_data(vec![bloom_data.clone()]);
-		engine.device.device.cmd_push_constants(
-			*command_buffer,
-			engine.compute_pipelines[0].pipeline_layout,
-			vk::ShaderStageFlags::COMPUTE,
-			0,
-			&push.data,
-		);
-		dispach(engine, command_buffer, mip_size, memory_barrier);
-	};
+	steps.push(BloomStep::Dispatch(BloomDispatch {
+		mode: MODE_APPLY,
+		lod: 0,
+		input: 3,
+		output: (3 * mip_count) as u32,
+		bloom: 2,
+	}));
+
+	steps
+}
+
+/// Prefilter into mip 0 of the pong chain, then each mip is downsampled from the
+/// previous pong mip into the ping chain and filtered back into the pong chain.
+fn push_downsample_steps(steps: &mut Vec<BloomStep>, mip_count: usize) {
+	//preFilter
+	steps.push(BloomStep::Dispatch(BloomDispatch {
+		mode: MODE_PREFILTER,
+		lod: 0,
+		input: 3,
+		output: 0,
+		bloom: 0,
+	}));
+
+	//DownSample
+	for i in 1..mip_count {
+		// Ping
+		steps.push(BloomStep::Dispatch(BloomDispatch {
+			mode: MODE_DOWNSAMPLE,
+			lod: (i - 1) as u32,
+			input: 0,
+			output: (mip_count + i) as u32,
+			bloom: 0,
+		}));
+
+		// Pong
+		steps.push(BloomStep::Dispatch(BloomDispatch {
+			mode: MODE_DOWNSAMPLE,
+			lod: i as u32,
+			input: 1,
+			output: i as u32,
+			bloom: 0,
+		}));
+	}
+}
 
-	// Change layout back to present
-	let image_memory_barrier = vk::ImageMemoryBarrier::builder()
-		.src_access_mask(vk::AccessFlags::MEMORY_WRITE | vk::AccessFlags::MEMORY_READ)
-		.dst_access_mask(vk::AccessFlags::MEMORY_READ)
-		.subresource_range(subresource_range)
-		.image(engine.swapchain.swapchain_images[current_image])
-		.src_queue_family_index(engine.device.queue_family_index)
-		.dst_queue_family_index(engine.device.queue_family_index)
-		.old_layout(vk::ImageLayout::UNDEFINED)
-		.new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+/// `BloomAlgorithm::DualKawase`: prefilter into mip 0 of the pong chain, then each
+/// pong mip is downsampled from the previous one.
+fn push_dual_kawase_downsample_steps(steps: &mut Vec<BloomStep>, mip_count: usize) {
+	steps.push(BloomStep::Dispatch(BloomDispatch {
+		mode: MODE_PREFILTER,
+		lod: 0,
+		input: 3,
+		output: 0,
+		bloom: 0,
+	}));
+
+	for i in 1..mip_count {
+		steps.push(BloomStep::Dispatch(BloomDispatch {
+			mode: MODE_DOWNSAMPLE,
+			lod: (i - 1) as u32,
+			input: 0,
+			output: i as u32,
+			bloom: 0,
+		}));
+	}
+}
+
+/// `BloomAlgorithm::Gaussian`: each mip is prefiltered or downsampled into the ping
+/// chain, blurred horizontally into the upsample chain (free until the upsample) and
+/// vertically into the pong chain.
+fn push_blurred_downsample_steps(steps: &mut Vec<BloomStep>, mip_count: usize) {
+	for i in 0..mip_count {
+		steps.push(BloomStep::Dispatch(if i == 0 {
+			BloomDispatch {
+				mode: MODE_PREFILTER,
+				lod: 0,
+				input: 3,
+				output: mip_count as u32,
+				bloom: 0,
+			}
+		} else {
+			BloomDispatch {
+				mode: MODE_DOWNSAMPLE,
+				lod: (i - 1) as u32,
+				input: 0,
+				output: (mip_count + i) as u32,
+				bloom: 0,
+			}
+		}));
+
+		steps.push(BloomStep::Dispatch(BloomDispatch {
+			mode: MODE_BLUR_HORIZONTAL,
+			lod: i as u32,
+			input: 1,
+			output: (2 * mip_count + i) as u32,
+			bloom: 0,
+		}));
+
+		steps.push(BloomStep::Dispatch(BloomDispatch {
+			mode: MODE_BLUR_VERTICAL,
+			lod: i as u32,
+			input: 2,
+			output: i as u32,
+			bloom: 0,
+		}));
+	}
+}
+
+/// Clamps `mip_count` between 2 and the number of mips the half resolution
+/// chain of an `extent` render target can hold.
+pub fn clamp_mip_count(mip_count: u32, extent: vk::Extent2D) -> usize {
+	let base_extent = vk::Extent2D::builder()
+		.width(extent.width / 2)
+		.height(extent.height / 2)
 		.build();
+	let mut supported = 0;
+	while supported < MAX_BLOOM_MIP_COUNT {
+		let mip_size = get_mip_size(supported, base_extent);
+		if mip_size.width == 0 || mip_size.height == 0 {
+			break;
+		}
+		supported += 1;
+	}
+	(mip_count as usize).clamp(2, supported.max(2))
+}
 
-	unsafe {
-		engine.device.device.cmd_pipeline_barrier(
-			*command_buffer,
-			vk::PipelineStageFlags::COMPUTE_SHADER,
-			vk::PipelineStageFlags::BOTTOM_OF_PIPE,
-			vk::DependencyFlags::empty(),
-			&[],
-			&[],
-			&[image_memory_barrier],
+fn get_mip_size(current_mip: usize, base_extent: vk::Extent2D) -> vk::Extent2D {
+	let mut width = base_extent.width;
+	let mut height = base_extent.height;
+	for _ in 0..current_mip {
+		width /= 2;
+		height /= 2;
+	}
+	vk::Extent2D::builder().width(width).height(height).build()
+}
+
+unsafe fn dispach(
+	engine: &vulkan_engine::VulkanEngine,
+	command_buffer: &vk::CommandBuffer,
+	image_size: vk::Extent2D,
+) {
+	let group_x = image_size.width.div_ceil(8);
+	let group_y = image_size.height.div_ceil(4);
+	engine
+		.device
+		.device
+		.cmd_dispatch(*command_buffer, group_x, group_y, 1);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn dispatch(mode: u32, lod: u32, input: u32, output: u32, bloom: u32) -> BloomDispatch {
+		BloomDispatch {
+			mode,
+			lod,
+			input,
+			output,
+			bloom,
+		}
+	}
+
+	#[test]
+	fn encode_accepts_the_largest_field_values() {
+		let cases = [
+			dispatch(MODE_MAX, 0, 0, 0, 0),
+			dispatch(0, 127, 0, 0, 0),
+			dispatch(0, 0, 127, 0, 0),
+			dispatch(0, 0, 0, 127, 0),
+			dispatch(0, 0, 0, 0, 127),
+			dispatch(MODE_MAX, 127, 127, 127, 127),
+		];
+		for case in cases {
+			let encoded = case.encode().expect("Failed to encode a valid dispatch.");
+			assert_eq!(BloomDispatch::decode(encoded), case);
+		}
+	}
+
+	#[test]
+	fn encode_rejects_overflowing_fields() {
+		assert_eq!(
+			dispatch(16, 0, 0, 0, 0).encode(),
+			Err(BloomDispatchError::ModeOutOfRange(16))
 		);
-	};
+		assert_eq!(
+			dispatch(0, 128, 0, 0, 0).encode(),
+			Err(BloomDispatchError::LodOutOfRange(128))
+		);
+		assert_eq!(
+			dispatch(0, 0, 128, 0, 0).encode(),
+			Err(BloomDispatchError::InputOutOfRange(128))
+		);
+		assert_eq!(
+			dispatch(0, 0, 0, 128, 0).encode(),
+			Err(BloomDispatchError::OutputOutOfRange(128))
+		);
+		assert_eq!(
+			dispatch(0, 0, 0, 0, 128).encode(),
+			Err(BloomDispatchError::BloomOutOfRange(128))
+		);
+	}
+
+	#[test]
+	fn fields_dont_overlap() {
+		let lod = dispatch(0, 127, 0, 0, 0).encode().unwrap();
+		let input = dispatch(0, 0, 127, 0, 0).encode().unwrap();
+		let output = dispatch(0, 0, 0, 127, 0).encode().unwrap();
+		let bloom = dispatch(0, 0, 0, 0, 127).encode().unwrap();
+		let mode = dispatch(MODE_MAX, 0, 0, 0, 0).encode().unwrap();
+		assert_eq!(lod & input, 0);
+		assert_eq!(input & output, 0);
+		assert_eq!(output & bloom, 0);
+		assert_eq!(mode & lod, 0);
+		assert_eq!(lod | input | output | bloom | mode, u32::MAX);
+	}
+
+	#[test]
+	fn every_read_mip_is_written_by_an_earlier_step() {
+		for mip_count in 2..=MAX_BLOOM_MIP_COUNT {
+			for algorithm in BloomAlgorithm::ALL {
+				for spd in [false, true] {
+					for flare_mip in [None, Some(0), Some(mip_count as u32)] {
+						let mut written = vec![false; 3 * mip_count + 1];
+						for step in bloom_steps(mip_count, algorithm, spd) {
+							for (chain, lod) in step.reads(mip_count, flare_mip) {
+								assert!(
+									chain == 3 || written[(chain * mip_count as u32 + lod) as usize],
+									"{:?} reads the mip {} of the chain {} before it is written ({} mips, {:?})",
+									step,
+									lod,
+									chain,
+									mip_count,
+									algorithm
+								);
+							}
+							for output in step.writes(mip_count) {
+								written[output as usize] = true;
+							}
+						}
+						assert!(written[3 * mip_count]);
+					}
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn every_bloom_step_encodes() {
+		for algorithm in BloomAlgorithm::ALL {
+			for mip_count in 2..=MAX_BLOOM_MIP_COUNT {
+				for spd in [false, true] {
+					for step in bloom_steps(mip_count, algorithm, spd) {
+						if let BloomStep::Dispatch(bloom_dispatch) = step {
+							bloom_dispatch
+								.encode()
+								.expect("Failed to encode a bloom step.");
+						}
+					}
+				}
+			}
+		}
+	}
 }
@@ -0,0 +1,360 @@
+use std::mem::size_of;
+
+use ash::vk;
+use gpu_alloc::UsageFlags;
+
+use crate::fft;
+use crate::vulkan_engine::{
+	self, buffer, descriptor, image, pipeline, push_constant, render_graph, shader_module,
+};
+
+/// Side of the FFT grid, must match `FFT_SIZE` in `bloom_fft.comp`.
+pub const FFT_SIZE: usize = 512;
+const CHANNEL_COUNT: usize = 3;
+/// Largest kernel side that doesn't wrap around the signal, which takes the other half
+/// of the grid.
+pub const MAX_KERNEL_SIZE: usize = FFT_SIZE / 2;
+
+pub const FFT_MODE_LOAD: u32 = 0;
+pub const FFT_MODE_ROWS: u32 = 1;
+pub const FFT_MODE_COLUMNS: u32 = 2;
+pub const FFT_MODE_MULTIPLY: u32 = 3;
+pub const FFT_MODE_INVERSE_COLUMNS: u32 = 4;
+pub const FFT_MODE_INVERSE_ROWS: u32 = 5;
+pub const FFT_MODE_STORE: u32 = 6;
+/// The dispatches of a convolution, in order.
+pub const FFT_MODES: [u32; 7] = [
+	FFT_MODE_LOAD,
+	FFT_MODE_ROWS,
+	FFT_MODE_COLUMNS,
+	FFT_MODE_MULTIPLY,
+	FFT_MODE_INVERSE_COLUMNS,
+	FFT_MODE_INVERSE_ROWS,
+	FFT_MODE_STORE,
+];
+
+/// Mirror of the push constant of `bloom_fft.comp`.
+#[derive(Default, Clone, Copy)]
+#[repr(C)]
+struct FftConstant {
+	mode: u32,
+	output_width: u32,
+	output_height: u32,
+	scale: f32,
+}
+
+/// Bloom by convolution with a kernel image (a lens point spread function), replacing
+/// the upsample chain. The prefiltered image is laid in half of the FFT grid, the
+/// other half is padding so the kernel doesn't wrap around the borders.
+pub struct FftBloom {
+	pipeline: pipeline::ComputePipeline,
+	push_constant: push_constant::PushConstant,
+	/// One per frame in flight.
+	descriptors: Vec<descriptor::DescriptorSet>,
+	/// One per frame in flight, the spectrum of the frame between the dispatches.
+	signal_buffers: Vec<buffer::Buffer>,
+	kernel_buffer: buffer::Buffer,
+	fft_data: FftConstant,
+}
+
+impl FftBloom {
+	pub fn new(engine: &vulkan_engine::VulkanEngine) -> FftBloom {
+		let buffer_size = FftBloom::buffer_size();
+		let signal_buffers: Vec<buffer::Buffer> = (0..engine.frames_in_flight)
+			.map(|_| {
+				buffer::Buffer::new(
+					&engine.device,
+					vk::BufferCreateFlags::empty(),
+					buffer_size,
+					vk::BufferUsageFlags::STORAGE_BUFFER,
+					vk::SharingMode::EXCLUSIVE,
+					UsageFlags::FAST_DEVICE_ACCESS,
+				)
+			})
+			.collect();
+		let kernel_buffer = buffer::Buffer::new(
+			&engine.device,
+			vk::BufferCreateFlags::empty(),
+			buffer_size,
+			vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+			vk::SharingMode::EXCLUSIVE,
+			UsageFlags::FAST_DEVICE_ACCESS,
+		);
+
+		let descriptors: Vec<descriptor::DescriptorSet> = (0..engine.frames_in_flight)
+			.map(|_| FftBloom::create_descriptor(engine, &kernel_buffer))
+			.collect();
+
+		let fft_data = FftConstant::default();
+		let push_constant = push_constant::PushConstant::new(
+			0,
+			size_of::<FftConstant>() as u32,
+			vk::ShaderStageFlags::COMPUTE,
+			vec![fft_data],
+		);
+
+		let compute_module =
+			shader_module::ShaderModule::new(&engine.device, "shaders/spv/bloom_fft.spv", "main");
+		let pipeline = pipeline::ComputePipeline::builder()
+			.add_push_constant(&push_constant)
+			.add_descriptor_set(&descriptors[0], 0)
+			.compute_module(&compute_module, vk::PipelineShaderStageCreateFlags::empty())
+			.build(&engine.device);
+
+		let mut fft_bloom = FftBloom {
+			pipeline,
+			push_constant,
+			descriptors,
+			signal_buffers,
+			kernel_buffer,
+			fft_data,
+		};
+		let (kernel, size) = default_kernel();
+		fft_bloom.upload_kernel(engine, &kernel, size, size);
+		fft_bloom
+	}
+
+	fn create_descriptor(
+		engine: &vulkan_engine::VulkanEngine,
+		kernel_buffer: &buffer::Buffer,
+	) -> descriptor::DescriptorSet {
+		let descriptor = descriptor::DescriptorSet::new(
+			&engine.device,
+			vec![
+				(vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 1),
+				(vk::DescriptorType::STORAGE_IMAGE, 1),
+				(vk::DescriptorType::STORAGE_BUFFER, 2),
+			],
+			1,
+			vec![
+				vk::DescriptorSetLayoutBinding::builder()
+					.binding(0)
+					.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+					.descriptor_count(1)
+					.stage_flags(vk::ShaderStageFlags::COMPUTE)
+					.build(),
+				vk::DescriptorSetLayoutBinding::builder()
+					.binding(1)
+					.descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+					.descriptor_count(1)
+					.stage_flags(vk::ShaderStageFlags::COMPUTE)
+					.build(),
+				vk::DescriptorSetLayoutBinding::builder()
+					.binding(2)
+					.descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+					.descriptor_count(1)
+					.stage_flags(vk::ShaderStageFlags::COMPUTE)
+					.build(),
+				vk::DescriptorSetLayoutBinding::builder()
+					.binding(3)
+					.descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+					.descriptor_count(1)
+					.stage_flags(vk::ShaderStageFlags::COMPUTE)
+					.build(),
+			],
+		);
+		descriptor.update_descriptor_set(
+			0,
+			3,
+			Some(vec![FftBloom::buffer_info(kernel_buffer)]),
+			None,
+		);
+		descriptor
+	}
+
+	fn buffer_info(buffer: &buffer::Buffer) -> vk::DescriptorBufferInfo {
+		vk::DescriptorBufferInfo::builder()
+			.buffer(*buffer.buffer)
+			.offset(0)
+			.range(FftBloom::buffer_size())
+			.build()
+	}
+
+	fn buffer_size() -> u64 {
+		(CHANNEL_COUNT * FFT_SIZE * FFT_SIZE * size_of::<fft::Complex>()) as u64
+	}
+
+	/// The signal buffer of `frame`, for the render graph.
+	pub fn signal_buffer(&self, frame: usize) -> render_graph::GraphBuffer {
+		render_graph::GraphBuffer {
+			buffer: *self.signal_buffers[frame].buffer,
+			size: FftBloom::buffer_size(),
+		}
+	}
+
+	/// Loads the kernel from disk, `None` restores the default glow with four spikes.
+	/// Kernels larger than `MAX_KERNEL_SIZE` are scaled down to fit.
+	pub fn set_kernel(
+		&mut self,
+		engine: &vulkan_engine::VulkanEngine,
+		path: Option<&std::path::Path>,
+	) -> Result<(), ::image::ImageError> {
+		match path {
+			Some(path) => {
+				let mut kernel = ::image::open(path)?;
+				if kernel.width() as usize > MAX_KERNEL_SIZE
+					|| kernel.height() as usize > MAX_KERNEL_SIZE
+				{
+					kernel = kernel.resize(
+						MAX_KERNEL_SIZE as u32,
+						MAX_KERNEL_SIZE as u32,
+						::image::imageops::FilterType::Triangle,
+					);
+				}
+				let kernel = kernel.into_rgb32f();
+				let (width, height) = (kernel.width() as usize, kernel.height() as usize);
+				self.upload_kernel(engine, &kernel.into_raw(), width, height);
+			}
+			None => {
+				let (kernel, size) = default_kernel();
+				self.upload_kernel(engine, &kernel, size, size);
+			}
+		}
+		Ok(())
+	}
+
+	/// `kernel` is interleaved rgb.
+	fn upload_kernel(
+		&mut self,
+		engine: &vulkan_engine::VulkanEngine,
+		kernel: &[f32],
+		width: usize,
+		height: usize,
+	) {
+		assert!(
+			width <= MAX_KERNEL_SIZE && height <= MAX_KERNEL_SIZE,
+			"The FFT kernel would wrap around the bloom."
+		);
+		let spectrum = kernel_spectrum_rgb(kernel, width, height);
+		unsafe {
+			engine
+				.device
+				.device
+				.device_wait_idle()
+				.expect("Failed to wait for the device to be idle.");
+		};
+		self.kernel_buffer
+			.write_to_vram(&engine.device, &engine.command_builder, 0, spectrum);
+	}
+
+	/// Records the dispatch `mode` of the convolution of mip 0 of `prefiltered`
+	/// (sampled with its mips) into mip 0 of `output`, both in `GENERAL` layout.
+	/// `FFT_MODE_LOAD` updates the descriptor set of the frame, so it must be recorded
+	/// first. Binds its own pipeline.
+	pub fn record(
+		&mut self,
+		engine: &vulkan_engine::VulkanEngine,
+		command_buffer: &vk::CommandBuffer,
+		mode: u32,
+		prefiltered: &image::Image,
+		output: &image::Image,
+	) {
+		let descriptor = &self.descriptors[engine.current_frame];
+		if mode == FFT_MODE_LOAD {
+			descriptor.update_descriptor_set(
+				0,
+				0,
+				None,
+				Some(vec![vk::DescriptorImageInfo::builder()
+					.image_layout(vk::ImageLayout::GENERAL)
+					.image_view(prefiltered.image_view)
+					.sampler(prefiltered.image_sampler.unwrap())
+					.build()]),
+			);
+			descriptor.update_descriptor_set(
+				0,
+				1,
+				None,
+				Some(vec![vk::DescriptorImageInfo::builder()
+					.image_layout(vk::ImageLayout::GENERAL)
+					.image_view(output.mip_image_views[0])
+					.build()]),
+			);
+			descriptor.update_descriptor_set(
+				0,
+				2,
+				Some(vec![FftBloom::buffer_info(
+					&self.signal_buffers[engine.current_frame],
+				)]),
+				None,
+			);
+		}
+
+		let (width, height) = (output.extent.width, output.extent.height);
+		self.fft_data.mode = mode;
+		self.fft_data.output_width = width;
+		self.fft_data.output_height = height;
+		self.fft_data.scale = width.max(height) as f32 / (FFT_SIZE / 2) as f32;
+		self.push_constant.set_data(vec![self.fft_data]);
+
+		let local_size = (FFT_SIZE / 2) as u32;
+		let group_count = match mode {
+			FFT_MODE_LOAD | FFT_MODE_MULTIPLY => (FFT_SIZE * FFT_SIZE) as u32 / local_size,
+			FFT_MODE_STORE => (width * height).div_ceil(local_size),
+			_ => FFT_SIZE as u32,
+		};
+
+		unsafe {
+			engine.device.device.cmd_bind_pipeline(
+				*command_buffer,
+				vk::PipelineBindPoint::COMPUTE,
+				self.pipeline.pipeline,
+			);
+			engine.device.device.cmd_bind_descriptor_sets(
+				*command_buffer,
+				vk::PipelineBindPoint::COMPUTE,
+				self.pipeline.pipeline_layout,
+				0,
+				&descriptor.descriptor_set,
+				&[],
+			);
+			engine.device.device.cmd_push_constants(
+				*command_buffer,
+				self.pipeline.pipeline_layout,
+				vk::ShaderStageFlags::COMPUTE,
+				0,
+				&self.push_constant.data,
+			);
+			engine
+				.device
+				.device
+				.cmd_dispatch(*command_buffer, group_count, 1, 1);
+		};
+	}
+}
+
+/// Spectrum of an interleaved rgb kernel, laid out like `kernel_spectrum` of
+/// `bloom_fft.comp`: one plane per channel.
+pub fn kernel_spectrum_rgb(kernel: &[f32], width: usize, height: usize) -> Vec<fft::Complex> {
+	let mut spectrum = Vec::with_capacity(CHANNEL_COUNT * FFT_SIZE * FFT_SIZE);
+	for channel in 0..CHANNEL_COUNT {
+		let plane: Vec<f32> = kernel
+			.iter()
+			.skip(channel)
+			.step_by(CHANNEL_COUNT)
+			.copied()
+			.collect();
+		spectrum.extend(fft::kernel_spectrum(&plane, width, height, FFT_SIZE));
+	}
+	spectrum
+}
+
+/// A soft glow with four diffraction spikes, as interleaved rgb.
+pub fn default_kernel() -> (Vec<f32>, usize) {
+	let size = MAX_KERNEL_SIZE;
+	let center = (size / 2) as f32;
+	let mut kernel = Vec::with_capacity(size * size * CHANNEL_COUNT);
+	for y in 0..size {
+		for x in 0..size {
+			let dx = x as f32 - center;
+			let dy = y as f32 - center;
+			let r2 = dx * dx + dy * dy;
+			let glow = 1.0 / (1.0 + r2 / 4.0).powf(1.5);
+			let spikes = (-dy * dy).exp() / (1.0 + dx.abs() / 8.0)
+				+ (-dx * dx).exp() / (1.0 + dy.abs() / 8.0);
+			let value = glow + 0.05 * spikes;
+			kernel.extend_from_slice(&[value, value, value]);
+		}
+	}
+	(kernel, size)
+}
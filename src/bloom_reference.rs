@@ -0,0 +1,1544 @@
+//! CPU mirror of `shaders/bloom.comp` over `f32` RGBA images.
+//!
+//! Every function follows its GLSL counterpart line by line, quirks included, so a
+//! change to the shader math can be checked against `BloomReference::run`. The GPU
+//! stores the mip chains as rgba16f, so comparisons need a small tolerance.
+
+use ash::vk;
+
+use crate::bloom::{
+	self, BloomAlgorithm, BloomDispatch, BloomParameters, BloomSettings, BloomShape, BloomStep,
+	DitherMode, Tonemapper, BLOOM_MIP_TINT_COUNT,
+};
+use crate::bloom_fft::{self, FFT_SIZE};
+use crate::bloom_spd;
+use crate::blue_noise::{self, BlueNoise};
+use crate::color_lut::CubeLut;
+use crate::fft;
+
+const EPSILON: f32 = 1.0e-4;
+const GOLDEN_RATIO_STEP: u32 = 2654435769;
+const R2_STEP_X: u32 = 3242174889;
+const R2_STEP_Y: u32 = 2447445414;
+
+/// The sampler address modes used by the bloom textures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressMode {
+	/// The bloom mip chains.
+	ClampToEdge,
+	/// The HDR input, with an opaque black border.
+	ClampToBorder,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RgbaImage {
+	pub width: usize,
+	pub height: usize,
+	pub pixels: Vec<uv::Vec4>,
+}
+
+impl RgbaImage {
+	pub fn new(width: usize, height: usize) -> RgbaImage {
+		RgbaImage {
+			width,
+			height,
+			pixels: vec![uv::Vec4::zero(); width * height],
+		}
+	}
+
+	pub fn from_fn(width: usize, height: usize, f: impl Fn(usize, usize) -> uv::Vec4) -> RgbaImage {
+		let mut pixels = Vec::with_capacity(width * height);
+		for y in 0..height {
+			for x in 0..width {
+				pixels.push(f(x, y));
+			}
+		}
+		RgbaImage {
+			width,
+			height,
+			pixels,
+		}
+	}
+
+	pub fn get(&self, x: usize, y: usize) -> uv::Vec4 {
+		self.pixels[y * self.width + x]
+	}
+
+	/// Largest per channel difference with `other`, for golden comparisons.
+	pub fn max_difference(&self, other: &RgbaImage) -> f32 {
+		assert_eq!(self.width, other.width, "Image widths differ.");
+		assert_eq!(self.height, other.height, "Image heights differ.");
+		self.pixels
+			.iter()
+			.zip(other.pixels.iter())
+			.map(|(a, b)| {
+				let d = (*a - *b).abs();
+				d.x.max(d.y).max(d.z).max(d.w)
+			})
+			.fold(0.0f32, f32::max)
+	}
+
+	fn texel(&self, x: i64, y: i64, address_mode: AddressMode) -> uv::Vec4 {
+		match address_mode {
+			AddressMode::ClampToEdge => self.get(
+				x.clamp(0, self.width as i64 - 1) as usize,
+				y.clamp(0, self.height as i64 - 1) as usize,
+			),
+			AddressMode::ClampToBorder => {
+				if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+					uv::Vec4::new(0.0, 0.0, 0.0, 1.0)
+				} else {
+					self.get(x as usize, y as usize)
+				}
+			}
+		}
+	}
+
+	/// Bilinear sample at normalized coordinates, as a `LINEAR` Vulkan sampler does.
+	pub fn sample(&self, uv: uv::Vec2, address_mode: AddressMode) -> uv::Vec4 {
+		let x = uv.x * self.width as f32 - 0.5;
+		let y = uv.y * self.height as f32 - 0.5;
+		let x0 = x.floor();
+		let y0 = y.floor();
+		let fx = x - x0;
+		let fy = y - y0;
+		let (x0, y0) = (x0 as i64, y0 as i64);
+
+		let top = self.texel(x0, y0, address_mode) * (1.0 - fx)
+			+ self.texel(x0 + 1, y0, address_mode) * fx;
+		let bottom = self.texel(x0, y0 + 1, address_mode) * (1.0 - fx)
+			+ self.texel(x0 + 1, y0 + 1, address_mode) * fx;
+		top * (1.0 - fy) + bottom * fy
+	}
+}
+
+/// An image with its mip chain, sampled like a `sampler2D`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Texture {
+	pub levels: Vec<RgbaImage>,
+	pub address_mode: AddressMode,
+}
+
+impl Texture {
+	pub fn new(
+		width: usize,
+		height: usize,
+		mip_count: usize,
+		address_mode: AddressMode,
+	) -> Texture {
+		let levels = (0..mip_count)
+			.map(|i| RgbaImage::new((width >> i).max(1), (height >> i).max(1)))
+			.collect();
+		Texture {
+			levels,
+			address_mode,
+		}
+	}
+
+	pub fn from_image(image: RgbaImage, address_mode: AddressMode) -> Texture {
+		Texture {
+			levels: vec![image],
+			address_mode,
+		}
+	}
+
+	fn level(&self, lod: u32) -> &RgbaImage {
+		&self.levels[(lod as usize).min(self.levels.len() - 1)]
+	}
+
+	/// `textureSize`
+	pub fn size(&self, lod: u32) -> uv::Vec2 {
+		let level = self.level(lod);
+		uv::Vec2::new(level.width as f32, level.height as f32)
+	}
+
+	/// `textureLod` with an integer lod.
+	pub fn sample_lod(&self, uv: uv::Vec2, lod: u32) -> uv::Vec4 {
+		self.level(lod).sample(uv, self.address_mode)
+	}
+
+	/// `textureLod` with `VK_SAMPLER_MIPMAP_MODE_LINEAR`.
+	pub fn sample_lod_linear(&self, uv: uv::Vec2, lod: f32) -> uv::Vec4 {
+		let lod = lod.clamp(0.0, (self.levels.len() - 1) as f32);
+		let base = lod.floor();
+		let low = self.sample_lod(uv, base as u32);
+		let high = self.sample_lod(uv, base as u32 + 1);
+		low + (high - low) * (lod - base)
+	}
+}
+
+/// Quadratic color thresholding
+/// curve = (threshold - knee, knee * 2, 0.25 / knee)
+pub fn quadratic_threshold(color: uv::Vec4, threshold: f32, curve: uv::Vec3) -> uv::Vec4 {
+	// Maximum pixel brightness
+	let brightness = color.x.max(color.y).max(color.z);
+	// Quadratic curve, GLSL clamp is min(max(x, lo), hi)
+	let mut rq = (brightness - curve.x).max(0.0).min(curve.y);
+	rq = (rq * rq) * curve.z;
+	color * (rq.max(brightness - threshold) / brightness.max(EPSILON))
+}
+
+pub fn prefilter(color: uv::Vec4, params: uv::Vec4, clamp_value: f32) -> uv::Vec4 {
+	let color = color.min_by_component(uv::Vec4::broadcast(clamp_value));
+	quadratic_threshold(color, params.x, uv::Vec3::new(params.y, params.z, params.w))
+}
+
+/// Keeps the duplicated outer taps of the shader (`F`/`L` and `I`/`J`).
+pub fn downsample_box13(tex: &Texture, lod: u32, uv: uv::Vec2, texel_size: uv::Vec2) -> uv::Vec3 {
+	let sample = |offset: uv::Vec2| tex.sample_lod(uv + offset, lod).xyz();
+
+	// Center
+	let a = sample(uv::Vec2::zero());
+
+	let texel_size = texel_size * 0.5; // Sample from center of texels
+
+	// Inner box
+	let b = sample(texel_size * uv::Vec2::new(-1.0, -1.0));
+	let c = sample(texel_size * uv::Vec2::new(-1.0, 1.0));
+	let d = sample(texel_size * uv::Vec2::new(1.0, 1.0));
+	let e = sample(texel_size * uv::Vec2::new(1.0, -1.0));
+
+	// Outer box
+	let f = sample(texel_size * uv::Vec2::new(-2.0, -2.0));
+	let g = sample(texel_size * uv::Vec2::new(-2.0, 0.0));
+	let h = sample(texel_size * uv::Vec2::new(0.0, 2.0));
+	let i = sample(texel_size * uv::Vec2::new(2.0, 2.0));
+	let j = sample(texel_size * uv::Vec2::new(2.0, 2.0));
+	let k = sample(texel_size * uv::Vec2::new(2.0, 0.0));
+	let l = sample(texel_size * uv::Vec2::new(-2.0, -2.0));
+	let m = sample(texel_size * uv::Vec2::new(0.0, -2.0));
+
+	// Weights
+	let mut result = uv::Vec3::zero();
+	// Inner box
+	result += (b + c + d + e) * 0.5;
+	// Bottom-left box
+	result += (f + g + a + m) * 0.125;
+	// Top-left box
+	result += (g + h + i + a) * 0.125;
+	// Top-right box
+	result += (a + i + j + k) * 0.125;
+	// Bottom-right box
+	result += (m + a + k + l) * 0.125;
+
+	// 4 samples each
+	result * 0.25
+}
+
+pub fn luma(color: uv::Vec3) -> f32 {
+	color.dot(uv::Vec3::new(0.2126, 0.7152, 0.0722))
+}
+
+/// Same taps as `downsample_box13`, each box weighted by `1 / (1 + luma)` of its
+/// exposed color.
+pub fn downsample_box13_karis(
+	tex: &Texture,
+	lod: u32,
+	uv: uv::Vec2,
+	texel_size: uv::Vec2,
+	exposure: f32,
+) -> uv::Vec3 {
+	let sample = |offset: uv::Vec2| tex.sample_lod(uv + offset, lod).xyz();
+
+	// Center
+	let a = sample(uv::Vec2::zero());
+
+	let texel_size = texel_size * 0.5; // Sample from center of texels
+
+	// Inner box
+	let b = sample(texel_size * uv::Vec2::new(-1.0, -1.0));
+	let c = sample(texel_size * uv::Vec2::new(-1.0, 1.0));
+	let d = sample(texel_size * uv::Vec2::new(1.0, 1.0));
+	let e = sample(texel_size * uv::Vec2::new(1.0, -1.0));
+
+	// Outer box
+	let f = sample(texel_size * uv::Vec2::new(-2.0, -2.0));
+	let g = sample(texel_size * uv::Vec2::new(-2.0, 0.0));
+	let h = sample(texel_size * uv::Vec2::new(0.0, 2.0));
+	let i = sample(texel_size * uv::Vec2::new(2.0, 2.0));
+	let j = sample(texel_size * uv::Vec2::new(2.0, 2.0));
+	let k = sample(texel_size * uv::Vec2::new(2.0, 0.0));
+	let l = sample(texel_size * uv::Vec2::new(-2.0, -2.0));
+	let m = sample(texel_size * uv::Vec2::new(0.0, -2.0));
+
+	// Inner, bottom-left, top-left, top-right and bottom-right boxes
+	let boxes = [
+		(b + c + d + e) * 0.25,
+		(f + g + a + m) * 0.25,
+		(g + h + i + a) * 0.25,
+		(a + i + j + k) * 0.25,
+		(m + a + k + l) * 0.25,
+	];
+	let box_weights = [0.5, 0.125, 0.125, 0.125, 0.125];
+
+	let mut result = uv::Vec3::zero();
+	let mut weight_sum = 0.0;
+	for (color, box_weight) in boxes.iter().zip(box_weights.iter()) {
+		let weight = box_weight / (1.0 + luma(*color * exposure));
+		result += *color * weight;
+		weight_sum += weight;
+	}
+	result / weight_sum
+}
+
+pub fn upsample_tent9(
+	tex: &Texture,
+	lod: u32,
+	uv: uv::Vec2,
+	texel_size: uv::Vec2,
+	radius: f32,
+) -> uv::Vec3 {
+	let sample = |uv: uv::Vec2| tex.sample_lod(uv, lod).xyz();
+	let (x, y, z, w) = (
+		texel_size.x * radius,
+		texel_size.y * radius,
+		-texel_size.x * radius,
+		0.0,
+	);
+
+	// Center
+	let mut result = sample(uv) * 4.0;
+
+	result += sample(uv - uv::Vec2::new(x, y));
+	result += sample(uv - uv::Vec2::new(w, y)) * 2.0;
+	result += sample(uv - uv::Vec2::new(z, y));
+
+	result += sample(uv + uv::Vec2::new(z, w)) * 2.0;
+	result += sample(uv + uv::Vec2::new(x, w)) * 2.0;
+
+	result += sample(uv + uv::Vec2::new(z, y));
+	result += sample(uv + uv::Vec2::new(w, y)) * 2.0;
+	result += sample(uv + uv::Vec2::new(x, y));
+
+	result * (1.0 / 16.0)
+}
+
+pub fn downsample_streak(tex: &Texture, lod: u32, uv: uv::Vec2, texel_size: uv::Vec2) -> uv::Vec3 {
+	let sample = |offset: f32| {
+		tex.sample_lod(uv + uv::Vec2::new(texel_size.x * offset, 0.0), lod)
+			.xyz()
+	};
+
+	let c0 = sample(-5.0);
+	let c1 = sample(-3.0);
+	let c2 = sample(-1.0);
+	let c3 = sample(1.0);
+	let c4 = sample(3.0);
+	let c5 = sample(5.0);
+
+	(c0 + c1 * 2.0 + c2 * 3.0 + c3 * 3.0 + c4 * 2.0 + c5) / 12.0
+}
+
+pub fn upsample_streak(
+	tex: &Texture,
+	lod: u32,
+	uv: uv::Vec2,
+	texel_size: uv::Vec2,
+	stretch: f32,
+) -> uv::Vec3 {
+	let dx = uv::Vec2::new(texel_size.x * stretch, 0.0);
+
+	let mut result = tex.sample_lod(uv, lod).xyz() * 2.0;
+	result += tex.sample_lod(uv - dx, lod).xyz();
+	result += tex.sample_lod(uv + dx, lod).xyz();
+
+	result * 0.25
+}
+
+pub fn downsample_dual_kawase(
+	tex: &Texture,
+	lod: u32,
+	uv: uv::Vec2,
+	texel_size: uv::Vec2,
+) -> uv::Vec3 {
+	let sample = |offset: uv::Vec2| tex.sample_lod(uv + offset, lod).xyz();
+
+	let mut result = sample(uv::Vec2::zero()) * 4.0;
+	result += sample(-texel_size);
+	result += sample(texel_size);
+	result += sample(uv::Vec2::new(texel_size.x, -texel_size.y));
+	result += sample(-uv::Vec2::new(texel_size.x, -texel_size.y));
+
+	result * (1.0 / 8.0)
+}
+
+pub fn upsample_dual_kawase(
+	tex: &Texture,
+	lod: u32,
+	uv: uv::Vec2,
+	texel_size: uv::Vec2,
+) -> uv::Vec3 {
+	let sample = |offset: uv::Vec2| tex.sample_lod(uv + offset, lod).xyz();
+	let half_texel = texel_size * 0.5;
+
+	let mut result = sample(uv::Vec2::new(-texel_size.x, 0.0));
+	result += sample(uv::Vec2::new(texel_size.x, 0.0));
+	result += sample(uv::Vec2::new(0.0, -texel_size.y));
+	result += sample(uv::Vec2::new(0.0, texel_size.y));
+
+	result += sample(uv::Vec2::new(-half_texel.x, half_texel.y)) * 2.0;
+	result += sample(uv::Vec2::new(half_texel.x, half_texel.y)) * 2.0;
+	result += sample(uv::Vec2::new(half_texel.x, -half_texel.y)) * 2.0;
+	result += sample(uv::Vec2::new(-half_texel.x, -half_texel.y)) * 2.0;
+
+	result * (1.0 / 12.0)
+}
+
+/// 9 tap gaussian through 5 bilinear fetches along `texel_step`.
+pub fn gaussian_blur9(tex: &Texture, lod: u32, uv: uv::Vec2, texel_step: uv::Vec2) -> uv::Vec3 {
+	const OFFSETS: [f32; 3] = [0.0, 1.3846154, 3.2307692];
+	const WEIGHTS: [f32; 3] = [0.22702703, 0.31621622, 0.07027027];
+
+	let mut result = tex.sample_lod(uv, lod).xyz() * WEIGHTS[0];
+	for (offset, weight) in OFFSETS.iter().zip(WEIGHTS.iter()).skip(1) {
+		result += tex.sample_lod(uv + texel_step * *offset, lod).xyz() * *weight;
+		result += tex.sample_lod(uv - texel_step * *offset, lod).xyz() * *weight;
+	}
+	result
+}
+
+fn fract(x: uv::Vec2) -> uv::Vec2 {
+	uv::Vec2::new(x.x - x.x.floor(), x.y - x.y.floor())
+}
+
+pub fn sample_chromatic(
+	tex: &Texture,
+	lod: u32,
+	uv: uv::Vec2,
+	direction: uv::Vec2,
+	distortion: uv::Vec3,
+) -> uv::Vec3 {
+	uv::Vec3::new(
+		tex.sample_lod(uv + direction * distortion.x, lod).x,
+		tex.sample_lod(uv + direction * distortion.y, lod).y,
+		tex.sample_lod(uv + direction * distortion.z, lod).z,
+	)
+}
+
+/// `LensFlare` of `bloom.comp`: ghosts mirrored through the screen centre and a halo.
+pub(crate) fn lens_flare(
+	tex: &Texture,
+	lod: u32,
+	uv: uv::Vec2,
+	parameters: &BloomParameters,
+) -> uv::Vec3 {
+	let texel_size = uv::Vec2::one() / tex.size(lod);
+	let half = uv::Vec2::broadcast(0.5);
+	let flipped_uv = uv::Vec2::one() - uv;
+	let ghost_vector = (half - flipped_uv) * parameters.ghost_spacing;
+	let direction = (ghost_vector + uv::Vec2::broadcast(EPSILON)).normalized();
+	let distortion =
+		uv::Vec3::new(-texel_size.x, 0.0, texel_size.x) * parameters.chromatic_distortion;
+
+	let mut result = uv::Vec3::zero();
+	for i in 0..parameters.ghost_count {
+		let offset = fract(flipped_uv + ghost_vector * i as f32);
+		// Only the bright spots near the centre cast ghosts
+		let weight = (1.0 - (half - offset).mag() / half.mag()).powf(10.0);
+		result += sample_chromatic(tex, lod, offset, direction, distortion) * weight;
+	}
+
+	let halo_vector = direction * parameters.halo_width;
+	let halo_uv = fract(flipped_uv + halo_vector);
+	let halo_weight = (1.0 - (half - halo_uv).mag() / half.mag()).powf(5.0);
+	result += sample_chromatic(tex, lod, halo_uv, direction, distortion) * halo_weight;
+
+	result * parameters.flare_intensity
+}
+
+/// `Bayer8` of `bloom.comp`.
+fn bayer8(x: usize, y: usize) -> f32 {
+	let (x, y) = (x & 7, y & 7);
+	let v = x ^ y;
+	let index = ((v & 1) << 5)
+		| ((y & 1) << 4)
+		| ((v & 2) << 2)
+		| ((y & 2) << 1)
+		| ((v & 4) >> 1)
+		| ((y & 4) >> 2);
+	(index as f32 + 0.5) / 64.0
+}
+
+/// `FrameSequence` of `bloom.comp`.
+fn frame_sequence(frame_index: u32, step: u32) -> f32 {
+	(frame_index.wrapping_mul(step) as f32 * (1.0 / 4294967296.0)).fract()
+}
+
+/// `Dither` of `bloom.comp`.
+pub fn dither(
+	color: uv::Vec3,
+	x: usize,
+	y: usize,
+	mode: u32,
+	frame_index: u32,
+	blue_noise: &BlueNoise,
+) -> uv::Vec3 {
+	let offset = uv::Vec2::new(
+		frame_sequence(frame_index, R2_STEP_X),
+		frame_sequence(frame_index, R2_STEP_Y),
+	);
+	let noise = if mode == DitherMode::Ordered as u32 {
+		bayer8(x + (offset.x * 8.0) as usize, y + (offset.y * 8.0) as usize)
+	} else {
+		blue_noise.fetch(
+			x + (offset.x * blue_noise.width as f32) as usize,
+			y + (offset.y * blue_noise.height as f32) as usize,
+		)
+	};
+	let noise = (noise + frame_sequence(frame_index, GOLDEN_RATIO_STEP)).fract() * 2.0 - 1.0;
+	let triangular = noise.signum() * (1.0 - (1.0 - noise.abs()).sqrt());
+	color + uv::Vec3::broadcast(triangular / 255.0)
+}
+
+pub fn combine(
+	existing_color: uv::Vec3,
+	color_to_add: uv::Vec3,
+	combine_constant: f32,
+) -> uv::Vec3 {
+	(existing_color - color_to_add) * combine_constant + color_to_add
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+	let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+	t * t * (3.0 - 2.0 * t)
+}
+
+pub fn gt_tonemap(x: f32) -> f32 {
+	let m = 0.22; // linear section start
+	let a = 1.0; // contrast
+	let c = 1.33; // black brightness
+	let p = 1.0; // maximum brightness
+	let l = 0.4; // linear section length
+	let l0 = ((p - m) * l) / a; // 0.312
+	let s0 = m + l0; // 0.532
+	let s1 = m + a * l0; // 0.532
+	let c2 = (a * p) / (p - s1); // 2.13675213675
+	let linear = m + a * (x - m);
+	let toe = m * (x / m).powf(c);
+	let shoulder = p - (p - s1) * (-c2 * (x - s0) / p).exp();
+	let w0 = 1.0 - smoothstep(0.0, m, x);
+	let w2 = if x < m + l { 0.0 } else { 1.0 };
+	let w1 = 1.0 - w0 - w2;
+	toe * w0 + linear * w1 + shoulder * w2
+}
+
+pub fn gt_tonemap_rgb(x: uv::Vec3) -> uv::Vec3 {
+	uv::Vec3::new(gt_tonemap(x.x), gt_tonemap(x.y), gt_tonemap(x.z))
+}
+
+pub fn reinhard(x: uv::Vec3) -> uv::Vec3 {
+	x / (uv::Vec3::one() + x)
+}
+
+pub fn reinhard_extended(x: uv::Vec3, white_point: f32) -> uv::Vec3 {
+	x * (uv::Vec3::one() + x / (white_point * white_point)) / (uv::Vec3::one() + x)
+}
+
+/// Column major, like the GLSL `mat3` constructor.
+fn mat3(columns: [f32; 9]) -> uv::Mat3 {
+	uv::Mat3::new(
+		uv::Vec3::new(columns[0], columns[1], columns[2]),
+		uv::Vec3::new(columns[3], columns[4], columns[5]),
+		uv::Vec3::new(columns[6], columns[7], columns[8]),
+	)
+}
+
+pub fn aces_fitted(color: uv::Vec3) -> uv::Vec3 {
+	let input = mat3([
+		0.59719, 0.07600, 0.02840, 0.35458, 0.90834, 0.13383, 0.04823, 0.01566, 0.83777,
+	]);
+	let output = mat3([
+		1.60475, -0.10208, -0.00327, -0.53108, 1.10813, -0.07276, -0.07367, -0.00605, 1.07602,
+	]);
+
+	let v = input * color;
+	let a = v * (v + uv::Vec3::broadcast(0.0245786)) - uv::Vec3::broadcast(0.000090537);
+	let b = v * (v * 0.983729 + uv::Vec3::broadcast(0.432951)) + uv::Vec3::broadcast(0.238081);
+	(output * (a / b)).clamped(uv::Vec3::zero(), uv::Vec3::one())
+}
+
+pub fn agx(color: uv::Vec3) -> uv::Vec3 {
+	let inset = mat3([
+		0.84247905,
+		0.042328242,
+		0.042375654,
+		0.0784336,
+		0.87846863,
+		0.0784336,
+		0.079223745,
+		0.07916613,
+		0.879143,
+	]);
+	let outset = mat3([
+		1.196879,
+		-0.052896854,
+		-0.052971635,
+		-0.09802088,
+		1.1519032,
+		-0.09804345,
+		-0.09902974,
+		-0.098961174,
+		1.1510737,
+	]);
+	let min_ev = -12.47393f32;
+	let max_ev = 4.026069f32;
+
+	let contrast = |x: f32| {
+		let x2 = x * x;
+		let x4 = x2 * x2;
+		15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+			- 0.00232
+	};
+	let encode = |x: f32| {
+		let ev = x.max(1.0e-10).log2().max(min_ev).min(max_ev);
+		contrast((ev - min_ev) / (max_ev - min_ev))
+	};
+
+	let color = inset * color;
+	let color = outset * uv::Vec3::new(encode(color.x), encode(color.y), encode(color.z));
+	uv::Vec3::new(
+		color.x.max(0.0).powf(2.2),
+		color.y.max(0.0).powf(2.2),
+		color.z.max(0.0).powf(2.2),
+	)
+}
+
+/// `Tonemap` of `bloom.comp`, `tonemapper` holds a `bloom::Tonemapper`.
+pub fn tonemap(color: uv::Vec3, tonemapper: u32, white_point: f32) -> uv::Vec3 {
+	match tonemapper {
+		t if t == Tonemapper::Reinhard as u32 => reinhard(color),
+		t if t == Tonemapper::ReinhardExtended as u32 => reinhard_extended(color, white_point),
+		t if t == Tonemapper::AcesFitted as u32 => aces_fitted(color),
+		t if t == Tonemapper::GranTurismo as u32 => gt_tonemap_rgb(color),
+		t if t == Tonemapper::AgX as u32 => agx(color),
+		_ => color,
+	}
+}
+
+/// `MAX_PENDING_TASKS` of `bloom_spd.comp`.
+const SPD_MAX_PENDING_TASKS: usize = 64;
+
+/// `BOX13_TAPS` of `bloom_spd.comp`, the taps of `downsample_box13` in half texels.
+const BOX13_TAPS: [(i64, i64); 13] = [
+	(0, 0),
+	(-1, -1),
+	(-1, 1),
+	(1, 1),
+	(1, -1),
+	(-2, -2),
+	(-2, 0),
+	(0, 2),
+	(2, 2),
+	(2, 2),
+	(2, 0),
+	(-2, -2),
+	(0, -2),
+];
+
+/// `Box13` of `bloom_spd.comp`, the weights of `downsample_box13` over the samples of
+/// `BOX13_TAPS`.
+fn box13(samples: &[uv::Vec3; 13]) -> uv::Vec3 {
+	let mut result = (samples[1] + samples[2] + samples[3] + samples[4]) * 0.5;
+	result += (samples[5] + samples[6] + samples[0] + samples[12]) * 0.125;
+	result += (samples[6] + samples[7] + samples[8] + samples[0]) * 0.125;
+	result += (samples[0] + samples[8] + samples[9] + samples[10]) * 0.125;
+	result += (samples[12] + samples[0] + samples[10] + samples[11]) * 0.125;
+	result * 0.25
+}
+
+/// The ping, pong and upsample mip chains of a `BloomPass`, filled on the CPU.
+pub struct BloomReference {
+	pub(crate) parameters: BloomParameters,
+	/// Value of `exposure_data.exposure`, applied to the HDR input.
+	pub exposure: f32,
+	/// Sampled like `lens_dirt_texture`, `None` is the neutral black texture.
+	pub lens_dirt: Option<Texture>,
+	/// Color grading of `MODE_APPLY`, `None` skips it like an unloaded `color_lut`.
+	pub color_lut: Option<CubeLut>,
+	/// Sampled like `blue_noise_texture`.
+	pub blue_noise: BlueNoise,
+	/// Value of `push.frame_index`, which moves the dither pattern.
+	pub frame_index: u32,
+	pub mip_count: usize,
+	pub images: Vec<Texture>,
+	pub algorithm: BloomAlgorithm,
+	/// Kernel spectrum of `BloomAlgorithm::Fft`, see `bloom_fft::kernel_spectrum_rgb`.
+	pub fft_spectrum: Vec<fft::Complex>,
+	/// Replaces the ping and pong dispatches by `single_pass_downsample`, comparing
+	/// `run` with and without it gives the error of the single pass downsampler.
+	pub single_pass_downsample: bool,
+}
+
+impl BloomReference {
+	pub fn new(settings: &BloomSettings, width: usize, height: usize) -> BloomReference {
+		let extent = vk::Extent2D::builder()
+			.width(width as u32)
+			.height(height as u32)
+			.build();
+		let mip_count = bloom::clamp_mip_count(settings.mip_count, extent);
+		let base_extent = vk::Extent2D::builder()
+			.width(extent.width / 2)
+			.height(extent.height / 2)
+			.build();
+		let images = (0..3)
+			.map(|_| Texture::new(width / 2, height / 2, mip_count, AddressMode::ClampToEdge))
+			.collect();
+		let fft_spectrum = if settings.algorithm == BloomAlgorithm::Fft {
+			let (kernel, size) = bloom_fft::default_kernel();
+			bloom_fft::kernel_spectrum_rgb(&kernel, size, size)
+		} else {
+			Vec::new()
+		};
+		BloomReference {
+			parameters: BloomParameters::new(settings),
+			exposure: 1.0,
+			lens_dirt: None,
+			color_lut: None,
+			blue_noise: BlueNoise::generate(blue_noise::BLUE_NOISE_SIZE),
+			frame_index: 0,
+			mip_count,
+			images,
+			algorithm: settings.algorithm,
+			fft_spectrum,
+			single_pass_downsample: settings.uses_single_pass_downsample()
+				&& bloom_spd::SinglePassDownsampler::supports(base_extent, mip_count),
+		}
+	}
+
+	/// Runs the whole `bloom::bloom_steps` over `input` and returns the tonemapped,
+	/// gamma corrected output.
+	pub fn run(&mut self, input: &RgbaImage) -> RgbaImage {
+		let input = Texture::from_image(input.clone(), AddressMode::ClampToBorder);
+		let mut output = RgbaImage::new(input.levels[0].width, input.levels[0].height);
+		for step in bloom::bloom_steps(self.mip_count, self.algorithm, self.single_pass_downsample)
+		{
+			let bloom_dispatch = match step {
+				BloomStep::Dispatch(bloom_dispatch) => bloom_dispatch,
+				BloomStep::FftConvolution => {
+					self.images[2].levels[0] = self.fft_convolution();
+					continue;
+				}
+				BloomStep::SinglePassDownsample => {
+					self.single_pass_downsample();
+					continue;
+				}
+			};
+			let out_index = bloom_dispatch.output as usize;
+			if out_index == 3 * self.mip_count {
+				output = self.dispatch(bloom_dispatch, &input, output.width, output.height);
+			} else {
+				let (image, mip) = (out_index / self.mip_count, out_index % self.mip_count);
+				let level = &self.images[image].levels[mip];
+				let result = self.dispatch(bloom_dispatch, &input, level.width, level.height);
+				self.images[image].levels[mip] = result;
+			}
+		}
+		output
+	}
+
+	/// `bloom_spd.comp`, running its workgroups one after the other.
+	pub fn single_pass_downsample(&mut self) {
+		let level = &self.images[0].levels[1];
+		let (tiles_x, tiles_y) = (
+			bloom_spd::tile_count(level.width as u32) as usize,
+			bloom_spd::tile_count(level.height as u32) as usize,
+		);
+		let workgroups: Vec<(usize, usize)> = (0..tiles_y)
+			.flat_map(|y| (0..tiles_x).map(move |x| (x, y)))
+			.collect();
+		self.single_pass_downsample_workgroups(&workgroups);
+	}
+
+	/// `bloom_spd.comp` with the workgroups, one per tile of mip 1, run in the order of
+	/// `workgroups`. Returns the counters, all back to 0 once every tile is written.
+	pub fn single_pass_downsample_workgroups(&mut self, workgroups: &[(usize, usize)]) -> Vec<u32> {
+		let tile_counts: Vec<(usize, usize)> = self.images[0]
+			.levels
+			.iter()
+			.map(|level| {
+				(
+					bloom_spd::tile_count(level.width as u32) as usize,
+					bloom_spd::tile_count(level.height as u32) as usize,
+				)
+			})
+			.collect();
+		// `CounterIndex`
+		let counter_index = |mip: usize, tile: (usize, usize)| {
+			let offset: usize = tile_counts[2..mip].iter().map(|(x, y)| x * y).sum();
+			offset + tile.1 * tile_counts[mip].0 + tile.0
+		};
+		let counter_count: usize = tile_counts[2..].iter().map(|(x, y)| x * y).sum();
+		let mut counters = vec![0u32; counter_count];
+
+		for &workgroup in workgroups {
+			let mut pending_tasks = vec![(1, workgroup)];
+			while let Some((mip, tile)) = pending_tasks.pop() {
+				self.spd_downsample_tile(mip, tile);
+				if mip + 1 >= self.mip_count {
+					continue;
+				}
+
+				// `CompleteTile`
+				let next = mip + 1;
+				let (count_x, count_y) = tile_counts[mip];
+				let first_x = (tile.0 as i64 - 1).div_euclid(2).max(0) as usize;
+				let first_y = (tile.1 as i64 - 1).div_euclid(2).max(0) as usize;
+				let last_x = ((tile.0 + 1) >> 1).min(tile_counts[next].0 - 1);
+				let last_y = ((tile.1 + 1) >> 1).min(tile_counts[next].1 - 1);
+				// The tiles 2 * tile - 1 to 2 * tile + 2 of `mip` a tile of `next` reads
+				let read_tiles = |tile: usize, count: usize| {
+					let low = (2 * tile as i64 - 1).max(0);
+					let high = (2 * tile as i64 + 2).min(count as i64 - 1);
+					(high - low + 1) as u32
+				};
+				for y in first_y..=last_y {
+					for x in first_x..=last_x {
+						let required = read_tiles(x, count_x) * read_tiles(y, count_y);
+						let counter = &mut counters[counter_index(next, (x, y))];
+						*counter += 1;
+						if *counter == required {
+							*counter = 0;
+							pending_tasks.push((next, (x, y)));
+						}
+					}
+				}
+				assert!(pending_tasks.len() <= SPD_MAX_PENDING_TASKS);
+			}
+		}
+		counters
+	}
+
+	/// `DownsampleTile` of `bloom_spd.comp`: the ping texels of `tile` of `mip` and
+	/// their border, then its pong texels filtered from them.
+	fn spd_downsample_tile(&mut self, mip: usize, tile: (usize, usize)) {
+		let tile_size = bloom_spd::TILE_SIZE as usize;
+		let ping_size = tile_size + 2;
+		let origin = (tile.0 * tile_size, tile.1 * tile_size);
+		let (pong, chains) = self.images.split_at_mut(1);
+		let previous = &pong[0].levels[mip - 1];
+		let ping_level = &mut chains[0].levels[mip];
+		let (width, height) = (ping_level.width, ping_level.height);
+		let size = uv::Vec2::new(width as f32, height as f32);
+		let half_texel = uv::Vec2::new(0.5 / previous.width as f32, 0.5 / previous.height as f32);
+
+		// `DownsamplePing`
+		let mut ping = vec![uv::Vec3::zero(); ping_size * ping_size];
+		for local_y in 0..ping_size {
+			for local_x in 0..ping_size {
+				let x = (origin.0 + local_x) as i64 - 1;
+				let y = (origin.1 + local_y) as i64 - 1;
+				let texel = uv::Vec2::new(
+					x.clamp(0, width as i64 - 1) as f32,
+					y.clamp(0, height as i64 - 1) as f32,
+				);
+				let uv = (texel + uv::Vec2::broadcast(0.5)) / size;
+				let samples = BOX13_TAPS.map(|(tap_x, tap_y)| {
+					let offset = uv::Vec2::new(tap_x as f32, tap_y as f32) * half_texel;
+					previous.sample(uv + offset, AddressMode::ClampToEdge).xyz()
+				});
+				let color = box13(&samples);
+				ping[local_y * ping_size + local_x] = color;
+				// The border belongs to the neighbor tiles
+				let in_tile =
+					(1..=tile_size).contains(&local_x) && (1..=tile_size).contains(&local_y);
+				if in_tile && (x as usize) < width && (y as usize) < height {
+					ping_level.pixels[y as usize * width + x as usize] =
+						color.into_homogeneous_point();
+				}
+			}
+		}
+
+		// `FilterPong`, every tap lands on a texel center, edge or corner of the ping texels
+		let pong_level = &mut pong[0].levels[mip];
+		let load = |x: usize, y: usize| ping[y * ping_size + x];
+		for local_y in 0..tile_size {
+			for local_x in 0..tile_size {
+				let (x, y) = (origin.0 + local_x, origin.1 + local_y);
+				if x >= width || y >= height {
+					continue;
+				}
+				let samples = BOX13_TAPS.map(|(tap_x, tap_y)| {
+					let position_x = ((local_x + 1) * 2) as i64 + tap_x;
+					let position_y = ((local_y + 1) * 2) as i64 + tap_y;
+					let (low_x, high_x) =
+						((position_x >> 1) as usize, ((position_x + 1) >> 1) as usize);
+					let (low_y, high_y) =
+						((position_y >> 1) as usize, ((position_y + 1) >> 1) as usize);
+					(load(low_x, low_y)
+						+ load(high_x, low_y)
+						+ load(low_x, high_y)
+						+ load(high_x, high_y))
+						* 0.25
+				});
+				pong_level.pixels[y * width + x] = box13(&samples).into_homogeneous_point();
+			}
+		}
+	}
+
+	/// `bloom_fft.comp`, from the pong chain to mip 0 of the upsample chain.
+	pub fn fft_convolution(&self) -> RgbaImage {
+		assert_eq!(
+			self.fft_spectrum.len(),
+			3 * FFT_SIZE * FFT_SIZE,
+			"The FFT kernel spectrum isn't set."
+		);
+		let prefiltered = &self.images[0];
+		let (width, height) = (
+			self.images[2].levels[0].width,
+			self.images[2].levels[0].height,
+		);
+		let output_size = uv::Vec2::new(width as f32, height as f32);
+		let scale = width.max(height) as f32 / (FFT_SIZE / 2) as f32;
+
+		// Load
+		let mut planes = vec![vec![0.0; FFT_SIZE * FFT_SIZE]; 3];
+		for y in 0..FFT_SIZE {
+			for x in 0..FFT_SIZE {
+				let uv = (uv::Vec2::new(x as f32, y as f32) + uv::Vec2::broadcast(0.5)) * scale
+					/ output_size;
+				if uv.x <= 1.0 && uv.y <= 1.0 {
+					let color = prefiltered.sample_lod_linear(uv, scale.log2().max(0.0));
+					planes[0][y * FFT_SIZE + x] = color.x;
+					planes[1][y * FFT_SIZE + x] = color.y;
+					planes[2][y * FFT_SIZE + x] = color.z;
+				}
+			}
+		}
+
+		// Convolve
+		let plane_size = FFT_SIZE * FFT_SIZE;
+		let planes: Vec<Vec<f32>> = planes
+			.iter()
+			.enumerate()
+			.map(|(c, plane)| {
+				fft::convolve(
+					plane,
+					&self.fft_spectrum[c * plane_size..(c + 1) * plane_size],
+					FFT_SIZE,
+				)
+			})
+			.collect();
+
+		// Store
+		let fetch = |x: i64, y: i64| {
+			if x < 0 || y < 0 || x >= FFT_SIZE as i64 || y >= FFT_SIZE as i64 {
+				return uv::Vec3::zero();
+			}
+			let index = y as usize * FFT_SIZE + x as usize;
+			uv::Vec3::new(planes[0][index], planes[1][index], planes[2][index])
+		};
+		RgbaImage::from_fn(width, height, |x, y| {
+			let position = (uv::Vec2::new(x as f32, y as f32) + uv::Vec2::broadcast(0.5)) / scale
+				- uv::Vec2::broadcast(0.5);
+			let base = uv::Vec2::new(position.x.floor(), position.y.floor());
+			let f = position - base;
+			let (bx, by) = (base.x as i64, base.y as i64);
+			let top = fetch(bx, by) + (fetch(bx + 1, by) - fetch(bx, by)) * f.x;
+			let bottom = fetch(bx, by + 1) + (fetch(bx + 1, by + 1) - fetch(bx, by + 1)) * f.x;
+			let color = (top + (bottom - top) * f.y).max_by_component(uv::Vec3::zero());
+			uv::Vec4::new(color.x, color.y, color.z, 1.0)
+		})
+	}
+
+	/// `MipTint` of `bloom.comp`.
+	fn mip_tint(&self, lod: u32) -> uv::Vec3 {
+		let tint = self.parameters.mip_tints[(lod as usize).min(BLOOM_MIP_TINT_COUNT - 1)];
+		tint.xyz() * tint.w
+	}
+
+	fn texture<'a>(&'a self, index: u32, input: &'a Texture) -> &'a Texture {
+		if index == 3 {
+			input
+		} else {
+			&self.images[index as usize]
+		}
+	}
+
+	fn downsample(&self, tex: &Texture, lod: u32, uv: uv::Vec2, texel_size: uv::Vec2) -> uv::Vec3 {
+		if self.algorithm == BloomAlgorithm::DualKawase {
+			downsample_dual_kawase(tex, lod, uv, texel_size)
+		} else if self.algorithm == BloomAlgorithm::Gaussian {
+			tex.sample_lod(uv, lod).xyz()
+		} else if self.parameters.shape == BloomShape::Anamorphic as u32 {
+			downsample_streak(tex, lod, uv, texel_size)
+		} else {
+			downsample_box13(tex, lod, uv, texel_size)
+		}
+	}
+
+	fn upsample(&self, tex: &Texture, lod: u32, uv: uv::Vec2, texel_size: uv::Vec2) -> uv::Vec3 {
+		if self.algorithm == BloomAlgorithm::DualKawase {
+			upsample_dual_kawase(tex, lod, uv, texel_size)
+		} else if self.algorithm == BloomAlgorithm::Gaussian {
+			tex.sample_lod(uv, lod).xyz()
+		} else if self.parameters.shape == BloomShape::Anamorphic as u32 {
+			upsample_streak(tex, lod, uv, texel_size, self.parameters.stretch)
+		} else {
+			upsample_tent9(tex, lod, uv, texel_size, 1.0)
+		}
+	}
+
+	/// One invocation of `bloom.comp` over a `width` x `height` output image.
+	pub fn dispatch(
+		&self,
+		bloom_dispatch: BloomDispatch,
+		input: &Texture,
+		width: usize,
+		height: usize,
+	) -> RgbaImage {
+		let BloomDispatch {
+			mode,
+			lod,
+			input: input_index,
+			bloom: bloom_index,
+			..
+		} = BloomDispatch::decode(bloom_dispatch.encode().expect("Invalid bloom dispatch."));
+		let input_texture = self.texture(input_index, input);
+		let bloom_texture = self.texture(bloom_index, input);
+		let img_size = uv::Vec2::new(width as f32, height as f32);
+		let combine_constant = self.parameters.combine_constant;
+
+		RgbaImage::from_fn(width, height, |x, y| {
+			let mut tex_coords = uv::Vec2::new(x as f32 / img_size.x, y as f32 / img_size.y);
+			tex_coords += (uv::Vec2::one() / img_size) * 0.5;
+
+			let tex_size = input_texture.size(lod);
+			let one = uv::Vec2::one();
+
+			match mode {
+				bloom::MODE_PREFILTER => {
+					let color = if self.parameters.karis_average != 0 {
+						downsample_box13_karis(
+							input_texture,
+							lod,
+							tex_coords,
+							one / tex_size,
+							self.exposure,
+						)
+					} else {
+						downsample_box13(input_texture, lod, tex_coords, one / tex_size)
+					} * self.exposure;
+					let mut color = prefilter(
+						color.into_homogeneous_point(),
+						self.parameters.params,
+						self.parameters.clamp_value,
+					);
+					color.w = 1.0;
+					color
+				}
+				bloom::MODE_DOWNSAMPLE => self
+					.downsample(input_texture, lod, tex_coords, one / tex_size)
+					.into_homogeneous_point(),
+				bloom::MODE_DEBUG => {
+					let mut debug_color = input_texture.sample_lod(tex_coords, lod).xyz();
+					if input_index == 3 {
+						debug_color *= self.exposure;
+					}
+					let mapped_color = tonemap(
+						debug_color,
+						self.parameters.tonemapper,
+						self.parameters.white_point,
+					);
+					let gamma = 1.0 / 2.2;
+					uv::Vec4::new(
+						mapped_color.x.powf(gamma),
+						mapped_color.y.powf(gamma),
+						mapped_color.z.powf(gamma),
+						1.0,
+					)
+				}
+				bloom::MODE_BLUR_HORIZONTAL => gaussian_blur9(
+					input_texture,
+					lod,
+					tex_coords,
+					uv::Vec2::new(1.0 / tex_size.x, 0.0),
+				)
+				.into_homogeneous_point(),
+				bloom::MODE_BLUR_VERTICAL => gaussian_blur9(
+					input_texture,
+					lod,
+					tex_coords,
+					uv::Vec2::new(0.0, 1.0 / tex_size.y),
+				)
+				.into_homogeneous_point(),
+				bloom::MODE_UPSAMPLE_FIRST | bloom::MODE_UPSAMPLE => {
+					let upsample_texture = if mode == bloom::MODE_UPSAMPLE_FIRST {
+						input_texture
+					} else {
+						bloom_texture
+					};
+					let bloom_tex_size = upsample_texture.size(lod + 1);
+					let mut upsampled =
+						self.upsample(upsample_texture, lod + 1, tex_coords, one / bloom_tex_size);
+					if mode == bloom::MODE_UPSAMPLE_FIRST {
+						upsampled *= self.mip_tint(lod + 1);
+					}
+					let existing =
+						input_texture.sample_lod(tex_coords, lod).xyz() * self.mip_tint(lod);
+					combine(existing, upsampled, combine_constant).into_homogeneous_point()
+				}
+				bloom::MODE_APPLY => {
+					let bloom_tex_size = bloom_texture.size(lod);
+					let mut upsampled =
+						self.upsample(bloom_texture, lod, tex_coords, one / bloom_tex_size);
+					if self.parameters.flare_intensity > 0.0 {
+						let flare_mip = self.parameters.flare_mip.min(self.mip_count as u32 - 1);
+						upsampled +=
+							lens_flare(&self.images[0], flare_mip, tex_coords, &self.parameters);
+					}
+					if let Some(lens_dirt) = &self.lens_dirt {
+						let dirt = lens_dirt.sample_lod(tex_coords, 0).xyz();
+						upsampled += upsampled * dirt * self.parameters.lens_dirt_intensity;
+					}
+					let existing = input_texture.sample_lod(tex_coords, 0).xyz() * self.exposure;
+					let combined_color = combine(
+						existing,
+						upsampled * self.parameters.intensity,
+						combine_constant,
+					);
+					let mapped_color = tonemap(
+						combined_color,
+						self.parameters.tonemapper,
+						self.parameters.white_point,
+					);
+					let gamma = 1.0 / 2.2;
+					let mut gamma_corrected_color = uv::Vec3::new(
+						mapped_color.x.powf(gamma),
+						mapped_color.y.powf(gamma),
+						mapped_color.z.powf(gamma),
+					);
+					let lut_strength = self.parameters.lut_strength;
+					if let Some(color_lut) = self.color_lut.as_ref().filter(|_| lut_strength > 0.0)
+					{
+						let graded = color_lut.sample(gamma_corrected_color);
+						gamma_corrected_color += (graded - gamma_corrected_color) * lut_strength;
+					}
+					if self.parameters.dither != DitherMode::Off as u32 {
+						gamma_corrected_color = dither(
+							gamma_corrected_color,
+							x,
+							y,
+							self.parameters.dither,
+							self.frame_index,
+							&self.blue_noise,
+						);
+					}
+					gamma_corrected_color.into_homogeneous_point()
+				}
+				_ => uv::Vec4::new(1.0, 0.0, 1.0, 1.0),
+			}
+		})
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const TOLERANCE: f32 = 1.0e-5;
+
+	fn assert_close(actual: f32, expected: f32) {
+		assert!(
+			(actual - expected).abs() <= TOLERANCE,
+			"{} isn't within {} of {}",
+			actual,
+			TOLERANCE,
+			expected
+		);
+	}
+
+	/// An 8x8 texture black but for `texel`, sampled at the center of texel (4, 4).
+	fn impulse(texel: (usize, usize)) -> (Texture, uv::Vec2) {
+		let image = RgbaImage::from_fn(8, 8, |x, y| {
+			if (x, y) == texel {
+				uv::Vec4::one()
+			} else {
+				uv::Vec4::zero()
+			}
+		});
+		(
+			Texture::from_image(image, AddressMode::ClampToEdge),
+			uv::Vec2::broadcast(4.5 / 8.0),
+		)
+	}
+
+	#[test]
+	fn quadratic_threshold_knee_curve() {
+		// threshold 1, knee 0.5
+		let curve = uv::Vec3::new(0.5, 1.0, 0.5);
+		let red = |r: f32| uv::Vec4::new(r, 0.0, 0.0, 1.0);
+
+		// Below threshold - knee
+		assert_eq!(quadratic_threshold(red(0.4), 1.0, curve), uv::Vec4::zero());
+		// In the knee, (1 - 0.5)² * 0.5
+		let color = quadratic_threshold(red(1.0), 1.0, curve);
+		assert_close(color.x, 0.125);
+		assert_close(color.w, 0.125);
+		// In the knee, (0.75 - 0.5)² * 0.5 / 0.75
+		assert_close(quadratic_threshold(red(0.75), 1.0, curve).x, 0.03125);
+		// Above the knee, linear past the threshold
+		assert_close(quadratic_threshold(red(3.0), 1.0, curve).x, 2.0);
+	}
+
+	#[test]
+	fn prefilter_clamps_before_the_threshold() {
+		let settings = BloomSettings {
+			threshold: 1.0,
+			knee: 0.5,
+			clamp_value: 4.0,
+			..Default::default()
+		};
+		let params = BloomParameters::new(&settings).params;
+		let color = prefilter(uv::Vec4::new(100.0, 0.0, 0.0, 1.0), params, 4.0);
+		assert_close(color.x, 3.0);
+	}
+
+	#[test]
+	fn downsample_box13_weights() {
+		let (tex, uv) = impulse((0, 0));
+		let texel_size = uv::Vec2::broadcast(2.0 / 8.0);
+		// The shader samples F and L, and I and J, at the same offset, so the bottom
+		// right corner is dropped and the top right one counted twice.
+		let weights = [
+			((0, 0), 0.125),
+			((-1, -1), 0.125),
+			((-1, 1), 0.125),
+			((1, 1), 0.125),
+			((1, -1), 0.125),
+			((-2, -2), 0.0625),
+			((-2, 0), 0.0625),
+			((0, 2), 0.03125),
+			((2, 2), 0.09375),
+			((2, 0), 0.0625),
+			((0, -2), 0.0625),
+			((-2, 2), 0.0),
+			((2, -2), 0.0),
+		];
+		let mut total = 0.0;
+		for ((dx, dy), weight) in weights {
+			let texel = ((4 + dx) as usize, (4 + dy) as usize);
+			let (impulse_tex, _) = impulse(texel);
+			assert_close(downsample_box13(&impulse_tex, 0, uv, texel_size).x, weight);
+			total += weight;
+		}
+		assert_close(total, 1.0);
+
+		let constant = Texture::from_image(
+			RgbaImage::from_fn(8, 8, |_, _| uv::Vec4::broadcast(0.7)),
+			AddressMode::ClampToEdge,
+		);
+		assert_close(downsample_box13(&constant, 0, uv, texel_size).y, 0.7);
+		assert_close(downsample_box13(&tex, 0, uv, texel_size).x, 0.0);
+	}
+
+	#[test]
+	fn downsample_box13_karis_matches_box13_on_a_constant() {
+		let constant = Texture::from_image(
+			RgbaImage::from_fn(8, 8, |_, _| uv::Vec4::broadcast(5.0)),
+			AddressMode::ClampToEdge,
+		);
+		let uv = uv::Vec2::broadcast(0.5);
+		let texel_size = uv::Vec2::broadcast(2.0 / 8.0);
+		assert_close(
+			downsample_box13_karis(&constant, 0, uv, texel_size, 2.0).x,
+			5.0,
+		);
+	}
+
+	#[test]
+	fn upsample_tent9_weights() {
+		let texel_size = uv::Vec2::broadcast(1.0 / 8.0);
+		for dy in -1i64..=1 {
+			for dx in -1i64..=1 {
+				let (tex, uv) = impulse(((4 + dx) as usize, (4 + dy) as usize));
+				let weight = match dx.abs() + dy.abs() {
+					0 => 4.0,
+					1 => 2.0,
+					_ => 1.0,
+				} / 16.0;
+				assert_close(upsample_tent9(&tex, 0, uv, texel_size, 1.0).z, weight);
+			}
+		}
+	}
+
+	#[test]
+	fn combine_lerps_toward_the_existing_color() {
+		let combined = combine(uv::Vec3::one(), uv::Vec3::broadcast(3.0), 0.25);
+		assert_close(combined.x, 2.5);
+	}
+
+	#[test]
+	fn gt_tonemap_known_values() {
+		assert_close(gt_tonemap(0.0), 0.0);
+		// Linear section
+		assert_close(gt_tonemap(0.3), 0.3);
+		assert_close(gt_tonemap(0.5), 0.5);
+		// Shoulder
+		assert_close(gt_tonemap(100.0), 1.0);
+		assert_eq!(reinhard(uv::Vec3::one()), uv::Vec3::broadcast(0.5));
+	}
+
+	#[test]
+	fn run_below_the_threshold_only_tonemaps() {
+		let settings = BloomSettings {
+			mip_count: 3,
+			..Default::default()
+		};
+		let mut reference = BloomReference::new(&settings, 16, 16);
+		let input = RgbaImage::from_fn(16, 16, |_, _| uv::Vec4::new(0.5, 0.5, 0.5, 1.0));
+		let output = reference.run(&input);
+
+		// No bloom, so the apply pass only scales the input by the combine constant
+		let expected = gt_tonemap(0.5 * settings.combine_constant).powf(1.0 / 2.2);
+		let expected = RgbaImage::from_fn(16, 16, |_, _| {
+			uv::Vec4::new(expected, expected, expected, 1.0)
+		});
+		assert!(output.max_difference(&expected) <= TOLERANCE);
+	}
+
+	/// Mostly above the default threshold, so the chains aren't black.
+	fn pattern(width: usize, height: usize) -> RgbaImage {
+		RgbaImage::from_fn(width, height, |x, y| {
+			let value = ((x * 37 + y * 11) % 17) as f32 * 0.5;
+			uv::Vec4::new(value, value * 0.5, 2.0, 1.0)
+		})
+	}
+
+	fn assert_close_rgb(actual: uv::Vec4, expected: uv::Vec4, tolerance: f32, what: &str) {
+		let difference = (actual.xyz() - expected.xyz()).abs();
+		assert!(
+			difference.x.max(difference.y).max(difference.z) <= tolerance,
+			"{}: {:?} isn't within {} of {:?}",
+			what,
+			actual,
+			tolerance,
+			expected
+		);
+	}
+
+	#[test]
+	fn dual_kawase_downsamples_each_mip_once() {
+		let mip_count = 4;
+		let settings = BloomSettings {
+			mip_count: mip_count as u32,
+			algorithm: BloomAlgorithm::DualKawase,
+			..Default::default()
+		};
+		let downsamples = bloom::bloom_steps(mip_count, BloomAlgorithm::DualKawase, false)
+			.into_iter()
+			.filter(|step| {
+				matches!(step, BloomStep::Dispatch(bloom_dispatch) if bloom_dispatch.mode == bloom::MODE_DOWNSAMPLE)
+			})
+			.count();
+		assert_eq!(downsamples, mip_count - 1);
+
+		let mut reference = BloomReference::new(&settings, 64, 32);
+		reference.run(&pattern(64, 32));
+
+		// Every texel of a mip sits on the corner shared by 2x2 texels of the previous
+		// one: the center box counts 4 times, the 4 diagonal boxes once.
+		let mode = AddressMode::ClampToEdge;
+		for mip in 1..mip_count {
+			let previous = &reference.images[0].levels[mip - 1];
+			let corner_box = |x: i64, y: i64| {
+				(previous.texel(x - 1, y - 1, mode)
+					+ previous.texel(x, y - 1, mode)
+					+ previous.texel(x - 1, y, mode)
+					+ previous.texel(x, y, mode))
+					* 0.25
+			};
+			let level = &reference.images[0].levels[mip];
+			for y in 0..level.height {
+				for x in 0..level.width {
+					let (cx, cy) = (x as i64 * 2 + 1, y as i64 * 2 + 1);
+					let expected = (corner_box(cx, cy) * 4.0
+						+ corner_box(cx - 1, cy - 1)
+						+ corner_box(cx + 1, cy + 1)
+						+ corner_box(cx + 1, cy - 1)
+						+ corner_box(cx - 1, cy + 1))
+						* (1.0 / 8.0);
+					assert_close_rgb(
+						level.get(x, y),
+						expected,
+						TOLERANCE,
+						&format!("Texel ({}, {}) of mip {}", x, y, mip),
+					);
+				}
+			}
+		}
+
+		// No ping pass
+		for level in reference.images[1].levels.iter() {
+			assert_eq!(
+				level.max_difference(&RgbaImage::new(level.width, level.height)),
+				0.0
+			);
+		}
+	}
+
+	#[test]
+	fn gaussian_blurs_each_mip_with_the_9_tap_kernel() {
+		// The binomial weights the 5 bilinear fetches of `gaussian_blur9` add up to
+		const WEIGHTS: [f32; 5] = [0.22702703, 0.19459459, 0.12162162, 0.05405405, 0.01621622];
+
+		let mip_count = 3;
+		let settings = BloomSettings {
+			mip_count: mip_count as u32,
+			algorithm: BloomAlgorithm::Gaussian,
+			..Default::default()
+		};
+		let mut reference = BloomReference::new(&settings, 64, 32);
+		reference.run(&pattern(64, 32));
+
+		// The ping chain holds the downsampled mips, the pong chain the blurred ones
+		let mode = AddressMode::ClampToEdge;
+		for mip in 0..mip_count {
+			let downsampled = &reference.images[1].levels[mip];
+			let blurred = &reference.images[0].levels[mip];
+			for y in 0..blurred.height {
+				for x in 0..blurred.width {
+					let mut expected = uv::Vec4::zero();
+					for j in -4i64..=4 {
+						for i in -4i64..=4 {
+							let weight = WEIGHTS[i.unsigned_abs() as usize]
+								* WEIGHTS[j.unsigned_abs() as usize];
+							expected +=
+								downsampled.texel(x as i64 + i, y as i64 + j, mode) * weight;
+						}
+					}
+					assert_close_rgb(
+						blurred.get(x, y),
+						expected,
+						1.0e-4,
+						&format!("Texel ({}, {}) of mip {}", x, y, mip),
+					);
+				}
+			}
+		}
+	}
+
+	/// The single pass downsampler computes the ping and pong mips of the
+	/// `BloomShape::Symmetric` chain tile by tile, so in fp32 they only differ by the
+	/// order of the additions. On the GPU it also rounds the ping texels to rgba16f in
+	/// shared memory, like the chain stores them, and filters mips 1 and up with its
+	/// own bilinear fetch.
+	#[test]
+	fn single_pass_downsample_matches_the_symmetric_chain() {
+		// Odd mip sizes, several tiles per mip and partial tiles at the border
+		for (width, height, mip_count) in [(100, 60, 5), (300, 170, 6), (262, 258, 8)] {
+			let settings = BloomSettings {
+				mip_count,
+				single_pass_downsample: true,
+				..Default::default()
+			};
+			let input = RgbaImage::from_fn(width, height, |x, y| {
+				let value = ((x * 37 + y * 11) % 17) as f32 * 0.5;
+				uv::Vec4::new(value, value * 0.5, 2.0, 1.0)
+			});
+
+			let mut single_pass = BloomReference::new(&settings, width, height);
+			assert!(single_pass.single_pass_downsample);
+			let single_pass_output = single_pass.run(&input);
+
+			let mut chain = BloomReference::new(&settings, width, height);
+			chain.single_pass_downsample = false;
+			let chain_output = chain.run(&input);
+
+			for image in 0..2 {
+				for (mip, (a, b)) in single_pass.images[image]
+					.levels
+					.iter()
+					.zip(chain.images[image].levels.iter())
+					.enumerate()
+					.skip(image)
+				{
+					let difference = a.max_difference(b);
+					assert!(
+						difference <= TOLERANCE,
+						"Mip {} of chain {} of {}x{} differs by {}",
+						mip,
+						image,
+						width,
+						height,
+						difference
+					);
+				}
+			}
+			assert!(single_pass_output.max_difference(&chain_output) <= TOLERANCE);
+		}
+	}
+
+	/// Whatever the order the workgroups run in, a tile is only downsampled once the
+	/// tiles it reads are written, and every counter is reset.
+	#[test]
+	fn single_pass_downsample_is_independent_of_the_workgroup_order() {
+		let (width, height) = (520, 390);
+		let settings = BloomSettings {
+			mip_count: 7,
+			single_pass_downsample: true,
+			..Default::default()
+		};
+		let input = RgbaImage::from_fn(width, height, |x, y| {
+			let value = ((x * 13 + y * 29) % 23) as f32 * 0.25;
+			uv::Vec4::new(value, 1.0, value * 0.5, 1.0)
+		});
+		let mut chain = BloomReference::new(&settings, width, height);
+		chain.single_pass_downsample = false;
+		chain.run(&input);
+
+		let level = &chain.images[0].levels[1];
+		let (tiles_x, tiles_y) = (
+			bloom_spd::tile_count(level.width as u32) as usize,
+			bloom_spd::tile_count(level.height as u32) as usize,
+		);
+		let workgroups: Vec<(usize, usize)> = (0..tiles_y)
+			.flat_map(|y| (0..tiles_x).map(move |x| (x, y)))
+			.collect();
+		assert!(workgroups.len() > 4);
+
+		let orders = [
+			workgroups.iter().rev().copied().collect::<Vec<_>>(),
+			// Columns first
+			(0..tiles_x)
+				.flat_map(|x| (0..tiles_y).map(move |y| (x, y)))
+				.collect(),
+		];
+		for order in orders {
+			let mut single_pass = BloomReference::new(&settings, width, height);
+			single_pass.images[0].levels[0] = chain.images[0].levels[0].clone();
+			let counters = single_pass.single_pass_downsample_workgroups(&order);
+			assert!(counters.iter().all(|counter| *counter == 0));
+			for image in 0..2 {
+				for mip in 1..single_pass.mip_count {
+					let difference = single_pass.images[image].levels[mip]
+						.max_difference(&chain.images[image].levels[mip]);
+					assert!(
+						difference <= TOLERANCE,
+						"Mip {} of chain {} differs by {}",
+						mip,
+						image,
+						difference
+					);
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn single_pass_downsample_falls_back_to_the_chain() {
+		let settings = BloomSettings {
+			shape: BloomShape::Anamorphic,
+			single_pass_downsample: true,
+			..Default::default()
+		};
+		assert!(!BloomReference::new(&settings, 64, 64).single_pass_downsample);
+		for algorithm in [BloomAlgorithm::DualKawase, BloomAlgorithm::Gaussian] {
+			let settings = BloomSettings {
+				algorithm,
+				single_pass_downsample: true,
+				..Default::default()
+			};
+			assert!(!BloomReference::new(&settings, 64, 64).single_pass_downsample);
+		}
+	}
+}
@@ -0,0 +1,214 @@
+use std::mem::size_of;
+
+use ash::vk;
+use gpu_alloc::UsageFlags;
+
+use crate::vulkan_engine::{
+	self, buffer, descriptor, image, pipeline, render_graph, shader_module,
+};
+
+/// Side of the tile of a mip written by one task of a workgroup, must match
+/// `TILE_SIZE` in `bloom_spd.comp`.
+pub const TILE_SIZE: u32 = 32;
+/// The counters are allocated for the tiles of the longest chain over the largest
+/// mip 0.
+pub const SPD_MAX_MIP_COUNT: usize = 13;
+pub const SPD_MAX_EXTENT: u32 = 4096;
+
+/// Builds mips 1 and up of the ping and pong chains from mip 0 of the pong chain in a
+/// single dispatch, in the style of FidelityFX SPD: a workgroup downsamples a 32x32
+/// tile of a mip in shared memory with `DownsampleBox13`, as the ping and pong
+/// dispatches of `BloomShape::Symmetric` do, and the one that completes the last tile
+/// a tile of the next mip reads (counted by an atomic) goes on with it.
+pub struct SinglePassDownsampler {
+	pipeline: pipeline::ComputePipeline,
+	/// One per frame in flight.
+	descriptors: Vec<descriptor::DescriptorSet>,
+	/// One per frame in flight, every counter is reset to 0 by the workgroup that
+	/// completes its tile.
+	counter_buffers: Vec<buffer::Buffer>,
+	mip_count: usize,
+}
+
+impl SinglePassDownsampler {
+	pub fn new(engine: &vulkan_engine::VulkanEngine, mip_count: usize) -> SinglePassDownsampler {
+		let counter_buffers: Vec<buffer::Buffer> = (0..engine.frames_in_flight)
+			.map(|_| {
+				let mut counter_buffer = buffer::Buffer::new(
+					&engine.device,
+					vk::BufferCreateFlags::empty(),
+					SinglePassDownsampler::counter_buffer_size(),
+					vk::BufferUsageFlags::STORAGE_BUFFER,
+					vk::SharingMode::EXCLUSIVE,
+					UsageFlags::UPLOAD | UsageFlags::HOST_ACCESS,
+				);
+				counter_buffer.write(0, vec![0u32; counter_count(SPD_MAX_EXTENT)]);
+				counter_buffer
+			})
+			.collect();
+
+		let descriptors: Vec<descriptor::DescriptorSet> = (0..engine.frames_in_flight)
+			.map(|_| SinglePassDownsampler::create_descriptor(engine, mip_count))
+			.collect();
+
+		let compute_module =
+			shader_module::ShaderModule::new(&engine.device, "shaders/spv/bloom_spd.spv", "main");
+		let pipeline = pipeline::ComputePipeline::builder()
+			.add_descriptor_set(&descriptors[0], 0)
+			.compute_module(&compute_module, vk::PipelineShaderStageCreateFlags::empty())
+			.add_specialization_constant(0, mip_count as u32)
+			.build(&engine.device);
+
+		SinglePassDownsampler {
+			pipeline,
+			descriptors,
+			counter_buffers,
+			mip_count,
+		}
+	}
+
+	fn create_descriptor(
+		engine: &vulkan_engine::VulkanEngine,
+		mip_count: usize,
+	) -> descriptor::DescriptorSet {
+		let descriptor = descriptor::DescriptorSet::new(
+			&engine.device,
+			vec![
+				(vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 1),
+				(vk::DescriptorType::STORAGE_IMAGE, 2 * mip_count as u32),
+				(vk::DescriptorType::STORAGE_BUFFER, 1),
+			],
+			1,
+			vec![
+				vk::DescriptorSetLayoutBinding::builder()
+					.binding(0)
+					.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+					.descriptor_count(1)
+					.stage_flags(vk::ShaderStageFlags::COMPUTE)
+					.build(),
+				vk::DescriptorSetLayoutBinding::builder()
+					.binding(1)
+					.descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
+					.descriptor_count(2 * mip_count as u32)
+					.stage_flags(vk::ShaderStageFlags::COMPUTE)
+					.build(),
+				vk::DescriptorSetLayoutBinding::builder()
+					.binding(2)
+					.descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+					.descriptor_count(1)
+					.stage_flags(vk::ShaderStageFlags::COMPUTE)
+					.build(),
+			],
+		);
+		descriptor
+	}
+
+	pub fn mip_count(&self) -> usize {
+		self.mip_count
+	}
+
+	fn counter_buffer_size() -> u64 {
+		(counter_count(SPD_MAX_EXTENT) * size_of::<u32>()) as u64
+	}
+
+	/// The counter buffer of `frame`, for the render graph.
+	pub fn counter_buffer(&self, frame: usize) -> render_graph::GraphBuffer {
+		render_graph::GraphBuffer {
+			buffer: *self.counter_buffers[frame].buffer,
+			size: SinglePassDownsampler::counter_buffer_size(),
+		}
+	}
+
+	/// Whether a chain of `mip_count` mips over a mip 0 of `extent` fits in one dispatch.
+	pub fn supports(extent: vk::Extent2D, mip_count: usize) -> bool {
+		mip_count <= SPD_MAX_MIP_COUNT
+			&& extent.width <= SPD_MAX_EXTENT
+			&& extent.height <= SPD_MAX_EXTENT
+	}
+
+	/// Writes mips 1 and up of `pong` and `ping` from mip 0 of `pong`, both must be in
+	/// `GENERAL` layout. Binds its own pipeline.
+	pub fn record(
+		&mut self,
+		engine: &vulkan_engine::VulkanEngine,
+		command_buffer: &vk::CommandBuffer,
+		pong: &image::Image,
+		ping: &image::Image,
+	) {
+		let descriptor = &self.descriptors[engine.current_frame];
+		descriptor.update_descriptor_set(
+			0,
+			0,
+			None,
+			Some(vec![vk::DescriptorImageInfo::builder()
+				.image_layout(vk::ImageLayout::GENERAL)
+				.image_view(pong.image_view)
+				.sampler(pong.image_sampler.unwrap())
+				.build()]),
+		);
+		descriptor.update_descriptor_set(
+			0,
+			1,
+			None,
+			Some(
+				pong.mip_image_views[..self.mip_count]
+					.iter()
+					.chain(&ping.mip_image_views[..self.mip_count])
+					.map(|view| {
+						vk::DescriptorImageInfo::builder()
+							.image_layout(vk::ImageLayout::GENERAL)
+							.image_view(*view)
+							.build()
+					})
+					.collect(),
+			),
+		);
+		descriptor.update_descriptor_set(
+			0,
+			2,
+			Some(vec![vk::DescriptorBufferInfo::builder()
+				.buffer(*self.counter_buffers[engine.current_frame].buffer)
+				.offset(0)
+				.range(SinglePassDownsampler::counter_buffer_size())
+				.build()]),
+			None,
+		);
+
+		// One workgroup per tile of mip 1
+		let group_x = (pong.extent.width >> 1).max(1).div_ceil(TILE_SIZE);
+		let group_y = (pong.extent.height >> 1).max(1).div_ceil(TILE_SIZE);
+
+		unsafe {
+			engine.device.device.cmd_bind_pipeline(
+				*command_buffer,
+				vk::PipelineBindPoint::COMPUTE,
+				self.pipeline.pipeline,
+			);
+			engine.device.device.cmd_bind_descriptor_sets(
+				*command_buffer,
+				vk::PipelineBindPoint::COMPUTE,
+				self.pipeline.pipeline_layout,
+				0,
+				&descriptor.descriptor_set,
+				&[],
+			);
+			engine
+				.device
+				.device
+				.cmd_dispatch(*command_buffer, group_x, group_y, 1);
+		};
+	}
+}
+
+/// Tiles of a mip of `size` texels, along one axis.
+pub fn tile_count(size: u32) -> u32 {
+	size.div_ceil(TILE_SIZE)
+}
+
+/// Counters of the tiles of mips 2 and up of a chain over a mip 0 of `extent` texels,
+/// as `CounterIndex` in `bloom_spd.comp` lays them out.
+pub fn counter_count(extent: u32) -> usize {
+	(2..SPD_MAX_MIP_COUNT)
+		.map(|mip| tile_count((extent >> mip).max(1)).pow(2) as usize)
+		.sum()
+}
@@ -0,0 +1,205 @@
+//! Blue noise dither of `MODE_APPLY`, generated with the void and cluster method of
+//! Robert Ulichney ("The void-and-cluster method for dither array generation", 1993)
+//! or loaded from an image.
+
+use std::path::Path;
+
+use ash::vk;
+use gpu_alloc::UsageFlags;
+
+use crate::vulkan_engine::{self, image};
+
+/// Side of the generated texture, tiled over the output.
+pub const BLUE_NOISE_SIZE: usize = 64;
+/// Standard deviation, in pixels, of the gaussian measuring clusters and voids.
+const SIGMA: f32 = 1.5;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlueNoise {
+	pub width: usize,
+	pub height: usize,
+	/// One threshold per pixel, row by row.
+	pub values: Vec<u8>,
+}
+
+impl BlueNoise {
+	/// Tiles seamlessly, every value is used the same number of times.
+	pub fn generate(size: usize) -> BlueNoise {
+		let count = size * size;
+		let mut pattern = Pattern::new(size);
+
+		// Initial binary pattern: random points, then the tightest cluster moved to
+		// the largest void until it's the same point
+		let mut state = 0x2545_f491u32;
+		let mut ones = 0;
+		while ones < count / 10 {
+			state ^= state << 13;
+			state ^= state >> 17;
+			state ^= state << 5;
+			let index = state as usize % count;
+			if !pattern.ones[index] {
+				pattern.toggle(index);
+				ones += 1;
+			}
+		}
+		loop {
+			let cluster = pattern.tightest_cluster();
+			pattern.toggle(cluster);
+			let void = pattern.largest_void();
+			pattern.toggle(void);
+			if void == cluster {
+				break;
+			}
+		}
+
+		let mut ranks = vec![0; count];
+		// Ranks of the initial points, from the tightest cluster down
+		let mut removed = pattern.clone();
+		for rank in (0..ones).rev() {
+			let cluster = removed.tightest_cluster();
+			removed.toggle(cluster);
+			ranks[cluster] = rank;
+		}
+		// Ranks of the others, from the largest void up. Past half the pixels, the
+		// tightest cluster of zeros is also the largest void of ones.
+		for rank in ones..count {
+			let void = pattern.largest_void();
+			pattern.toggle(void);
+			ranks[void] = rank;
+		}
+
+		BlueNoise {
+			width: size,
+			height: size,
+			values: ranks
+				.into_iter()
+				.map(|rank| (rank * 256 / count) as u8)
+				.collect(),
+		}
+	}
+
+	/// Uses the luma of the image as thresholds.
+	pub fn load(path: &Path) -> Result<BlueNoise, ::image::ImageError> {
+		let noise = ::image::open(path)?.into_luma8();
+		Ok(BlueNoise {
+			width: noise.width() as usize,
+			height: noise.height() as usize,
+			values: noise.into_raw(),
+		})
+	}
+
+	/// Threshold in [0, 1] of `texelFetch(blue_noise_texture, ...).r`, wrapping
+	/// around the borders.
+	#[cfg(test)]
+	pub fn fetch(&self, x: usize, y: usize) -> f32 {
+		self.values[(y % self.height) * self.width + x % self.width] as f32 / 255.0
+	}
+
+	/// Uploads the values as a `R8_UNORM` image in `SHADER_READ_ONLY_OPTIMAL` layout,
+	/// read with `texelFetch`.
+	pub fn create_image(&self, engine: &vulkan_engine::VulkanEngine) -> image::Image {
+		let mut noise = image::Image::new(
+			&engine.device,
+			vk::ImageCreateFlags::empty(),
+			vk::ImageType::TYPE_2D,
+			vk::Format::R8_UNORM,
+			vk::Extent3D::builder()
+				.width(self.width as u32)
+				.height(self.height as u32)
+				.depth(1)
+				.build(),
+			1,
+			1,
+			vk::ImageTiling::OPTIMAL,
+			vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+			engine.device.queue_family_index,
+			vk::ImageLayout::UNDEFINED,
+			vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+			vk::ImageViewType::TYPE_2D,
+			vk::ImageAspectFlags::COLOR,
+			UsageFlags::FAST_DEVICE_ACCESS,
+		);
+		noise.set_sampler(
+			vk::Filter::NEAREST,
+			vk::Filter::NEAREST,
+			vk::SamplerMipmapMode::NEAREST,
+			vk::SamplerAddressMode::REPEAT,
+			vk::SamplerAddressMode::REPEAT,
+			vk::SamplerAddressMode::REPEAT,
+			0.0,
+			false,
+			1.0,
+			false,
+			vk::CompareOp::ALWAYS,
+			0.0,
+			0.0,
+			vk::BorderColor::FLOAT_OPAQUE_BLACK,
+		);
+		noise.write_to_vram(&engine.device, &engine.command_builder, self.values.clone());
+		noise
+	}
+}
+
+/// Binary pattern with the energy of every pixel: the sum of the gaussians of the
+/// ones around it, on a torus.
+#[derive(Clone)]
+struct Pattern {
+	size: usize,
+	/// Gaussian of each toroidal offset, indexed like the pixels.
+	kernel: Vec<f32>,
+	ones: Vec<bool>,
+	energy: Vec<f32>,
+}
+
+impl Pattern {
+	fn new(size: usize) -> Pattern {
+		let count = size * size;
+		let kernel = (0..count)
+			.map(|index| {
+				let dx = (index % size).min(size - index % size) as f32;
+				let dy = (index / size).min(size - index / size) as f32;
+				(-(dx * dx + dy * dy) / (2.0 * SIGMA * SIGMA)).exp()
+			})
+			.collect();
+		Pattern {
+			size,
+			kernel,
+			ones: vec![false; count],
+			energy: vec![0.0; count],
+		}
+	}
+
+	fn toggle(&mut self, index: usize) {
+		let sign = if self.ones[index] { -1.0 } else { 1.0 };
+		self.ones[index] = !self.ones[index];
+		let size = self.size;
+		let (x, y) = (index % size, index / size);
+		for (other, energy) in self.energy.iter_mut().enumerate() {
+			let dx = (other % size + size - x) % size;
+			let dy = (other / size + size - y) % size;
+			*energy += sign * self.kernel[dy * size + dx];
+		}
+	}
+
+	/// The one with the highest energy.
+	fn tightest_cluster(&self) -> usize {
+		self.extremum(true, |energy, best| energy > best)
+	}
+
+	/// The zero with the lowest energy.
+	fn largest_void(&self) -> usize {
+		self.extremum(false, |energy, best| energy < best)
+	}
+
+	#[allow(clippy::unnecessary_map_or)]
+	fn extremum(&self, one: bool, better: impl Fn(f32, f32) -> bool) -> usize {
+		let mut best = None;
+		for (index, energy) in self.energy.iter().enumerate() {
+			if self.ones[index] == one && best.map_or(true, |(_, best)| better(*energy, best)) {
+				best = Some((index, *energy));
+			}
+		}
+		best.expect("Failed to find a pixel in the blue noise pattern.")
+			.0
+	}
+}
@@ -0,0 +1,180 @@
+//! Physical camera exposure and photometric light units, after "Moving Frostbite to
+//! Physically Based Rendering" (Lagarde and de Rousiers, Siggraph 2014).
+
+use std::f32::consts::PI;
+
+/// Exposure of a physical camera, used by `exposure::ExposureMode::Camera`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Camera {
+	/// f-number of the aperture.
+	pub aperture: f32,
+	/// In seconds.
+	pub shutter_speed: f32,
+	/// Sensor sensitivity.
+	pub iso: f32,
+}
+
+impl Default for Camera {
+	/// A dim interior: f/1.4, 1/30 s, ISO 3200.
+	fn default() -> Self {
+		Camera {
+			aperture: 1.4,
+			shutter_speed: 1.0 / 30.0,
+			iso: 3200.0,
+		}
+	}
+}
+
+impl Camera {
+	/// Exposure value at ISO 100: log2(N² / t * 100 / S).
+	pub fn ev100(&self) -> f32 {
+		(self.aperture * self.aperture / self.shutter_speed * 100.0 / self.iso).log2()
+	}
+
+	/// Multiplier from a luminance in nits to the output of the sensor, with the
+	/// saturation based sensitivity: the luminance 1.2 * 2^EV100 saturates it.
+	pub fn exposure(&self) -> f32 {
+		1.0 / (1.2 * self.ev100().exp2())
+	}
+}
+
+/// Units of the light and emissive intensities, converted on the CPU so the shaders
+/// always work in `light_color` (the intensity of a point light) and emitted radiance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LightUnits {
+	/// Colors scaled by the intensities, as they are.
+	Arbitrary,
+	/// Lights in lumens and emissive surfaces in nits. The colors are normalized by
+	/// their luminance, so the intensity alone sets the brightness.
+	Photometric,
+}
+
+impl LightUnits {
+	pub const ALL: [LightUnits; 2] = [LightUnits::Arbitrary, LightUnits::Photometric];
+
+	pub fn name(self) -> &'static str {
+		match self {
+			LightUnits::Arbitrary => "Arbitrary",
+			LightUnits::Photometric => "Photometric (lm, nits)",
+		}
+	}
+
+	/// `light_color` of a point light. In photometric units the luminous power is
+	/// spread over the sphere: I = Φ / 4π candelas.
+	pub fn point_light_color(self, color: uv::Vec3, intensity: f32) -> uv::Vec3 {
+		match self {
+			LightUnits::Arbitrary => color * intensity,
+			LightUnits::Photometric => normalize_luminance(color) * (intensity / (4.0 * PI)),
+		}
+	}
+
+	/// `emissive_intensity` of a surface of `color`, the emitted luminance is
+	/// `intensity` nits in photometric units.
+	pub fn emissive_intensity(self, color: uv::Vec3, intensity: f32) -> f32 {
+		match self {
+			LightUnits::Arbitrary => intensity,
+			LightUnits::Photometric => {
+				let luminance = luminance(color);
+				if luminance > 0.0 {
+					intensity / luminance
+				} else {
+					0.0
+				}
+			}
+		}
+	}
+}
+
+/// Rec. 709 relative luminance.
+pub fn luminance(color: uv::Vec3) -> f32 {
+	color.dot(uv::Vec3::new(0.2126, 0.7152, 0.0722))
+}
+
+fn normalize_luminance(color: uv::Vec3) -> uv::Vec3 {
+	let luminance = luminance(color);
+	if luminance > 0.0 {
+		color / luminance
+	} else {
+		uv::Vec3::zero()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const TOLERANCE: f32 = 1.0e-6;
+
+	fn assert_close(actual: f32, expected: f32) {
+		assert!(
+			(actual - expected).abs() <= TOLERANCE,
+			"{} isn't within {} of {}",
+			actual,
+			TOLERANCE,
+			expected
+		);
+	}
+
+	#[test]
+	fn camera_exposure_of_the_reference_settings() {
+		// f/1, 1 s and ISO 100 define EV100 0
+		let camera = Camera {
+			aperture: 1.0,
+			shutter_speed: 1.0,
+			iso: 100.0,
+		};
+		assert_close(camera.ev100(), 0.0);
+		assert_close(camera.exposure(), 1.0 / 1.2);
+
+		// One stop less light per halving of the shutter speed
+		let camera = Camera {
+			shutter_speed: 0.5,
+			..camera
+		};
+		assert_close(camera.ev100(), 1.0);
+		assert_close(camera.exposure(), 1.0 / 2.4);
+	}
+
+	#[test]
+	fn photometric_point_light_is_in_candelas() {
+		let white = uv::Vec3::one();
+		let color = LightUnits::Photometric.point_light_color(white, 4.0 * PI);
+		for channel in [color.x, color.y, color.z] {
+			assert_close(channel, 1.0);
+		}
+		// Only the luminance of the color is normalized
+		let red = LightUnits::Photometric.point_light_color(uv::Vec3::new(2.0, 0.0, 0.0), 4.0 * PI);
+		assert_close(luminance(red), 1.0);
+
+		let color = LightUnits::Arbitrary.point_light_color(uv::Vec3::new(0.5, 1.0, 2.0), 3.0);
+		assert_eq!(color, uv::Vec3::new(1.5, 3.0, 6.0));
+	}
+
+	#[test]
+	fn photometric_emissive_intensity_is_in_nits() {
+		let white = uv::Vec3::one();
+		assert_close(
+			LightUnits::Photometric.emissive_intensity(white, 500.0),
+			500.0,
+		);
+		let gray = uv::Vec3::broadcast(0.5);
+		assert_close(
+			luminance(gray) * LightUnits::Photometric.emissive_intensity(gray, 500.0),
+			500.0,
+		);
+		assert_eq!(LightUnits::Arbitrary.emissive_intensity(gray, 3.0), 3.0);
+	}
+
+	#[test]
+	fn black_is_not_normalized_to_nan() {
+		let black = uv::Vec3::zero();
+		assert_eq!(
+			LightUnits::Photometric.point_light_color(black, 100.0),
+			black
+		);
+		assert_eq!(
+			LightUnits::Photometric.emissive_intensity(black, 100.0),
+			0.0
+		);
+	}
+}
@@ -0,0 +1,524 @@
+//! Adobe / Resolve `.cube` 3D LUTs, uploaded as a `TYPE_3D` image sampled by the
+//! apply pass of `bloom.comp`.
+
+use std::path::Path;
+
+use ash::vk;
+use gpu_alloc::UsageFlags;
+
+use crate::vulkan_engine::{self, image};
+
+/// Bounds of `LUT_3D_SIZE` in the Adobe specification.
+pub const MIN_LUT_SIZE: usize = 2;
+pub const MAX_LUT_SIZE: usize = 256;
+
+#[derive(Debug)]
+pub enum CubeError {
+	Io(std::io::Error),
+	/// `LUT_1D_SIZE`, only 3D LUTs are supported.
+	Unsupported1D {
+		line: usize,
+	},
+	MissingSize,
+	InvalidSize {
+		line: usize,
+	},
+	DuplicateKeyword {
+		line: usize,
+		keyword: String,
+	},
+	UnknownKeyword {
+		line: usize,
+		keyword: String,
+	},
+	/// A keyword after the first entry of the table.
+	KeywordAfterTable {
+		line: usize,
+		keyword: String,
+	},
+	/// Not a finite float.
+	InvalidNumber {
+		line: usize,
+		value: String,
+	},
+	/// A table entry or a domain without exactly 3 values.
+	WrongValueCount {
+		line: usize,
+		count: usize,
+	},
+	WrongEntryCount {
+		expected: usize,
+		found: usize,
+	},
+	/// A `DOMAIN_MAX` channel not greater than its `DOMAIN_MIN`.
+	InvalidDomain,
+}
+
+impl std::fmt::Display for CubeError {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			CubeError::Io(error) => write!(f, "{}", error),
+			CubeError::Unsupported1D { line } => {
+				write!(f, "line {}: 1D LUTs aren't supported", line)
+			}
+			CubeError::MissingSize => write!(f, "missing LUT_3D_SIZE"),
+			CubeError::InvalidSize { line } => write!(
+				f,
+				"line {}: LUT_3D_SIZE must be an integer between {} and {}",
+				line, MIN_LUT_SIZE, MAX_LUT_SIZE
+			),
+			CubeError::DuplicateKeyword { line, keyword } => {
+				write!(f, "line {}: duplicate {}", line, keyword)
+			}
+			CubeError::UnknownKeyword { line, keyword } => {
+				write!(f, "line {}: unknown keyword {}", line, keyword)
+			}
+			CubeError::KeywordAfterTable { line, keyword } => {
+				write!(f, "line {}: {} after the first table entry", line, keyword)
+			}
+			CubeError::InvalidNumber { line, value } => {
+				write!(f, "line {}: {} isn't a finite number", line, value)
+			}
+			CubeError::WrongValueCount { line, count } => {
+				write!(f, "line {}: expected 3 values, found {}", line, count)
+			}
+			CubeError::WrongEntryCount { expected, found } => {
+				write!(f, "expected {} table entries, found {}", expected, found)
+			}
+			CubeError::InvalidDomain => write!(f, "DOMAIN_MAX must be greater than DOMAIN_MIN"),
+		}
+	}
+}
+
+impl std::error::Error for CubeError {}
+
+impl From<std::io::Error> for CubeError {
+	fn from(error: std::io::Error) -> Self {
+		CubeError::Io(error)
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CubeLut {
+	pub title: Option<String>,
+	/// Number of entries along each axis.
+	pub size: usize,
+	pub domain_min: [f32; 3],
+	pub domain_max: [f32; 3],
+	/// `size`³ rgb entries, red varies fastest, then green, then blue.
+	pub table: Vec<[f32; 3]>,
+}
+
+impl CubeLut {
+	pub fn identity(size: usize) -> CubeLut {
+		let scale = 1.0 / (size - 1) as f32;
+		let mut table = Vec::with_capacity(size * size * size);
+		for b in 0..size {
+			for g in 0..size {
+				for r in 0..size {
+					table.push([r as f32 * scale, g as f32 * scale, b as f32 * scale]);
+				}
+			}
+		}
+		CubeLut {
+			title: None,
+			size,
+			domain_min: [0.0; 3],
+			domain_max: [1.0; 3],
+			table,
+		}
+	}
+
+	pub fn load(path: &Path) -> Result<CubeLut, CubeError> {
+		CubeLut::parse(&std::fs::read_to_string(path)?)
+	}
+
+	pub fn parse(text: &str) -> Result<CubeLut, CubeError> {
+		let mut title = None;
+		let mut size = None;
+		let mut domain_min = None;
+		let mut domain_max = None;
+		let mut table = Vec::new();
+
+		for (index, line) in text.lines().enumerate() {
+			let line_number = index + 1;
+			let line = line.trim();
+			if line.is_empty() || line.starts_with('#') {
+				continue;
+			}
+			let mut words = line.split_whitespace();
+			let keyword = words.next().unwrap();
+
+			if !keyword.starts_with(|c: char| c.is_ascii_alphabetic()) {
+				table.push(parse_values(line, line_number)?);
+				continue;
+			}
+			if !table.is_empty() {
+				return Err(CubeError::KeywordAfterTable {
+					line: line_number,
+					keyword: keyword.to_string(),
+				});
+			}
+			let duplicate = || CubeError::DuplicateKeyword {
+				line: line_number,
+				keyword: keyword.to_string(),
+			};
+			match keyword {
+				"TITLE" => {
+					if title.is_some() {
+						return Err(duplicate());
+					}
+					let value = line[keyword.len()..].trim();
+					title = Some(value.trim_matches('"').to_string());
+				}
+				"LUT_3D_SIZE" => {
+					if size.is_some() {
+						return Err(duplicate());
+					}
+					let value = words
+						.next()
+						.and_then(|value| value.parse::<usize>().ok())
+						.filter(|value| (MIN_LUT_SIZE..=MAX_LUT_SIZE).contains(value));
+					match (value, words.next()) {
+						(Some(value), None) => size = Some(value),
+						_ => return Err(CubeError::InvalidSize { line: line_number }),
+					}
+				}
+				"LUT_1D_SIZE" => return Err(CubeError::Unsupported1D { line: line_number }),
+				"DOMAIN_MIN" | "DOMAIN_MAX" => {
+					let domain = if keyword == "DOMAIN_MIN" {
+						&mut domain_min
+					} else {
+						&mut domain_max
+					};
+					if domain.is_some() {
+						return Err(duplicate());
+					}
+					*domain = Some(parse_values(&line[keyword.len()..], line_number)?);
+				}
+				_ => {
+					return Err(CubeError::UnknownKeyword {
+						line: line_number,
+						keyword: keyword.to_string(),
+					})
+				}
+			}
+		}
+
+		let size = size.ok_or(CubeError::MissingSize)?;
+		let expected = size * size * size;
+		if table.len() != expected {
+			return Err(CubeError::WrongEntryCount {
+				expected,
+				found: table.len(),
+			});
+		}
+		let domain_min = domain_min.unwrap_or([0.0; 3]);
+		let domain_max = domain_max.unwrap_or([1.0; 3]);
+		if (0..3).any(|i| domain_max[i] <= domain_min[i]) {
+			return Err(CubeError::InvalidDomain);
+		}
+
+		Ok(CubeLut {
+			title,
+			size,
+			domain_min,
+			domain_max,
+			table,
+		})
+	}
+
+	#[cfg(test)]
+	fn entry(&self, r: usize, g: usize, b: usize) -> uv::Vec3 {
+		let [r, g, b] = self.table[(b * self.size + g) * self.size + r];
+		uv::Vec3::new(r, g, b)
+	}
+
+	/// Trilinear lookup, as the `LINEAR` sampler of the uploaded image does.
+	#[cfg(test)]
+	pub fn sample(&self, color: uv::Vec3) -> uv::Vec3 {
+		let [r, g, b] = self.domain_min;
+		let domain_min = uv::Vec3::new(r, g, b);
+		let [r, g, b] = self.domain_max;
+		let domain_max = uv::Vec3::new(r, g, b);
+		let position = ((color - domain_min) / (domain_max - domain_min))
+			.clamped(uv::Vec3::zero(), uv::Vec3::one())
+			* (self.size - 1) as f32;
+		let base = uv::Vec3::new(position.x.floor(), position.y.floor(), position.z.floor())
+			.min_by_component(uv::Vec3::broadcast((self.size - 2) as f32));
+		let f = position - base;
+		let (r, g, b) = (base.x as usize, base.y as usize, base.z as usize);
+
+		let lerp = |a: uv::Vec3, b: uv::Vec3, t: f32| a + (b - a) * t;
+		let plane = |b: usize| {
+			lerp(
+				lerp(self.entry(r, g, b), self.entry(r + 1, g, b), f.x),
+				lerp(self.entry(r, g + 1, b), self.entry(r + 1, g + 1, b), f.x),
+				f.y,
+			)
+		};
+		lerp(plane(b), plane(b + 1), f.z)
+	}
+
+	/// Uploads the table as a `R16G16B16A16_SFLOAT` 3D image in
+	/// `SHADER_READ_ONLY_OPTIMAL` layout, with a clamped linear sampler.
+	pub fn create_image(&self, engine: &vulkan_engine::VulkanEngine) -> image::Image {
+		let size = self.size as u32;
+		let mut lut = image::Image::new(
+			&engine.device,
+			vk::ImageCreateFlags::empty(),
+			vk::ImageType::TYPE_3D,
+			vk::Format::R16G16B16A16_SFLOAT,
+			vk::Extent3D::builder()
+				.width(size)
+				.height(size)
+				.depth(size)
+				.build(),
+			1,
+			1,
+			vk::ImageTiling::OPTIMAL,
+			vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST,
+			engine.device.queue_family_index,
+			vk::ImageLayout::UNDEFINED,
+			vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+			vk::ImageViewType::TYPE_3D,
+			vk::ImageAspectFlags::COLOR,
+			UsageFlags::FAST_DEVICE_ACCESS,
+		);
+		lut.set_sampler(
+			vk::Filter::LINEAR,
+			vk::Filter::LINEAR,
+			vk::SamplerMipmapMode::NEAREST,
+			vk::SamplerAddressMode::CLAMP_TO_EDGE,
+			vk::SamplerAddressMode::CLAMP_TO_EDGE,
+			vk::SamplerAddressMode::CLAMP_TO_EDGE,
+			0.0,
+			false,
+			1.0,
+			false,
+			vk::CompareOp::ALWAYS,
+			0.0,
+			0.0,
+			vk::BorderColor::FLOAT_OPAQUE_BLACK,
+		);
+		let one = f32_to_f16(1.0);
+		let texels: Vec<u16> = self
+			.table
+			.iter()
+			.flat_map(|[r, g, b]| [f32_to_f16(*r), f32_to_f16(*g), f32_to_f16(*b), one])
+			.collect();
+		lut.write_to_vram(&engine.device, &engine.command_builder, texels);
+		lut
+	}
+}
+
+/// Exactly 3 finite floats.
+fn parse_values(text: &str, line: usize) -> Result<[f32; 3], CubeError> {
+	let words: Vec<&str> = text.split_whitespace().collect();
+	if words.len() != 3 {
+		return Err(CubeError::WrongValueCount {
+			line,
+			count: words.len(),
+		});
+	}
+	let mut values = [0.0; 3];
+	for (value, word) in values.iter_mut().zip(words) {
+		*value = word
+			.parse::<f32>()
+			.ok()
+			.filter(|value| value.is_finite())
+			.ok_or_else(|| CubeError::InvalidNumber {
+				line,
+				value: word.to_string(),
+			})?;
+	}
+	Ok(values)
+}
+
+/// Rounds to the nearest half float, out of range values are clamped to the largest
+/// finite one.
+pub fn f32_to_f16(value: f32) -> u16 {
+	let sign = ((value.to_bits() >> 16) & 0x8000) as u16;
+	if value.is_nan() {
+		return sign | 0x7e00;
+	}
+	let value = value.abs().min(65504.0);
+	// Below the smallest normal half, multiples of 2^-24
+	if value < 6.103_515_6e-5 {
+		return sign | (value * 16_777_216.0).round() as u16;
+	}
+	let bits = value.to_bits();
+	let exponent = (bits >> 23) - 127 + 15;
+	let mantissa = bits & 0x7f_ffff;
+	let mut half = (exponent << 10) | (mantissa >> 13);
+	// Round half to even
+	let rest = mantissa & 0x1fff;
+	if rest > 0x1000 || (rest == 0x1000 && half & 1 == 1) {
+		half += 1;
+	}
+	sign | half as u16
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// The identity LUT of size 2, red varying fastest.
+	const IDENTITY_2: &str = "0 0 0\n1 0 0\n0 1 0\n1 1 0\n0 0 1\n1 0 1\n0 1 1\n1 1 1\n";
+
+	#[test]
+	fn parses_a_valid_lut() {
+		let text = format!(
+			"# comment\nTITLE \"Identity\"\nLUT_3D_SIZE 2\n\n{}",
+			IDENTITY_2
+		);
+		let lut = CubeLut::parse(&text).expect("Failed to parse the LUT.");
+		assert_eq!(
+			lut,
+			CubeLut {
+				title: Some("Identity".to_string()),
+				..CubeLut::identity(2)
+			}
+		);
+	}
+
+	#[test]
+	fn missing_size() {
+		let result = CubeLut::parse(IDENTITY_2);
+		assert!(
+			matches!(result, Err(CubeError::MissingSize)),
+			"{:?}",
+			result
+		);
+	}
+
+	#[test]
+	fn invalid_size() {
+		for size in ["1", "257", "two", "2 2"] {
+			let text = format!("LUT_3D_SIZE {}\n{}", size, IDENTITY_2);
+			let result = CubeLut::parse(&text);
+			assert!(
+				matches!(result, Err(CubeError::InvalidSize { line: 1 })),
+				"{:?}",
+				result
+			);
+		}
+	}
+
+	#[test]
+	fn wrong_entry_count() {
+		let text = format!("LUT_3D_SIZE 2\n{}1 1 1\n", IDENTITY_2);
+		let result = CubeLut::parse(&text);
+		assert!(
+			matches!(
+				result,
+				Err(CubeError::WrongEntryCount {
+					expected: 8,
+					found: 9
+				})
+			),
+			"{:?}",
+			result
+		);
+
+		let result = CubeLut::parse("LUT_3D_SIZE 2\n0 0 0\n");
+		assert!(
+			matches!(
+				result,
+				Err(CubeError::WrongEntryCount {
+					expected: 8,
+					found: 1
+				})
+			),
+			"{:?}",
+			result
+		);
+	}
+
+	#[test]
+	fn non_numeric_rows() {
+		let result = CubeLut::parse("LUT_3D_SIZE 2\n0 0 0\n1 x 0\n");
+		match result {
+			Err(CubeError::InvalidNumber { line, value }) => {
+				assert_eq!(line, 3);
+				assert_eq!(value, "x");
+			}
+			_ => panic!("Expected an invalid number, got {:?}", result),
+		}
+
+		let result = CubeLut::parse("LUT_3D_SIZE 2\n0 0 0\n1 nan 0\n");
+		assert!(
+			matches!(result, Err(CubeError::InvalidNumber { line: 3, .. })),
+			"{:?}",
+			result
+		);
+
+		let result = CubeLut::parse("LUT_3D_SIZE 2\n0 0\n");
+		assert!(
+			matches!(
+				result,
+				Err(CubeError::WrongValueCount { line: 2, count: 2 })
+			),
+			"{:?}",
+			result
+		);
+	}
+
+	#[test]
+	fn domain() {
+		let text = format!(
+			"LUT_3D_SIZE 2\nDOMAIN_MIN 0 0 0\nDOMAIN_MAX 2 2 2\n{}",
+			IDENTITY_2
+		);
+		let lut = CubeLut::parse(&text).expect("Failed to parse the LUT.");
+		assert_eq!(lut.domain_min, [0.0; 3]);
+		assert_eq!(lut.domain_max, [2.0; 3]);
+		// The domain is remapped to the table before the lookup
+		assert_eq!(
+			lut.sample(uv::Vec3::new(1.0, 2.0, 0.5)),
+			uv::Vec3::new(0.5, 1.0, 0.25)
+		);
+
+		let text = format!(
+			"LUT_3D_SIZE 2\nDOMAIN_MIN 0 0 1\nDOMAIN_MAX 1 1 1\n{}",
+			IDENTITY_2
+		);
+		let result = CubeLut::parse(&text);
+		assert!(
+			matches!(result, Err(CubeError::InvalidDomain)),
+			"{:?}",
+			result
+		);
+
+		let result = CubeLut::parse("LUT_3D_SIZE 2\nDOMAIN_MIN 0 0\n");
+		assert!(
+			matches!(
+				result,
+				Err(CubeError::WrongValueCount { line: 2, count: 2 })
+			),
+			"{:?}",
+			result
+		);
+
+		let text = format!(
+			"LUT_3D_SIZE 2\nDOMAIN_MIN 0 0 0\nDOMAIN_MIN 0 0 0\n{}",
+			IDENTITY_2
+		);
+		let result = CubeLut::parse(&text);
+		assert!(
+			matches!(result, Err(CubeError::DuplicateKeyword { line: 3, .. })),
+			"{:?}",
+			result
+		);
+	}
+
+	#[test]
+	fn keyword_after_table() {
+		let result = CubeLut::parse("0 0 0\nLUT_3D_SIZE 2\n");
+		assert!(
+			matches!(result, Err(CubeError::KeywordAfterTable { line: 2, .. })),
+			"{:?}",
+			result
+		);
+	}
+}
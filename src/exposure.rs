@@ -0,0 +1,335 @@
+use std::mem::size_of;
+
+use ash::vk;
+use gpu_alloc::UsageFlags;
+
+use crate::camera;
+use crate::vulkan_engine::{
+	self, buffer, descriptor, pipeline, push_constant, render_graph, shader_module,
+};
+
+/// Number of bins of the luminance histogram, bin 0 holds the black pixels.
+pub const HISTOGRAM_BIN_COUNT: usize = 256;
+pub const EXPOSURE_MODE_MANUAL: u32 = 0;
+pub const EXPOSURE_MODE_AUTO: u32 = 1;
+pub const EXPOSURE_MODE_CAMERA: u32 = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExposureMode {
+	/// Only the EV compensation is applied.
+	Manual = EXPOSURE_MODE_MANUAL as isize,
+	/// The average luminance is mapped to middle grey, then compensated.
+	Auto = EXPOSURE_MODE_AUTO as isize,
+	/// The exposure of the physical camera, then compensated. The scene is expected
+	/// in photometric units, see `camera::LightUnits`.
+	Camera = EXPOSURE_MODE_CAMERA as isize,
+}
+
+impl ExposureMode {
+	pub const ALL: [ExposureMode; 3] = [
+		ExposureMode::Manual,
+		ExposureMode::Auto,
+		ExposureMode::Camera,
+	];
+
+	pub fn name(self) -> &'static str {
+		match self {
+			ExposureMode::Manual => "Manual",
+			ExposureMode::Auto => "Auto",
+			ExposureMode::Camera => "Physical camera",
+		}
+	}
+}
+
+#[derive(Clone, Copy)]
+pub struct ExposureSettings {
+	pub mode: ExposureMode,
+	pub ev_compensation: f32,
+	/// Log2 luminance range of the histogram, the adapted luminance is clamped to it.
+	pub min_ev: f32,
+	pub max_ev: f32,
+	/// How fast the adapted luminance follows the scene, per second.
+	pub adaptation_speed: f32,
+	pub camera: camera::Camera,
+}
+
+impl Default for ExposureSettings {
+	fn default() -> Self {
+		ExposureSettings {
+			mode: ExposureMode::Manual,
+			ev_compensation: 0.0,
+			min_ev: -8.0,
+			max_ev: 8.0,
+			adaptation_speed: 1.5,
+			camera: camera::Camera::default(),
+		}
+	}
+}
+
+/// Mirror of the push constant of `luminance_histogram.comp` and
+/// `luminance_average.comp`.
+#[derive(Default, Clone, Copy)]
+#[repr(C)]
+struct ExposureConstant {
+	min_log_luminance: f32,
+	log_luminance_range: f32,
+	time_coefficient: f32,
+	pixel_count: u32,
+	ev_compensation: f32,
+	mode: u32,
+	camera_exposure: f32,
+}
+
+/// Mirror of the `exposure_buffer` storage buffer, also read by `bloom.comp`.
+#[derive(Clone, Copy)]
+#[repr(C)]
+pub struct ExposureData {
+	pub histogram: [u32; HISTOGRAM_BIN_COUNT],
+	pub average_luminance: f32,
+	pub exposure: f32,
+	padding: [f32; 2],
+}
+
+/// Builds a luminance histogram of the HDR image, then averages it into a temporally
+/// smoothed luminance and the exposure multiplier used by the bloom pass.
+pub struct Exposure {
+	pub settings: ExposureSettings,
+	histogram_pipeline: pipeline::ComputePipeline,
+	average_pipeline: pipeline::ComputePipeline,
+	push_constant: push_constant::PushConstant,
+	/// One per frame in flight, the input changes with the swapchain image.
+	descriptors: Vec<descriptor::DescriptorSet>,
+	/// Shared by the frames in flight, the adaptation carries over from one to the next.
+	/// The render graph orders its uses after those of the previous frames.
+	pub buffer: buffer::Buffer,
+	exposure_data: ExposureConstant,
+}
+
+impl Exposure {
+	pub fn new(engine: &vulkan_engine::VulkanEngine, settings: ExposureSettings) -> Exposure {
+		let mut buffer = buffer::Buffer::new(
+			&engine.device,
+			vk::BufferCreateFlags::empty(),
+			size_of::<ExposureData>() as u64,
+			vk::BufferUsageFlags::STORAGE_BUFFER
+				| vk::BufferUsageFlags::TRANSFER_DST
+				| vk::BufferUsageFlags::TRANSFER_SRC,
+			vk::SharingMode::EXCLUSIVE,
+			UsageFlags::DOWNLOAD | UsageFlags::UPLOAD | UsageFlags::HOST_ACCESS,
+		);
+		// An average luminance of 0 makes the first frame skip the adaptation
+		buffer.write(
+			0,
+			vec![ExposureData {
+				histogram: [0; HISTOGRAM_BIN_COUNT],
+				average_luminance: 0.0,
+				exposure: 1.0,
+				padding: [0.0; 2],
+			}],
+		);
+
+		let descriptors: Vec<descriptor::DescriptorSet> = (0..engine.frames_in_flight)
+			.map(|_| Exposure::create_descriptor(engine, &buffer))
+			.collect();
+
+		let exposure_data = ExposureConstant::default();
+		let push_constant = push_constant::PushConstant::new(
+			0,
+			size_of::<ExposureConstant>() as u32,
+			vk::ShaderStageFlags::COMPUTE,
+			vec![exposure_data],
+		);
+
+		let histogram_pipeline = Exposure::create_pipeline(
+			engine,
+			"shaders/spv/luminance_histogram.spv",
+			&descriptors[0],
+			&push_constant,
+		);
+		let average_pipeline = Exposure::create_pipeline(
+			engine,
+			"shaders/spv/luminance_average.spv",
+			&descriptors[0],
+			&push_constant,
+		);
+
+		let mut exposure = Exposure {
+			settings,
+			histogram_pipeline,
+			average_pipeline,
+			push_constant,
+			descriptors,
+			buffer,
+			exposure_data,
+		};
+		exposure.set_settings(settings, 0.0);
+		exposure
+	}
+
+	fn create_descriptor(
+		engine: &vulkan_engine::VulkanEngine,
+		buffer: &buffer::Buffer,
+	) -> descriptor::DescriptorSet {
+		let descriptor = descriptor::DescriptorSet::new(
+			&engine.device,
+			vec![
+				(vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 1),
+				(vk::DescriptorType::STORAGE_BUFFER, 1),
+			],
+			1,
+			vec![
+				vk::DescriptorSetLayoutBinding::builder()
+					.binding(0)
+					.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
+					.descriptor_count(1)
+					.stage_flags(vk::ShaderStageFlags::COMPUTE)
+					.build(),
+				vk::DescriptorSetLayoutBinding::builder()
+					.binding(1)
+					.descriptor_type(vk::DescriptorType::STORAGE_BUFFER)
+					.descriptor_count(1)
+					.stage_flags(vk::ShaderStageFlags::COMPUTE)
+					.build(),
+			],
+		);
+		descriptor.update_descriptor_set(
+			0,
+			1,
+			Some(vec![vk::DescriptorBufferInfo::builder()
+				.buffer(*buffer.buffer)
+				.offset(0)
+				.range(size_of::<ExposureData>() as u64)
+				.build()]),
+			None,
+		);
+		descriptor
+	}
+
+	fn create_pipeline(
+		engine: &vulkan_engine::VulkanEngine,
+		shader_path: &str,
+		descriptor: &descriptor::DescriptorSet,
+		push_constant: &push_constant::PushConstant,
+	) -> pipeline::ComputePipeline {
+		let compute_module = shader_module::ShaderModule::new(&engine.device, shader_path, "main");
+
+		pipeline::ComputePipeline::builder()
+			.add_push_constant(push_constant)
+			.add_descriptor_set(descriptor, 0)
+			.compute_module(&compute_module, vk::PipelineShaderStageCreateFlags::empty())
+			.build(&engine.device)
+	}
+
+	/// Must be called once per frame, `delta_time` (in seconds) drives the adaptation.
+	pub fn set_settings(&mut self, settings: ExposureSettings, delta_time: f32) {
+		self.settings = settings;
+		let min_ev = settings.min_ev.min(settings.max_ev - 1.0);
+		self.exposure_data.min_log_luminance = min_ev;
+		self.exposure_data.log_luminance_range = settings.max_ev - min_ev;
+		self.exposure_data.time_coefficient =
+			(1.0 - (-delta_time * settings.adaptation_speed).exp()).clamp(0.0, 1.0);
+		self.exposure_data.ev_compensation = settings.ev_compensation;
+		self.exposure_data.mode = settings.mode as u32;
+		self.exposure_data.camera_exposure = settings.camera.exposure();
+	}
+
+	/// Reads back the last values written by the GPU, up to `frames_in_flight` frames old.
+	pub fn read_data(&mut self) -> ExposureData {
+		let mut data = Vec::<ExposureData>::new();
+		self.buffer.read(0, &mut data);
+		data[0]
+	}
+
+	pub fn descriptor_buffer_info(&self) -> vk::DescriptorBufferInfo {
+		vk::DescriptorBufferInfo::builder()
+			.buffer(*self.buffer.buffer)
+			.offset(0)
+			.range(size_of::<ExposureData>() as u64)
+			.build()
+	}
+
+	/// The shared buffer, for the render graph.
+	pub fn graph_buffer(&self) -> render_graph::GraphBuffer {
+		render_graph::GraphBuffer {
+			buffer: *self.buffer.buffer,
+			size: size_of::<ExposureData>() as u64,
+		}
+	}
+
+	/// Records the histogram pass, which reads `input_hdr` in `GENERAL` layout and
+	/// updates the descriptor set of the frame, so it must come before the average pass.
+	pub fn record_histogram(
+		&mut self,
+		engine: &vulkan_engine::VulkanEngine,
+		command_buffer: &vk::CommandBuffer,
+		input_hdr: render_graph::GraphImage,
+	) {
+		self.descriptors[engine.current_frame].update_descriptor_set(
+			0,
+			0,
+			None,
+			Some(vec![vk::DescriptorImageInfo::builder()
+				.image_layout(vk::ImageLayout::GENERAL)
+				.image_view(input_hdr.view)
+				.sampler(engine.swapchain.swapchain_image_sampler)
+				.build()]),
+		);
+		self.exposure_data.pixel_count = input_hdr.extent.width * input_hdr.extent.height;
+		self.push_constant.set_data(vec![self.exposure_data]);
+
+		let group_x = input_hdr.extent.width.div_ceil(16);
+		let group_y = input_hdr.extent.height.div_ceil(16);
+		self.dispatch(
+			engine,
+			command_buffer,
+			&self.histogram_pipeline,
+			group_x,
+			group_y,
+		);
+	}
+
+	/// Records the average pass, after the histogram pass.
+	pub fn record_average(
+		&self,
+		engine: &vulkan_engine::VulkanEngine,
+		command_buffer: &vk::CommandBuffer,
+	) {
+		self.dispatch(engine, command_buffer, &self.average_pipeline, 1, 1);
+	}
+
+	fn dispatch(
+		&self,
+		engine: &vulkan_engine::VulkanEngine,
+		command_buffer: &vk::CommandBuffer,
+		pipeline: &pipeline::ComputePipeline,
+		group_x: u32,
+		group_y: u32,
+	) {
+		unsafe {
+			engine.device.device.cmd_bind_pipeline(
+				*command_buffer,
+				vk::PipelineBindPoint::COMPUTE,
+				pipeline.pipeline,
+			);
+			engine.device.device.cmd_bind_descriptor_sets(
+				*command_buffer,
+				vk::PipelineBindPoint::COMPUTE,
+				pipeline.pipeline_layout,
+				0,
+				&self.descriptors[engine.current_frame].descriptor_set,
+				&[],
+			);
+			engine.device.device.cmd_push_constants(
+				*command_buffer,
+				pipeline.pipeline_layout,
+				vk::ShaderStageFlags::COMPUTE,
+				0,
+				&self.push_constant.data,
+			);
+			engine
+				.device
+				.device
+				.cmd_dispatch(*command_buffer, group_x, group_y, 1);
+		};
+	}
+}
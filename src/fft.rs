@@ -0,0 +1,226 @@
+//! CPU radix-2 FFT, written the same way as `shaders/bloom_fft.comp`.
+//!
+//! Used to precompute the spectrum of the FFT bloom kernel, and as the reference the
+//! GPU convolution is checked against.
+
+use std::ops::{Add, Mul, Sub};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+#[repr(C)]
+pub struct Complex {
+	pub re: f32,
+	pub im: f32,
+}
+
+impl Complex {
+	pub fn new(re: f32, im: f32) -> Complex {
+		Complex { re, im }
+	}
+
+	pub fn from_polar(angle: f32) -> Complex {
+		Complex::new(angle.cos(), angle.sin())
+	}
+}
+
+impl Add for Complex {
+	type Output = Complex;
+	fn add(self, rhs: Complex) -> Complex {
+		Complex::new(self.re + rhs.re, self.im + rhs.im)
+	}
+}
+
+impl Sub for Complex {
+	type Output = Complex;
+	fn sub(self, rhs: Complex) -> Complex {
+		Complex::new(self.re - rhs.re, self.im - rhs.im)
+	}
+}
+
+impl Mul for Complex {
+	type Output = Complex;
+	fn mul(self, rhs: Complex) -> Complex {
+		Complex::new(
+			self.re * rhs.re - self.im * rhs.im,
+			self.re * rhs.im + self.im * rhs.re,
+		)
+	}
+}
+
+impl Mul<f32> for Complex {
+	type Output = Complex;
+	fn mul(self, rhs: f32) -> Complex {
+		Complex::new(self.re * rhs, self.im * rhs)
+	}
+}
+
+/// In place FFT of a power of two sized line, the inverse is scaled by `1 / len`.
+pub fn fft(data: &mut [Complex], inverse: bool) {
+	let len = data.len();
+	assert!(
+		len.is_power_of_two(),
+		"The FFT size must be a power of two."
+	);
+	let bits = len.trailing_zeros();
+	if bits == 0 {
+		return;
+	}
+
+	// Bit reversal
+	for i in 0..len {
+		let j = i.reverse_bits() >> (usize::BITS - bits);
+		if i < j {
+			data.swap(i, j);
+		}
+	}
+
+	// Butterflies
+	let sign = if inverse { 2.0 } else { -2.0 };
+	let mut half_size = 1;
+	while half_size < len {
+		for group in (0..len).step_by(half_size * 2) {
+			for pos in 0..half_size {
+				let angle = sign * std::f32::consts::PI * pos as f32 / (half_size * 2) as f32;
+				let a = data[group + pos];
+				let b = Complex::from_polar(angle) * data[group + pos + half_size];
+				data[group + pos] = a + b;
+				data[group + pos + half_size] = a - b;
+			}
+		}
+		half_size *= 2;
+	}
+
+	if inverse {
+		let scale = 1.0 / len as f32;
+		for value in data.iter_mut() {
+			*value = *value * scale;
+		}
+	}
+}
+
+/// In place 2D FFT of a `size` x `size` row major grid: rows, then columns.
+pub fn fft_2d(data: &mut [Complex], size: usize, inverse: bool) {
+	assert_eq!(data.len(), size * size, "The FFT grid must be square.");
+	for row in data.chunks_mut(size) {
+		fft(row, inverse);
+	}
+	let mut column = vec![Complex::default(); size];
+	for x in 0..size {
+		for y in 0..size {
+			column[y] = data[y * size + x];
+		}
+		fft(&mut column, inverse);
+		for y in 0..size {
+			data[y * size + x] = column[y];
+		}
+	}
+}
+
+/// Spectrum of a `width` x `height` kernel, placed at the center of a `size` x `size`
+/// grid then shifted so its center lands on texel (0, 0). The kernel is normalized to
+/// a sum of 1, so the convolution keeps the energy of the signal.
+pub fn kernel_spectrum(kernel: &[f32], width: usize, height: usize, size: usize) -> Vec<Complex> {
+	assert!(
+		width <= size && height <= size,
+		"The kernel doesn't fit in the FFT grid."
+	);
+	let sum: f32 = kernel.iter().sum();
+	let scale = if sum > 0.0 { 1.0 / sum } else { 0.0 };
+
+	let mut grid = vec![Complex::default(); size * size];
+	for y in 0..height {
+		for x in 0..width {
+			let grid_x = (x + size - width / 2) % size;
+			let grid_y = (y + size - height / 2) % size;
+			grid[grid_y * size + grid_x] = Complex::new(kernel[y * width + x] * scale, 0.0);
+		}
+	}
+	fft_2d(&mut grid, size, false);
+	grid
+}
+
+/// Circular convolution of a `size` x `size` signal through the FFT.
+#[cfg(test)]
+pub fn convolve(signal: &[f32], spectrum: &[Complex], size: usize) -> Vec<f32> {
+	let mut grid: Vec<Complex> = signal.iter().map(|&x| Complex::new(x, 0.0)).collect();
+	fft_2d(&mut grid, size, false);
+	for (value, kernel) in grid.iter_mut().zip(spectrum.iter()) {
+		*value = *value * *kernel;
+	}
+	fft_2d(&mut grid, size, true);
+	grid.iter().map(|value| value.re).collect()
+}
+
+/// Brute force circular convolution, slow but obviously right. `kernel` is laid out
+/// like the grid given to `kernel_spectrum`, before the shift.
+#[cfg(test)]
+pub fn convolve_direct(
+	signal: &[f32],
+	size: usize,
+	kernel: &[f32],
+	width: usize,
+	height: usize,
+) -> Vec<f32> {
+	let sum: f32 = kernel.iter().sum();
+	let scale = if sum > 0.0 { 1.0 / sum } else { 0.0 };
+
+	let mut result = vec![0.0; size * size];
+	for y in 0..size {
+		for x in 0..size {
+			let mut value = 0.0;
+			for ky in 0..height {
+				for kx in 0..width {
+					let sx = (x + size * 2 + width / 2 - kx) % size;
+					let sy = (y + size * 2 + height / 2 - ky) % size;
+					value += signal[sy * size + sx] * kernel[ky * width + kx] * scale;
+				}
+			}
+			result[y * size + x] = value;
+		}
+	}
+	result
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const SIZE: usize = 16;
+
+	fn signal() -> Vec<f32> {
+		(0..SIZE * SIZE)
+			.map(|i| ((i * 7919) % 31) as f32 / 31.0)
+			.collect()
+	}
+
+	#[test]
+	fn inverse_fft_restores_the_signal() {
+		let signal = signal();
+		let mut grid: Vec<Complex> = signal.iter().map(|&x| Complex::new(x, 0.0)).collect();
+		fft_2d(&mut grid, SIZE, false);
+		fft_2d(&mut grid, SIZE, true);
+		for (value, expected) in grid.iter().zip(signal.iter()) {
+			assert!((value.re - expected).abs() < 1.0e-5);
+			assert!(value.im.abs() < 1.0e-5);
+		}
+	}
+
+	#[test]
+	fn convolve_matches_convolve_direct() {
+		let signal = signal();
+		// Asymmetric, odd and even sides, so a flipped or shifted kernel shows up
+		let (width, height) = (5, 4);
+		let kernel: Vec<f32> = (0..width * height).map(|i| (i + 1) as f32).collect();
+
+		let spectrum = kernel_spectrum(&kernel, width, height, SIZE);
+		let fast = convolve(&signal, &spectrum, SIZE);
+		let direct = convolve_direct(&signal, SIZE, &kernel, width, height);
+		for (fast, direct) in fast.iter().zip(direct.iter()) {
+			assert!(
+				(fast - direct).abs() < 1.0e-5,
+				"FFT convolution {} differs from direct convolution {}",
+				fast,
+				direct
+			);
+		}
+	}
+}
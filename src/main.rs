@@ -1,28 +1,81 @@
 extern crate ultraviolet as uv;
 
+use std::cell::RefCell;
 use std::mem::size_of;
+use std::rc::Rc;
 
 use ash::vk;
 
 mod bloom;
+mod bloom_fft;
+#[cfg(test)]
+mod bloom_reference;
+mod bloom_spd;
+mod blue_noise;
+mod camera;
+mod color_lut;
+mod exposure;
+mod fft;
+mod post_process;
 mod render;
 mod vulkan_engine;
 
 use gpu_alloc::UsageFlags;
-use vulkan_engine::{buffer, descriptor, image, pipeline, push_constant, shader_module, window};
+use vulkan_engine::{buffer, descriptor, render_graph};
 use winit::platform::run_return::EventLoopExtRunReturn;
 
-fn main() {
-	vulkan_engine::compile_shaders();
-	let mut engine = vulkan_engine::VulkanEngine::new();
+/// Number of frames recorded by the CPU while the GPU renders the previous ones.
+pub const FRAMES_IN_FLIGHT: usize = 2;
+/// Frames rendered by `--headless` before the last one is saved, so the automatic
+/// exposure has settled.
+pub const HEADLESS_FRAME_COUNT: usize = 120;
+
+/// Extent of the offscreen images of `--headless` when none is given, the size of the
+/// window.
+pub const HEADLESS_DEFAULT_EXTENT: vk::Extent2D = vk::Extent2D {
+	width: 1080,
+	height: 720,
+};
+const HEADLESS_USAGE: &str = "Usage: --headless <output.png> [<width>x<height>]";
+
+/// `--headless <output.png> [<width>x<height>]` renders without a window and saves the
+/// frame: returns the output path and the extent of the offscreen images.
+fn headless_arguments(args: impl Iterator<Item = String>) -> Option<(String, vk::Extent2D)> {
+	let mut args = args.skip_while(|arg| arg != "--headless");
+	args.next()?;
+	let output_path = args.next().expect(HEADLESS_USAGE);
+	let extent = args.next().map_or(HEADLESS_DEFAULT_EXTENT, |extent| {
+		parse_extent(&extent).unwrap_or_else(|| panic!("{}, got {}.", HEADLESS_USAGE, extent))
+	});
+	Some((output_path, extent))
+}
 
-	let proj = uv::projection::perspective_vk(
+/// `<width>x<height>`, both non zero.
+fn parse_extent(extent: &str) -> Option<vk::Extent2D> {
+	let (width, height) = extent.split_once('x')?;
+	let (width, height) = (width.parse().ok()?, height.parse().ok()?);
+	(width > 0 && height > 0).then(|| vk::Extent2D::builder().width(width).height(height).build())
+}
+
+fn projection(extent: vk::Extent2D) -> uv::Mat4 {
+	uv::projection::perspective_vk(
 		std::f32::consts::FRAC_PI_3,
-		engine.surface.surface_resolution.width as f32
-			/ engine.surface.surface_resolution.height as f32,
+		extent.width as f32 / extent.height as f32,
 		0.1f32,
 		1000f32,
-	);
+	)
+}
+
+fn main() {
+	vulkan_engine::compile_shaders();
+	let headless = headless_arguments(std::env::args());
+	let mut engine = if let Some((_, extent)) = headless {
+		vulkan_engine::VulkanEngine::new_headless(FRAMES_IN_FLIGHT, extent)
+	} else {
+		vulkan_engine::VulkanEngine::new(FRAMES_IN_FLIGHT)
+	};
+
+	let proj = projection(engine.surface.surface_resolution);
 
 	let view = uv::Mat4::look_at(
 		uv::Vec3::new(0.0, 0.0, -8.0),
@@ -30,7 +83,7 @@ fn main() {
 		uv::Vec3::new(0.0, 1.0, 0.0),
 	);
 
-	let pv = proj * view;
+	let mut pv = proj * view;
 	let model = uv::Mat4::from_scale(1.0f32);
 
 	let mut pbr_param = vulkan_engine::PbrParameters {
@@ -50,91 +103,94 @@ fn main() {
 	};
 
 	let u_buffer_size = buffer::aligne_offset(size_of::<uv::Mat4>() as u64) * 2
-		+ buffer::aligne_offset((size_of::<uv::Vec4>() * 2) as u64)
 		+ buffer::aligne_offset(size_of::<vulkan_engine::PbrParameters>() as u64);
 
-	let mut uniform_buffer = buffer::Buffer::new(
-		&engine.device,
-		vk::BufferCreateFlags::empty(),
-		u_buffer_size,
-		vk::BufferUsageFlags::UNIFORM_BUFFER
-			| vk::BufferUsageFlags::TRANSFER_DST
-			| vk::BufferUsageFlags::TRANSFER_SRC,
-		vk::SharingMode::EXCLUSIVE,
-		// UsageFlags::DOWNLOAD | UsageFlags::UPLOAD | UsageFlags::FAST_DEVICE_ACCESS,
-		UsageFlags::DOWNLOAD | UsageFlags::UPLOAD | UsageFlags::HOST_ACCESS,
-	);
+	// One uniform buffer and descriptor set per frame in flight, `engine.descriptors`
+	// is indexed by `engine.current_frame`
+	let mut uniform_buffers = Vec::with_capacity(FRAMES_IN_FLIGHT);
+	for _ in 0..FRAMES_IN_FLIGHT {
+		let mut uniform_buffer = buffer::Buffer::new(
+			&engine.device,
+			vk::BufferCreateFlags::empty(),
+			u_buffer_size,
+			vk::BufferUsageFlags::UNIFORM_BUFFER
+				| vk::BufferUsageFlags::TRANSFER_DST
+				| vk::BufferUsageFlags::TRANSFER_SRC,
+			vk::SharingMode::EXCLUSIVE,
+			// UsageFlags::DOWNLOAD | UsageFlags::UPLOAD | UsageFlags::FAST_DEVICE_ACCESS,
+			UsageFlags::DOWNLOAD | UsageFlags::UPLOAD | UsageFlags::HOST_ACCESS,
+		);
 
-	uniform_buffer.write(0, vec![pv]);
-	uniform_buffer.write(size_of::<uv::Mat4>() as u64, vec![model]);
-	uniform_buffer.write(
-		buffer::aligne_offset((size_of::<uv::Mat4>() * 2 + size_of::<uv::Vec4>() * 2) as u64),
-		vec![pbr_param],
-	);
+		uniform_buffer.write(0, vec![pv]);
+		uniform_buffer.write(size_of::<uv::Mat4>() as u64, vec![model]);
+		uniform_buffer.write(
+			buffer::aligne_offset((size_of::<uv::Mat4>() * 2) as u64),
+			vec![pbr_param],
+		);
 
-	let mut uniform_descriptor = descriptor::DescriptorSet::new(
-		&engine.device,
-		[(vk::DescriptorType::UNIFORM_BUFFER, 2)].to_vec(),
-		2,
-		vec![
-			vk::DescriptorSetLayoutBinding::builder()
+		let mut uniform_descriptor = descriptor::DescriptorSet::new(
+			&engine.device,
+			[(vk::DescriptorType::UNIFORM_BUFFER, 2)].to_vec(),
+			2,
+			vec![
+				vk::DescriptorSetLayoutBinding::builder()
+					.binding(0)
+					.descriptor_count(1)
+					.descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+					.stage_flags(vk::ShaderStageFlags::VERTEX)
+					.build(),
+				vk::DescriptorSetLayoutBinding::builder()
+					.binding(1)
+					.descriptor_count(1)
+					.descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
+					.stage_flags(vk::ShaderStageFlags::VERTEX)
+					.build(),
+			],
+		);
+		uniform_descriptor.update_descriptor_set(
+			0,
+			0,
+			Some(vec![vk::DescriptorBufferInfo::builder()
+				.buffer(*uniform_buffer.buffer)
+				.offset(0)
+				.range(size_of::<uv::Mat4>() as u64)
+				.build()]),
+			None,
+		);
+		uniform_descriptor.update_descriptor_set(
+			0,
+			1,
+			Some(vec![vk::DescriptorBufferInfo::builder()
+				.buffer(*uniform_buffer.buffer)
+				.offset(size_of::<uv::Mat4>() as u64)
+				.range(size_of::<uv::Mat4>() as u64)
+				.build()]),
+			None,
+		);
+
+		uniform_descriptor.create_another_set(
+			&engine.device,
+			vec![vk::DescriptorSetLayoutBinding::builder()
 				.binding(0)
 				.descriptor_count(1)
 				.descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-				.stage_flags(vk::ShaderStageFlags::VERTEX)
-				.build(),
-			vk::DescriptorSetLayoutBinding::builder()
-				.binding(1)
-				.descriptor_count(1)
-				.descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-				.stage_flags(vk::ShaderStageFlags::VERTEX)
-				.build(),
-		],
-	);
-	uniform_descriptor.update_descriptor_set(
-		0,
-		0,
-		Some(vec![vk::DescriptorBufferInfo::builder()
-			.buffer(*uniform_buffer.buffer)
-			.offset(0)
-			.range(size_of::<uv::Mat4>() as u64)
-			.build()]),
-		None,
-	);
-	uniform_descriptor.update_descriptor_set(
-		0,
-		1,
-		Some(vec![vk::DescriptorBufferInfo::builder()
-			.buffer(*uniform_buffer.buffer)
-			.offset(size_of::<uv::Mat4>() as u64)
-			.range(size_of::<uv::Mat4>() as u64)
-			.build()]),
-		None,
-	);
-
-	uniform_descriptor.create_another_set(
-		&engine.device,
-		vec![vk::DescriptorSetLayoutBinding::builder()
-			.binding(0)
-			.descriptor_count(1)
-			.descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-			.stage_flags(vk::ShaderStageFlags::FRAGMENT)
-			.build()],
-	);
-	uniform_descriptor.update_descriptor_set(
-		1,
-		0,
-		Some(vec![vk::DescriptorBufferInfo::builder()
-			.buffer(*uniform_buffer.buffer)
-			.offset(buffer::aligne_offset(
-				(size_of::<uv::Mat4>() * 2 + size_of::<uv::Vec4>() * 2) as u64,
-			))
-			.range(size_of::<vulkan_engine::PbrParameters>() as u64)
-			.build()]),
-		None,
-	);
+				.stage_flags(vk::ShaderStageFlags::FRAGMENT)
+				.build()],
+		);
+		uniform_descriptor.update_descriptor_set(
+			1,
+			0,
+			Some(vec![vk::DescriptorBufferInfo::builder()
+				.buffer(*uniform_buffer.buffer)
+				.offset(buffer::aligne_offset((size_of::<uv::Mat4>() * 2) as u64))
+				.range(size_of::<vulkan_engine::PbrParameters>() as u64)
+				.build()]),
+			None,
+		);
 
-	engine.descriptors.push(uniform_descriptor);
+		engine.descriptors.push(uniform_descriptor);
+		uniform_buffers.push(uniform_buffer);
+	}
 
 	engine.build_basic_pipeline(
 		0,
@@ -144,7 +200,7 @@ fn main() {
 		None,
 	);
 
-	let arg = std::path::Path::new(&std::env::args().into_iter().next().unwrap())
+	let arg = std::path::Path::new(&std::env::args().next().unwrap())
 		.parent()
 		.unwrap()
 		.parent()
@@ -205,139 +261,44 @@ fn main() {
 
 	vertex_buffer.write_to_vram(&engine.device, &engine.command_builder, 0, vertex_data);
 
-	let mut downsample_image = Vec::<image::Image>::with_capacity(3);
-
-	let image_width = engine.surface.surface_resolution.width / 2;
-	let image_height = engine.surface.surface_resolution.height / 2;
-
-	for _ in 0..3 {
-		let mut image = image::Image::new(
-			&engine.device,
-			vk::ImageCreateFlags::empty(),
-			vk::ImageType::TYPE_2D,
-			vk::Format::R16G16B16A16_SFLOAT,
-			vk::Extent3D::builder()
-				.width(image_width)
-				.height(image_height)
-				.depth(1)
-				.build(),
-			bloom::BLOOM_MIP_COUNT as u32,
-			1,
-			vk::ImageTiling::OPTIMAL,
-			vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
-			engine.device.queue_family_index,
-			vk::ImageLayout::UNDEFINED,
-			vk::ImageLayout::GENERAL,
-			vk::ImageViewType::TYPE_2D,
-			vk::ImageAspectFlags::COLOR,
-			UsageFlags::FAST_DEVICE_ACCESS,
-		);
-		image.set_sampler(
-			vk::Filter::LINEAR,
-			vk::Filter::LINEAR,
-			vk::SamplerMipmapMode::LINEAR,
-			vk::SamplerAddressMode::CLAMP_TO_EDGE,
-			vk::SamplerAddressMode::CLAMP_TO_EDGE,
-			vk::SamplerAddressMode::CLAMP_TO_EDGE,
-			0.0,
-			false,
-			1.0,
-			false,
-			vk::CompareOp::ALWAYS,
-			-1000.0,
-			1000.0,
-			vk::BorderColor::FLOAT_OPAQUE_BLACK,
-		);
-		image.change_layout(
-			&engine.device,
-			&engine.command_builder,
-			image.initial_layout,
-			image.final_layout,
-		);
-		downsample_image.push(image);
-	}
-
-	let image_descriptor = descriptor::DescriptorSet::new(
-		&engine.device,
-		vec![(vk::DescriptorType::UNIFORM_BUFFER, 1)],
-		1,
-		vec![
-			vk::DescriptorSetLayoutBinding::builder()
-				.binding(0)
-				.descriptor_type(vk::DescriptorType::STORAGE_IMAGE)
-				.descriptor_count((3 * bloom::BLOOM_MIP_COUNT + 1) as u32)
-				.stage_flags(vk::ShaderStageFlags::COMPUTE)
-				.build(),
-			vk::DescriptorSetLayoutBinding::builder()
-				.binding(1)
-				.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-				.descriptor_count(4)
-				.stage_flags(vk::ShaderStageFlags::COMPUTE)
-				.build(),
-			vk::DescriptorSetLayoutBinding::builder()
-				.binding(2)
-				.descriptor_type(vk::DescriptorType::COMBINED_IMAGE_SAMPLER)
-				.descriptor_count(4)
-				.stage_flags(vk::ShaderStageFlags::COMPUTE)
-				.build(),
-			vk::DescriptorSetLayoutBinding::builder()
-				.binding(3)
-				.descriptor_type(vk::DescriptorType::UNIFORM_BUFFER)
-				.descriptor_count(1)
-				.stage_flags(vk::ShaderStageFlags::COMPUTE)
-				.build(),
-		],
-	);
-
-	let mut bloom_threshold = 1.0f32;
-	let mut bloom_knee = 0.2f32;
-
-	uniform_buffer.write(
-		(size_of::<uv::Mat4>() * 2) as u64,
-		vec![
-			uv::Vec4::new(
-				bloom_threshold,
-				bloom_threshold - bloom_knee,
-				bloom_knee * 2.0f32,
-				0.25f32 / bloom_knee,
-			),
-			uv::Vec4::new(1.0f32, 0.68f32, 0.0f32, 0.0f32),
-		],
-	);
-
-	image_descriptor.update_descriptor_set(
-		0,
-		3,
-		Some(vec![vk::DescriptorBufferInfo::builder()
-			.buffer(*uniform_buffer.buffer)
-			.offset((size_of::<uv::Mat4>() * 2) as u64)
-			.range((size_of::<uv::Vec4>() * 2) as u64)
-			.build()]),
-		None,
-	);
-
-	let mut bloom_data = bloom::BloomConstant {
-		mode_lod_in_out_bloom: 0,
+	let mesh = render::Mesh {
+		vertex_buffer,
+		index_buffer,
+		index_count,
 	};
 
-	let compute_constant = push_constant::PushConstant::new(
-		0,
-		std::mem::size_of::<bloom::BloomConstant>() as u32,
-		vk::ShaderStageFlags::COMPUTE,
-		vec![bloom_data.clone()],
-	);
-	let compute_module =
-		shader_module::ShaderModule::new(&engine.device, "shaders/spv/bloom.spv", "main");
-
-	let compute_pipeline = pipeline::ComputePipeline::builder()
-		.add_push_constant(&compute_constant)
-		.add_descriptor_set(&image_descriptor, 0)
-		.compute_module(&compute_module, vk::PipelineShaderStageCreateFlags::empty())
-		.build(&engine.device);
-
-	engine.descriptors.push(image_descriptor);
-	engine.push_constants.push(compute_constant);
-	engine.compute_pipelines.push(compute_pipeline);
+	let mut bloom_settings = bloom::BloomSettings::default();
+	let mut exposure_settings = exposure::ExposureSettings::default();
+	let exposure_mode_names: Vec<&str> = exposure::ExposureMode::ALL
+		.iter()
+		.map(|mode| mode.name())
+		.collect();
+	let light_units_names: Vec<&str> = camera::LightUnits::ALL
+		.iter()
+		.map(|units| units.name())
+		.collect();
+	let mut lens_dirt_path = String::with_capacity(256);
+	let mut fft_kernel_path = String::with_capacity(256);
+	let mut color_lut_path = String::with_capacity(256);
+	let mut blue_noise_path = String::with_capacity(256);
+	let dither_names: Vec<&str> = bloom::DitherMode::ALL
+		.iter()
+		.map(|dither| dither.name())
+		.collect();
+	let algorithm_names: Vec<&str> = bloom::BloomAlgorithm::ALL
+		.iter()
+		.map(|algorithm| algorithm.name())
+		.collect();
+	let tonemapper_names: Vec<&str> = bloom::Tonemapper::ALL
+		.iter()
+		.map(|tonemapper| tonemapper.name())
+		.collect();
+	let bloom_pass = Rc::new(RefCell::new(bloom::BloomPass::new(&engine, bloom_settings)));
+	let post_process = Rc::new(RefCell::new(post_process::PostProcessStack::new()));
+	post_process.borrow_mut().push(bloom_pass.clone());
+	engine.register_resizable(post_process.clone());
+	let transient_images = Rc::new(RefCell::new(render_graph::TransientImagePool::new()));
+	engine.register_resizable(transient_images.clone());
 
 	let mut imgui = imgui::Context::create();
 
@@ -350,12 +311,44 @@ fn main() {
 		engine.renderpass.renderpass,
 		&mut imgui,
 		Some(imgui_rs_vulkan_renderer::Options {
-			in_flight_frames: 1,
+			in_flight_frames: FRAMES_IN_FLIGHT,
 			..Default::default()
 		}),
 	)
 	.unwrap();
 
+	if let Some((output_path, _)) = headless {
+		let extent = engine.surface.surface_resolution;
+		let delta_time = 1.0f32 / 60.0;
+		imgui.io_mut().display_size = [extent.width as f32, extent.height as f32];
+		imgui.io_mut().delta_time = delta_time;
+		let mut last_image = None;
+		for _ in 0..HEADLESS_FRAME_COUNT {
+			bloom_pass
+				.borrow_mut()
+				.exposure
+				.set_settings(exposure_settings, delta_time);
+			// An empty ui
+			let ui = imgui.frame();
+			let draw_data = ui.render();
+			engine.wait_for_frame();
+			last_image = render::render_func(
+				&mut engine,
+				&mesh,
+				&mut renderer,
+				draw_data,
+				&post_process.borrow(),
+				&mut transient_images.borrow_mut(),
+			)
+			.or(last_image);
+		}
+		let frame = engine.read_output(last_image.expect("No frame was rendered."));
+		frame
+			.save(&output_path)
+			.unwrap_or_else(|error| panic!("Failed to save {}: {}", output_path, error));
+		return;
+	}
+
 	let mut platform = imgui_winit_support::WinitPlatform::init(&mut imgui); // step 1
 
 	platform.attach_window(
@@ -367,15 +360,22 @@ fn main() {
 	let mut albedo_color = [0.0f32; 3];
 	let mut emissive_color = [0.0f32; 3];
 	let mut emissive_intensity = 1.0;
-	let mut bloom_intensity = 1.0;
-	let mut combine_constant = 0.68;
-
-	let mut current_image = 0;
-	let mut window: window::Window = unsafe { std::mem::transmute_copy(&engine.window) };
+	// The intensities are converted to the shader units by `light_units`
+	let mut light_units = camera::LightUnits::Arbitrary;
+	let mut light_color = [1.0f32; 3];
+	let mut light_intensity = 25.0f32;
+
+	let mut event_loop = engine
+		.window
+		.as_mut()
+		.unwrap()
+		.event_loop
+		.take()
+		.expect("The event loop is already running.");
 	let mut time = std::time::Instant::now();
 	let mut delta_time = std::time::Duration::ZERO;
 
-	window.event_loop.run_return(|event, _, control_flow| {
+	event_loop.run_return(|event, _, control_flow| {
 		*control_flow = winit::event_loop::ControlFlow::Poll;
 		match event {
 			winit::event::Event::WindowEvent { event, window_id } => match event {
@@ -404,7 +404,7 @@ fn main() {
 						engine.minimized = false;
 					} else if logical_size.width == 0 && logical_size.height == 0 {
 						engine.minimized = true;
-					} else if engine.minimized == true
+					} else if engine.minimized
 						&& logical_size.width != 0
 						&& logical_size.height != 0
 					{
@@ -430,10 +430,14 @@ fn main() {
 				time = now;
 				imgui.io_mut().update_delta_time(delta_time);
 
-				if engine.resized == true && engine.old_extent == engine.new_extent {
-					engine.window_resized(&mut current_image);
+				if engine.resized && engine.old_extent == engine.new_extent {
+					engine.window_resized();
 					engine.resized = false;
-				} else if engine.resized == true && engine.old_extent != engine.new_extent {
+					pv = projection(engine.surface.surface_resolution) * view;
+					for uniform_buffer in uniform_buffers.iter_mut() {
+						uniform_buffer.write(0, vec![pv]);
+					}
+				} else if engine.resized && engine.old_extent != engine.new_extent {
 					engine.old_extent = engine.new_extent;
 				}
 			}
@@ -444,7 +448,7 @@ fn main() {
 				engine.window.as_ref().unwrap().window.request_redraw();
 			}
 			winit::event::Event::RedrawRequested(_) => {
-				if engine.resized == false && engine.minimized == false {
+				if !engine.resized && !engine.minimized {
 					let ui = imgui.frame();
 					// let mut opened = true;
 
@@ -455,7 +459,22 @@ fn main() {
 					emissive_color[0] = pbr_param.emissive_color.x;
 					emissive_color[1] = pbr_param.emissive_color.y;
 					emissive_color[2] = pbr_param.emissive_color.z;
-					emissive_intensity = pbr_param.emissive_intensity;
+					let exposure_data = bloom_pass.borrow_mut().exposure.read_data();
+					let gpu_timings = engine.timestamps.borrow().timings().to_vec();
+					let gpu_frame_times = engine.timestamps.borrow().frame_times().to_vec();
+					let debug_views = bloom::BloomDebugView::all(bloom_pass.borrow().mip_count());
+					let debug_view_names: Vec<String> =
+						debug_views.iter().map(|view| view.name()).collect();
+					let debug_view_names: Vec<&str> =
+						debug_view_names.iter().map(String::as_str).collect();
+					let mut load_lens_dirt = false;
+					let mut clear_lens_dirt = false;
+					let mut load_fft_kernel = false;
+					let mut reset_fft_kernel = false;
+					let mut load_color_lut = false;
+					let mut clear_color_lut = false;
+					let mut load_blue_noise = false;
+					let mut reset_blue_noise = false;
 
 					imgui::Window::new("Pbr parameters")
 						.size([300.0, 150.0], imgui::Condition::FirstUseEver)
@@ -475,67 +494,335 @@ fn main() {
 								imgui::EditableColor::Float3(&mut emissive_color),
 							)
 							.build(&ui);
-							imgui::Slider::new("emissive intensity", 0.0f32, 20.0f32)
+							let mut light_units_index = camera::LightUnits::ALL
+								.iter()
+								.position(|units| *units == light_units)
+								.unwrap();
+							if ui.combo_simple_string(
+								"light units",
+								&mut light_units_index,
+								&light_units_names,
+							) {
+								light_units = camera::LightUnits::ALL[light_units_index];
+							}
+							let (emissive_label, emissive_max, light_label, light_max) =
+								match light_units {
+									camera::LightUnits::Arbitrary => {
+										("emissive intensity", 20.0f32, "light intensity", 100.0f32)
+									}
+									camera::LightUnits::Photometric => (
+										"emissive luminance (nits)",
+										10000.0f32,
+										"light power (lm)",
+										10000.0f32,
+									),
+								};
+							imgui::Slider::new(emissive_label, 0.0f32, emissive_max)
+								.flags(imgui::SliderFlags::LOGARITHMIC)
 								.build(&ui, &mut emissive_intensity);
+							imgui::ColorEdit::new(
+								"light color",
+								imgui::EditableColor::Float3(&mut light_color),
+							)
+							.build(&ui);
+							imgui::Slider::new(light_label, 0.0f32, light_max)
+								.flags(imgui::SliderFlags::LOGARITHMIC)
+								.build(&ui, &mut light_intensity);
 							imgui::Slider::new("bloom intensity", 0.0f32, 100.0f32)
-								.build(&ui, &mut bloom_intensity);
+								.build(&ui, &mut bloom_settings.intensity);
 							imgui::Slider::new("combine constant", 0.005f32, 1.0f32)
-								.build(&ui, &mut combine_constant);
+								.build(&ui, &mut bloom_settings.combine_constant);
 							imgui::Slider::new("bloom threshold", 0.0f32, 50.0f32)
-								.build(&ui, &mut bloom_threshold);
+								.build(&ui, &mut bloom_settings.threshold);
 							imgui::Slider::new("bloom knee", 0.0f32, 50.0f32)
-								.build(&ui, &mut bloom_knee);
+								.build(&ui, &mut bloom_settings.knee);
+							imgui::Slider::new("bloom clamp value", 1.0f32, 100.0f32)
+								.build(&ui, &mut bloom_settings.clamp_value);
+							ui.checkbox("karis average", &mut bloom_settings.karis_average);
+							ui.checkbox(
+								"single pass downsample",
+								&mut bloom_settings.single_pass_downsample,
+							);
+							if imgui::CollapsingHeader::new("lens flare").build(&ui) {
+								let lens_flare = &mut bloom_settings.lens_flare;
+								imgui::Slider::new("flare intensity", 0.0f32, 2.0f32)
+									.build(&ui, &mut lens_flare.intensity);
+								imgui::Slider::new("ghost count", 0u32, 8u32)
+									.build(&ui, &mut lens_flare.ghost_count);
+								imgui::Slider::new("ghost spacing", 0.0f32, 1.0f32)
+									.build(&ui, &mut lens_flare.ghost_spacing);
+								imgui::Slider::new("halo width", 0.0f32, 1.0f32)
+									.build(&ui, &mut lens_flare.halo_width);
+								imgui::Slider::new("chromatic distortion", 0.0f32, 10.0f32)
+									.build(&ui, &mut lens_flare.chromatic_distortion);
+								imgui::Slider::new("flare mip", 0u32, 8u32)
+									.build(&ui, &mut lens_flare.mip);
+							}
+							let mut debug_view_index = debug_views
+								.iter()
+								.position(|view| *view == bloom_settings.debug_view)
+								.unwrap_or(0);
+							if ui.combo_simple_string(
+								"bloom debug view",
+								&mut debug_view_index,
+								&debug_view_names,
+							) {
+								bloom_settings.debug_view = debug_views[debug_view_index];
+							}
+							if imgui::CollapsingHeader::new("bloom mip tints").build(&ui) {
+								// The mips of the chain the pass actually builds
+								let mip_count = bloom::clamp_mip_count(
+									bloom_settings.mip_count,
+									engine.surface.surface_resolution,
+								);
+								let tinted_mips = mip_count.min(bloom::BLOOM_MIP_TINT_COUNT);
+								for i in 0..tinted_mips {
+									imgui::Slider::new(format!("mip {} weight", i), 0.0f32, 4.0f32)
+										.build(&ui, &mut bloom_settings.mip_weights[i]);
+									imgui::ColorEdit::new(
+										format!("mip {} tint", i),
+										imgui::EditableColor::Float3(
+											&mut bloom_settings.mip_tints[i],
+										),
+									)
+									.build(&ui);
+								}
+							}
+							imgui::Slider::new("bloom mip count", 2u32, 12u32)
+								.build(&ui, &mut bloom_settings.mip_count);
+							let mut algorithm_index = bloom::BloomAlgorithm::ALL
+								.iter()
+								.position(|algorithm| *algorithm == bloom_settings.algorithm)
+								.unwrap();
+							if ui.combo_simple_string(
+								"bloom algorithm",
+								&mut algorithm_index,
+								&algorithm_names,
+							) {
+								bloom_settings.algorithm =
+									bloom::BloomAlgorithm::ALL[algorithm_index];
+							}
+							ui.input_text("fft kernel path", &mut fft_kernel_path)
+								.build();
+							load_fft_kernel = ui.button("load fft kernel");
+							ui.same_line();
+							reset_fft_kernel = ui.button("reset fft kernel");
+							let mut tonemapper_index = bloom::Tonemapper::ALL
+								.iter()
+								.position(|tonemapper| *tonemapper == bloom_settings.tonemapper)
+								.unwrap();
+							if ui.combo_simple_string(
+								"tonemapper",
+								&mut tonemapper_index,
+								&tonemapper_names,
+							) {
+								bloom_settings.tonemapper =
+									bloom::Tonemapper::ALL[tonemapper_index];
+							}
+							imgui::Slider::new("white point", 1.0f32, 20.0f32)
+								.build(&ui, &mut bloom_settings.white_point);
+							let mut anamorphic =
+								bloom_settings.shape == bloom::BloomShape::Anamorphic;
+							if ui.checkbox("anamorphic bloom", &mut anamorphic) {
+								bloom_settings.shape = if anamorphic {
+									bloom::BloomShape::Anamorphic
+								} else {
+									bloom::BloomShape::Symmetric
+								};
+							}
+							imgui::Slider::new("anamorphic stretch", 1.0f32, 16.0f32)
+								.build(&ui, &mut bloom_settings.stretch);
+							ui.input_text("lens dirt path", &mut lens_dirt_path).build();
+							load_lens_dirt = ui.button("load lens dirt");
+							ui.same_line();
+							clear_lens_dirt = ui.button("clear lens dirt");
+							imgui::Slider::new("lens dirt intensity", 0.0f32, 10.0f32)
+								.build(&ui, &mut bloom_settings.lens_dirt_intensity);
+							ui.input_text("color lut path", &mut color_lut_path).build();
+							load_color_lut = ui.button("load color lut");
+							ui.same_line();
+							clear_color_lut = ui.button("clear color lut");
+							imgui::Slider::new("color lut strength", 0.0f32, 1.0f32)
+								.build(&ui, &mut bloom_settings.lut_strength);
+							let mut dither_index = bloom::DitherMode::ALL
+								.iter()
+								.position(|dither| *dither == bloom_settings.dither)
+								.unwrap();
+							if ui.combo_simple_string("dither", &mut dither_index, &dither_names) {
+								bloom_settings.dither = bloom::DitherMode::ALL[dither_index];
+							}
+							ui.input_text("blue noise path", &mut blue_noise_path)
+								.build();
+							load_blue_noise = ui.button("load blue noise");
+							ui.same_line();
+							reset_blue_noise = ui.button("reset blue noise");
+							let mut exposure_mode_index = exposure::ExposureMode::ALL
+								.iter()
+								.position(|mode| *mode == exposure_settings.mode)
+								.unwrap();
+							if ui.combo_simple_string(
+								"exposure mode",
+								&mut exposure_mode_index,
+								&exposure_mode_names,
+							) {
+								exposure_settings.mode =
+									exposure::ExposureMode::ALL[exposure_mode_index];
+							}
+							imgui::Slider::new("aperture (f-stop)", 1.0f32, 22.0f32)
+								.build(&ui, &mut exposure_settings.camera.aperture);
+							imgui::Slider::new("shutter speed (s)", 1.0f32 / 8000.0, 1.0f32)
+								.flags(imgui::SliderFlags::LOGARITHMIC)
+								.display_format("%.5f")
+								.build(&ui, &mut exposure_settings.camera.shutter_speed);
+							imgui::Slider::new("iso", 50.0f32, 25600.0f32)
+								.flags(imgui::SliderFlags::LOGARITHMIC)
+								.display_format("%.0f")
+								.build(&ui, &mut exposure_settings.camera.iso);
+							ui.text(format!("ev100: {:.2}", exposure_settings.camera.ev100()));
+							imgui::Slider::new("ev compensation", -5.0f32, 5.0f32)
+								.build(&ui, &mut exposure_settings.ev_compensation);
+							imgui::Slider::new("min ev", -16.0f32, 0.0f32)
+								.build(&ui, &mut exposure_settings.min_ev);
+							imgui::Slider::new("max ev", 0.0f32, 16.0f32)
+								.build(&ui, &mut exposure_settings.max_ev);
+							imgui::Slider::new("adaptation speed", 0.1f32, 10.0f32)
+								.build(&ui, &mut exposure_settings.adaptation_speed);
+							ui.text(format!(
+								"average luminance: {:.4}, exposure: {:.4}",
+								exposure_data.average_luminance, exposure_data.exposure
+							));
 						})
 						.expect("Failed to create the ui");
+
+					imgui::Window::new("GPU timings")
+						.size([300.0, 250.0], imgui::Condition::FirstUseEver)
+						.build(&ui, || {
+							if !engine.timestamps.borrow().is_supported() {
+								ui.text("The queue doesn't support timestamps.");
+								return;
+							}
+							let last_frame_time = gpu_frame_times.last().copied().unwrap_or(0.0);
+							imgui::PlotLines::new(&ui, "frame (ms)", &gpu_frame_times)
+								.overlay_text(format!("{:.3} ms", last_frame_time))
+								.scale_min(0.0)
+								.graph_size([0.0, 60.0])
+								.build();
+							if let Some(table) = ui.begin_table_with_flags(
+								"pass timings",
+								2,
+								imgui::TableFlags::BORDERS | imgui::TableFlags::ROW_BG,
+							) {
+								ui.table_setup_column("pass");
+								ui.table_setup_column("ms");
+								ui.table_headers_row();
+								for timing in gpu_timings.iter() {
+									ui.table_next_column();
+									ui.text(format!(
+										"{}{}",
+										"  ".repeat(timing.depth),
+										timing.name
+									));
+									ui.table_next_column();
+									ui.text(format!("{:.3}", timing.milliseconds));
+								}
+								table.end();
+							}
+						});
 					pbr_param.albedo.x = albedo_color[0];
 					pbr_param.albedo.y = albedo_color[1];
 					pbr_param.albedo.z = albedo_color[2];
 					pbr_param.emissive_color.x = emissive_color[0];
 					pbr_param.emissive_color.y = emissive_color[1];
 					pbr_param.emissive_color.z = emissive_color[2];
-					pbr_param.emissive_intensity = emissive_intensity;
+					pbr_param.emissive_intensity = light_units
+						.emissive_intensity(pbr_param.emissive_color, emissive_intensity);
+					pbr_param.lights[0].light_color = light_units.point_light_color(
+						uv::Vec3::new(light_color[0], light_color[1], light_color[2]),
+						light_intensity,
+					);
 
-					uniform_buffer.write(
-						buffer::aligne_offset(
-							(size_of::<uv::Mat4>() * 2 + size_of::<uv::Vec4>() * 2) as u64,
-						),
+					engine.wait_for_frame();
+					uniform_buffers[engine.current_frame].write(
+						buffer::aligne_offset((size_of::<uv::Mat4>() * 2) as u64),
 						vec![pbr_param],
 					);
-					uniform_buffer.write(
-						(size_of::<uv::Mat4>() * 2) as u64,
-						vec![uv::Vec4::new(
-							bloom_threshold,
-							bloom_threshold - bloom_knee,
-							bloom_knee * 2.0f32,
-							0.25f32 / bloom_knee,
-						)],
-					);
-					uniform_buffer.write(
-						(size_of::<uv::Mat4>() * 2 + size_of::<uv::Vec4>()) as u64,
-						vec![uv::Vec4::new(
-							bloom_intensity * 2.0,
-							combine_constant,
-							0.0,
-							0.0,
-						)],
-					);
+					bloom_pass
+						.borrow_mut()
+						.set_settings(&engine, bloom_settings);
+					if load_lens_dirt {
+						if let Err(error) = bloom_pass
+							.borrow_mut()
+							.set_lens_dirt(&engine, Some(std::path::Path::new(&lens_dirt_path)))
+						{
+							println!("Failed to load the lens dirt {}: {}", lens_dirt_path, error);
+						}
+					} else if clear_lens_dirt {
+						bloom_pass
+							.borrow_mut()
+							.set_lens_dirt(&engine, None)
+							.expect("Failed to clear the lens dirt.");
+					}
+					if load_color_lut {
+						if let Err(error) = bloom_pass
+							.borrow_mut()
+							.set_color_lut(&engine, Some(std::path::Path::new(&color_lut_path)))
+						{
+							println!("Failed to load the color lut {}: {}", color_lut_path, error);
+						}
+					} else if clear_color_lut {
+						bloom_pass
+							.borrow_mut()
+							.set_color_lut(&engine, None)
+							.expect("Failed to clear the color lut.");
+					}
+					if load_blue_noise {
+						if let Err(error) = bloom_pass
+							.borrow_mut()
+							.set_blue_noise(&engine, Some(std::path::Path::new(&blue_noise_path)))
+						{
+							println!(
+								"Failed to load the blue noise {}: {}",
+								blue_noise_path, error
+							);
+						}
+					} else if reset_blue_noise {
+						bloom_pass
+							.borrow_mut()
+							.set_blue_noise(&engine, None)
+							.expect("Failed to reset the blue noise.");
+					}
+					if load_fft_kernel {
+						if let Err(error) = bloom_pass
+							.borrow_mut()
+							.set_fft_kernel(&engine, Some(std::path::Path::new(&fft_kernel_path)))
+						{
+							println!(
+								"Failed to load the fft kernel {}: {}",
+								fft_kernel_path, error
+							);
+						}
+					} else if reset_fft_kernel {
+						bloom_pass
+							.borrow_mut()
+							.set_fft_kernel(&engine, None)
+							.expect("Failed to reset the fft kernel.");
+					}
+					bloom_pass
+						.borrow_mut()
+						.exposure
+						.set_settings(exposure_settings, delta_time.as_secs_f32());
 
 					platform.prepare_render(&ui, &engine.window.as_ref().unwrap().window);
 					let draw_data = ui.render();
-					let mut tmp_current_image = current_image as usize;
 					render::render_func(
-						&engine,
-						&vertex_buffer,
-						&index_buffer,
-						&mut tmp_current_image,
-						index_count,
+						&mut engine,
+						&mesh,
 						&mut renderer,
 						draw_data,
-						&mut downsample_image,
-						&mut bloom_data,
+						&post_process.borrow(),
+						&mut transient_images.borrow_mut(),
 					);
-					current_image = tmp_current_image as u32;
-				} else if engine.minimized == true {
+				} else if engine.minimized {
 					std::thread::sleep(std::time::Duration::from_millis(10));
 				}
 			}
@@ -550,3 +837,37 @@ fn main() {
 		}
 	});
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn args<'a>(args: &'a [&str]) -> impl Iterator<Item = String> + 'a {
+		args.iter().map(|arg| arg.to_string())
+	}
+
+	#[test]
+	fn headless_arguments_take_the_extent() {
+		assert_eq!(headless_arguments(args(&["vulkan_bloom"])), None);
+		assert_eq!(
+			headless_arguments(args(&["vulkan_bloom", "--headless", "out.png"])),
+			Some(("out.png".to_string(), HEADLESS_DEFAULT_EXTENT))
+		);
+		assert_eq!(
+			headless_arguments(args(&["vulkan_bloom", "--headless", "out.png", "640x360"])),
+			Some((
+				"out.png".to_string(),
+				vk::Extent2D::builder().width(640).height(360).build()
+			))
+		);
+		assert_eq!(parse_extent("640"), None);
+		assert_eq!(parse_extent("0x360"), None);
+		assert_eq!(parse_extent("640x-1"), None);
+	}
+
+	#[test]
+	#[should_panic]
+	fn headless_arguments_reject_a_bad_extent() {
+		headless_arguments(args(&["vulkan_bloom", "--headless", "out.png", "large"]));
+	}
+}
@@ -0,0 +1,169 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use ash::vk;
+
+use crate::vulkan_engine::{
+	self,
+	render_graph::{
+		BufferHandle, BufferUsage, GraphImage, ImageHandle, ImageUsage, PassResources, RenderGraph,
+		TransientImageDesc,
+	},
+};
+
+/// Color of the images passed between the effects of a `PostProcessStack`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PostProcessFormat {
+	/// Scene referred color, in `R16G16B16A16_SFLOAT`.
+	Hdr = 0,
+	/// Tonemapped and gamma corrected color, in the format of the swapchain.
+	Ldr = 1,
+}
+
+/// An image an effect reads or writes, in `GENERAL` layout.
+pub type PostProcessTarget = GraphImage;
+
+/// A pass of an effect, with the graph resources it uses.
+pub struct PostProcessPass {
+	/// Name of its timestamp scope, consecutive passes of the same name share it.
+	pub name: &'static str,
+	pub images: Vec<(ImageHandle, ImageUsage)>,
+	pub buffers: Vec<(BufferHandle, BufferUsage)>,
+}
+
+/// A compute effect of a `PostProcessStack`, resized with the swapchain by the stack.
+pub trait PostProcessEffect: vulkan_engine::Resizable {
+	fn name(&self) -> &str;
+
+	/// Format of the image the effect reads: the output of the previous effect, or the
+	/// HDR scene for the first one.
+	fn input(&self) -> PostProcessFormat;
+
+	/// Format of the image the effect writes: the input of the next effect, or the
+	/// swapchain image for the last one.
+	fn output(&self) -> PostProcessFormat;
+
+	/// Imports the resources of the effect in `graph` and returns its passes from
+	/// `input` to `output`, both the size of the swapchain and in `GENERAL` layout.
+	/// `input` is sampled with `swapchain_image_sampler`.
+	fn passes(
+		&mut self,
+		engine: &vulkan_engine::VulkanEngine,
+		graph: &mut RenderGraph,
+		input: ImageHandle,
+		output: ImageHandle,
+	) -> Vec<PostProcessPass>;
+
+	/// Records the pass `index` of the last `passes`. The passes culled by the graph are
+	/// skipped, the others are recorded in order with the barriers between them.
+	fn record_pass(
+		&mut self,
+		engine: &vulkan_engine::VulkanEngine,
+		command_buffer: &vk::CommandBuffer,
+		index: usize,
+		resources: &PassResources,
+	);
+}
+
+/// The effects run in order between the HDR render pass and the UI pass, as passes of
+/// the frame's `RenderGraph`. The intermediate images between the effects are
+/// transient images of the graph.
+pub struct PostProcessStack {
+	effects: Vec<Rc<RefCell<dyn PostProcessEffect>>>,
+}
+
+impl PostProcessStack {
+	pub fn new() -> PostProcessStack {
+		PostProcessStack {
+			effects: Vec::new(),
+		}
+	}
+
+	/// Appends `effect`, its input must match the output of the last effect, or be
+	/// `PostProcessFormat::Hdr` for the first one.
+	pub fn push(&mut self, effect: Rc<RefCell<dyn PostProcessEffect>>) {
+		let previous_output = self
+			.effects
+			.last()
+			.map_or(PostProcessFormat::Hdr, |last| last.borrow().output());
+		assert!(
+			effect.borrow().input() == previous_output,
+			"The post process effect {} reads {:?} but the previous one writes {:?}.",
+			effect.borrow().name(),
+			effect.borrow().input(),
+			previous_output
+		);
+		self.effects.push(effect);
+	}
+
+	/// Adds the passes of every effect from `scene`, written by the HDR render pass, to
+	/// `output`. The passes of an effect are timed in a scope named after it.
+	pub fn add_passes<'a>(
+		&self,
+		engine: &'a vulkan_engine::VulkanEngine,
+		graph: &mut RenderGraph<'a>,
+		scene: ImageHandle,
+		output: ImageHandle,
+	) {
+		let last_output = self
+			.effects
+			.last()
+			.expect("The post process stack is empty.")
+			.borrow()
+			.output();
+		assert!(
+			last_output == PostProcessFormat::Ldr,
+			"The last post process effect must write the swapchain format."
+		);
+
+		let mut input = scene;
+		let last = self.effects.len() - 1;
+		for (i, effect) in self.effects.iter().enumerate() {
+			let effect_output = if i == last {
+				output
+			} else {
+				let format = match effect.borrow().output() {
+					PostProcessFormat::Hdr => vk::Format::R16G16B16A16_SFLOAT,
+					PostProcessFormat::Ldr => engine.surface.desired_format,
+				};
+				graph.create_image(
+					&format!("{} output", effect.borrow().name()),
+					TransientImageDesc {
+						format,
+						extent: engine.surface.surface_resolution,
+						usage: vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+					},
+				)
+			};
+			let name = effect.borrow().name().to_string();
+			graph.push_scope(&name);
+			let passes = effect
+				.borrow_mut()
+				.passes(engine, graph, input, effect_output);
+			for (index, pass) in passes.into_iter().enumerate() {
+				let effect = effect.clone();
+				graph.add_pass(
+					pass.name,
+					pass.images,
+					pass.buffers,
+					move |command_buffer, resources| {
+						effect
+							.borrow_mut()
+							.record_pass(engine, command_buffer, index, resources);
+					},
+				);
+			}
+			graph.pop_scope();
+			input = effect_output;
+		}
+	}
+}
+
+impl vulkan_engine::Resizable for PostProcessStack {
+	/// Resizes every effect.
+	fn resize(&mut self, engine: &vulkan_engine::VulkanEngine, extent: vk::Extent2D) {
+		for effect in &self.effects {
+			effect.borrow_mut().resize(engine, extent);
+		}
+	}
+}
@@ -3,36 +3,84 @@ extern crate ultraviolet as uv;
 use ash::vk;
 
 use crate::{
-	bloom,
-	vulkan_engine::{self, buffer, image},
+	post_process,
+	vulkan_engine::{
+		self, buffer,
+		query::TimestampScope,
+		render_graph::{GraphImage, ImageState, ImageUsage, RenderGraph, TransientImagePool},
+	},
 };
 
+/// The scene drawn by the HDR render pass.
+pub struct Mesh {
+	pub vertex_buffer: buffer::Buffer,
+	pub index_buffer: buffer::Buffer,
+	pub index_count: u32,
+}
+
+/// Records and submits `engine.current_frame`, then moves to the next frame.
+/// `engine.wait_for_frame` must have been called before its resources were written.
+///
+/// The frame is recorded as a `RenderGraph`, whose transient images are kept in
+/// `transient_images` between the frames.
+///
+/// An out of date, suboptimal or lost swapchain requests its recreation, the frame is
+/// skipped if no image could be acquired. In the headless mode, the frame renders to
+/// the offscreen image `current_frame` and nothing is presented.
+///
+/// Returns the index of the swapchain image rendered to, `None` if the frame was
+/// skipped.
 pub fn render_func(
-	engine: &vulkan_engine::VulkanEngine,
-	vertex_buffer: &buffer::Buffer,
-	index_buffer: &buffer::Buffer,
-	current_image_save: &mut usize,
-	index_count: u32,
+	engine: &mut vulkan_engine::VulkanEngine,
+	mesh: &Mesh,
 	renderer: &mut imgui_rs_vulkan_renderer::Renderer,
 	draw_data: &imgui::DrawData,
-	bloom_images: &mut Vec<image::Image>,
-	bloom_data: &mut bloom::BloomConstant,
-) {
-	let current_image = *current_image_save;
-	unsafe {
-		let tmp = engine
-			.swapchain
-			.swapchain_loader
-			.acquire_next_image(
+	post_process: &post_process::PostProcessStack,
+	transient_images: &mut TransientImagePool,
+) -> Option<usize> {
+	let frame = engine.current_frame;
+	let headless = engine.is_headless();
+	let (current_image, suboptimal) = if headless {
+		(frame, false)
+	} else {
+		let acquire_result = unsafe {
+			engine.swapchain.swapchain_loader.acquire_next_image(
 				engine.swapchain.swapchain,
-				std::u64::MAX,
-				engine.image_available_semaphore.semaphores[0],
-				engine.fences.fences[current_image],
+				u64::MAX,
+				engine.image_available_semaphore.semaphores[frame],
+				vk::Fence::null(),
 			)
-			.expect("Failed to acquire the next swapchain image");
-		*current_image_save = tmp.0 as usize;
+		};
+		// A suboptimal swapchain can still be presented to, it's recreated after this
+		// frame
+		match acquire_result {
+			Ok((image_index, suboptimal)) => (image_index as usize, suboptimal),
+			Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+				engine.request_swapchain_recreation(false);
+				return None;
+			}
+			Err(vk::Result::ERROR_SURFACE_LOST_KHR) => {
+				engine.request_swapchain_recreation(true);
+				return None;
+			}
+			Err(error) => panic!("Failed to acquire the next swapchain image: {}", error),
+		}
 	};
 
+	// The image may still be rendered by another frame when there are more frames in
+	// flight than swapchain images, or when they are acquired out of order
+	let image_fence = engine.images_in_flight[current_image];
+	if image_fence != vk::Fence::null() {
+		unsafe {
+			engine
+				.device
+				.device
+				.wait_for_fences(&[image_fence], true, u64::MAX)
+				.expect("Failed to wait for fences.");
+		};
+	}
+	engine.images_in_flight[current_image] = engine.fences.fences[frame];
+
 	let clear_value = [
 		vk::ClearValue {
 			color: vk::ClearColorValue {
@@ -50,109 +98,173 @@ pub fn render_func(
 		.extent(engine.surface.surface_resolution)
 		.build();
 
-	let hdr_render_pass_begin_info = vk::RenderPassBeginInfo::builder()
-		.render_pass(engine.hdr_renderpass.renderpass)
-		.framebuffer(engine.swapchain.swapchain_hdr_framebuffers[current_image])
-		.render_area(render_area)
-		.clear_values(&clear_value)
-		.build();
-
-	let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
-		.render_pass(engine.renderpass.renderpass)
-		.framebuffer(engine.swapchain.swapchain_framebuffers[current_image])
-		.render_area(render_area)
-		.clear_values(&clear_value)
-		.build();
-
-	let mut command_buffer = engine.command_builder.build();
-	unsafe {
-		engine.device.device.cmd_begin_render_pass(
-			command_buffer,
-			&hdr_render_pass_begin_info,
-			vk::SubpassContents::INLINE,
-		);
-		engine.device.device.cmd_bind_pipeline(
-			command_buffer,
-			vk::PipelineBindPoint::GRAPHICS,
-			engine.graphics_pipelines[0].pipeline,
-		);
-		engine.device.device.cmd_bind_descriptor_sets(
-			command_buffer,
-			vk::PipelineBindPoint::GRAPHICS,
-			engine.graphics_pipelines[0].pipeline_layout,
-			0,
-			&engine.descriptors[0].descriptor_set,
-			&[],
+	let command_buffer = engine.begin_frame_command_buffer();
+	{
+		let engine: &vulkan_engine::VulkanEngine = engine;
+		let mut graph = RenderGraph::new();
+		let hdr_image = &engine.swapchain.swapchain_hdr_images[current_image];
+		let scene = graph.import_image(
+			"HDR scene",
+			GraphImage {
+				image: hdr_image.image,
+				view: hdr_image.image_view,
+				extent: engine.surface.surface_resolution,
+				mip_level: 0,
+			},
+			ImageState::UNDEFINED,
 		);
-		engine.device.device.cmd_bind_vertex_buffers(
-			command_buffer,
-			0,
-			&[*vertex_buffer.buffer],
-			&[0],
+		let swapchain_image = graph.import_image(
+			"Swapchain image",
+			GraphImage {
+				image: engine.swapchain.swapchain_images[current_image],
+				view: engine.swapchain.swapchain_image_views[current_image],
+				extent: engine.surface.surface_resolution,
+				mip_level: 0,
+			},
+			if headless {
+				// The previous frame and read back of the image are waited by the CPU
+				ImageState::UNDEFINED
+			} else {
+				ImageState::acquired(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+			},
 		);
+		if headless {
+			// For `VulkanEngine::read_output`
+			graph.export_image(swapchain_image, Some(ImageUsage::TransferRead));
+		} else {
+			// Left in PRESENT_SRC_KHR by the ui pass
+			graph.export_image(swapchain_image, None);
+		}
 
-		engine.device.device.cmd_bind_index_buffer(
-			command_buffer,
-			*index_buffer.buffer,
-			0,
-			vk::IndexType::UINT32,
-		);
+		graph.add_pass(
+			"Scene",
+			vec![(
+				scene,
+				ImageUsage::ColorAttachment {
+					initial_layout: vk::ImageLayout::UNDEFINED,
+					final_layout: vk::ImageLayout::GENERAL,
+				},
+			)],
+			Vec::new(),
+			|command_buffer, _| unsafe {
+				let hdr_render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+					.render_pass(engine.hdr_renderpass.renderpass)
+					.framebuffer(engine.swapchain.swapchain_hdr_framebuffers[current_image])
+					.render_area(render_area)
+					.clear_values(&clear_value)
+					.build();
+				engine.device.device.cmd_begin_render_pass(
+					*command_buffer,
+					&hdr_render_pass_begin_info,
+					vk::SubpassContents::INLINE,
+				);
+				engine.device.device.cmd_bind_pipeline(
+					*command_buffer,
+					vk::PipelineBindPoint::GRAPHICS,
+					engine.graphics_pipelines[0].pipeline,
+				);
+				engine.device.device.cmd_bind_descriptor_sets(
+					*command_buffer,
+					vk::PipelineBindPoint::GRAPHICS,
+					engine.graphics_pipelines[0].pipeline_layout,
+					0,
+					&engine.descriptors[frame].descriptor_set,
+					&[],
+				);
+				engine.device.device.cmd_bind_vertex_buffers(
+					*command_buffer,
+					0,
+					&[*mesh.vertex_buffer.buffer],
+					&[0],
+				);
 
-		engine
-			.device
-			.device
-			.cmd_draw_indexed(command_buffer, index_count, 1, 0, 0, 0);
+				engine.device.device.cmd_bind_index_buffer(
+					*command_buffer,
+					*mesh.index_buffer.buffer,
+					0,
+					vk::IndexType::UINT32,
+				);
 
-		// renderer.cmd_draw(command_buffer, draw_data).expect("Failed to draw the ui.");
+				engine.device.device.cmd_draw_indexed(
+					*command_buffer,
+					mesh.index_count,
+					1,
+					0,
+					0,
+					0,
+				);
 
-		engine.device.device.cmd_end_render_pass(command_buffer);
+				engine.device.device.cmd_end_render_pass(*command_buffer);
+			},
+		);
 
-		//BLOOM BEGIN
+		post_process.add_passes(engine, &mut graph, scene, swapchain_image);
 
-		bloom::bloom(
-			engine,
-			&mut command_buffer,
-			*current_image_save,
-			bloom_images,
-			bloom_data,
-		);
+		graph.add_pass(
+			"UI",
+			vec![(
+				swapchain_image,
+				ImageUsage::ColorAttachment {
+					initial_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+					final_layout: engine.surface.final_layout(),
+				},
+			)],
+			Vec::new(),
+			|command_buffer, _| unsafe {
+				let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+					.render_pass(engine.renderpass.renderpass)
+					.framebuffer(engine.swapchain.swapchain_framebuffers[current_image])
+					.render_area(render_area)
+					.clear_values(&clear_value)
+					.build();
+				engine.device.device.cmd_begin_render_pass(
+					*command_buffer,
+					&render_pass_begin_info,
+					vk::SubpassContents::INLINE,
+				);
 
-		//BLOOM END
+				renderer
+					.cmd_draw(*command_buffer, draw_data)
+					.expect("Failed to draw the ui.");
 
-		engine.device.device.cmd_begin_render_pass(
-			command_buffer,
-			&render_pass_begin_info,
-			vk::SubpassContents::INLINE,
+				engine.device.device.cmd_end_render_pass(*command_buffer);
+			},
 		);
 
-		renderer
-			.cmd_draw(command_buffer, draw_data)
-			.expect("Failed to draw the ui.");
-
-		engine.device.device.cmd_end_render_pass(command_buffer);
+		let _scope = TimestampScope::begin(engine, &command_buffer, "Frame");
+		graph.execute(engine, &command_buffer, transient_images);
+	}
 
+	let command_buffers = [command_buffer];
+	// Without a swapchain, there is no image to wait for nor to present
+	let (wait_semaphores, signal_semaphores) = if headless {
+		(Vec::new(), Vec::new())
+	} else {
+		(
+			vec![engine.image_available_semaphore.semaphores[frame]],
+			vec![engine.render_finished_semaphore.semaphores[frame]],
+		)
+	};
+	let wait_dst_stage_mask =
+		vec![vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT; wait_semaphores.len()];
+	unsafe {
 		engine
 			.device
 			.device
 			.end_command_buffer(command_buffer)
 			.expect("Failed to end a command Buffer.");
-		engine
-			.device
-			.device
-			.wait_for_fences(&[engine.fences.fences[current_image]], true, std::u64::MAX)
-			.expect("Failed to wait for fences.");
 
 		let submit_info = vk::SubmitInfo::builder()
-			.command_buffers(&[command_buffer])
-			.wait_semaphores(&engine.image_available_semaphore.semaphores)
-			.signal_semaphores(&engine.render_finished_semaphore.semaphores)
-			.wait_dst_stage_mask(&[vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT])
+			.command_buffers(&command_buffers)
+			.wait_semaphores(&wait_semaphores)
+			.signal_semaphores(&signal_semaphores)
+			.wait_dst_stage_mask(&wait_dst_stage_mask)
 			.build();
 
 		engine
 			.device
 			.device
-			.reset_fences(&engine.fences.fences)
+			.reset_fences(&[engine.fences.fences[frame]])
 			.expect("Failed to reset fences.");
 		engine
 			.device
@@ -160,35 +272,36 @@ pub fn render_func(
 			.queue_submit(
 				engine.device.graphic_queue,
 				&[submit_info],
-				engine.fences.fences[current_image],
+				engine.fences.fences[frame],
 			)
 			.expect("Failed to submit a command buffer to the graphics queue.");
+	};
+
+	if !headless {
+		let swapchains = [engine.swapchain.swapchain];
+		let image_indices = [current_image as u32];
 		let present_info = vk::PresentInfoKHR::builder()
-			.swapchains(&[engine.swapchain.swapchain])
-			.wait_semaphores(&engine.render_finished_semaphore.semaphores)
-			.image_indices(&[current_image as u32])
+			.swapchains(&swapchains)
+			.wait_semaphores(&signal_semaphores)
+			.image_indices(&image_indices)
 			.build();
-		engine
-			.swapchain
-			.swapchain_loader
-			.queue_present(engine.device.present_queue, &present_info)
-			.expect("Failed to present an image to the present queue.");
-
-		engine
-			.device
-			.device
-			.wait_for_fences(&[engine.fences.fences[current_image]], true, std::u64::MAX)
-			.expect("Failed to wait for fences.");
-		engine
-			.device
-			.device
-			.reset_fences(&engine.fences.fences)
-			.expect("Failed to reset fences.");
-		engine.device.device.device_wait_idle().unwrap();
-		engine.device.device.free_command_buffers(
-			engine.command_builder.command_pool.command_pool,
-			&[command_buffer],
-		)
-	};
-	*current_image_save = (*current_image_save + 1) % engine.swapchain.max_image_in_flight;
+		let present_result = unsafe {
+			engine
+				.swapchain
+				.swapchain_loader
+				.queue_present(engine.device.present_queue, &present_info)
+		};
+		match present_result {
+			Ok(present_suboptimal) => {
+				if suboptimal || present_suboptimal {
+					engine.request_swapchain_recreation(false);
+				}
+			}
+			Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => engine.request_swapchain_recreation(false),
+			Err(vk::Result::ERROR_SURFACE_LOST_KHR) => engine.request_swapchain_recreation(true),
+			Err(error) => panic!("Failed to present an image to the present queue: {}", error),
+		}
+	}
+	engine.current_frame = (frame + 1) % engine.frames_in_flight;
+	Some(current_image)
 }
@@ -12,7 +12,9 @@ pub struct Buffer {
 	pub buffer: Rc<vk::Buffer>,
 	pub block: Option<MemoryBlock<vk::DeviceMemory>>,
 	pub data_size: u64,
+	#[allow(dead_code)]
 	pub allocated_size: u64,
+	#[allow(dead_code)]
 	pub memory_requierments: vk::MemoryRequirements,
 	device: Arc<ash::Device>,
 	allocator: Arc<Mutex<GpuAllocator<vk::DeviceMemory>>>,
@@ -42,6 +44,7 @@ impl Buffer {
 		allocation_type: UsageFlags,
 	) -> Buffer {
 		#[cfg(debug_assertions)]
+		#[allow(clippy::manual_is_multiple_of)]
 		if usage & vk::BufferUsageFlags::UNIFORM_BUFFER == vk::BufferUsageFlags::UNIFORM_BUFFER
 			&& size % 64 != 0
 		{
@@ -242,6 +245,7 @@ pub fn aligne_offset(offset: u64) -> u64 {
 	(offset - offset % 64) + 64
 }
 
+#[allow(dead_code)]
 pub struct BufferOffsetRange {
 	pub buffer_ref: Rc<Buffer>,
 	pub offset: usize,
@@ -162,13 +162,11 @@ impl DescriptorSet {
 			.dst_binding(dst_binding)
 			.dst_array_element(0)
 			.descriptor_type(self.bindings_info[dst_binding as usize].descriptor_type);
-		if buffer_info.is_some() {
-			write_descriptor_builder =
-				write_descriptor_builder.buffer_info(buffer_info.as_ref().unwrap());
+		if let Some(buffer_info) = buffer_info.as_ref() {
+			write_descriptor_builder = write_descriptor_builder.buffer_info(buffer_info);
 		}
-		if image_info.is_some() {
-			write_descriptor_builder =
-				write_descriptor_builder.image_info(image_info.as_ref().unwrap());
+		if let Some(image_info) = image_info.as_ref() {
+			write_descriptor_builder = write_descriptor_builder.image_info(image_info);
 		}
 		let write_descriptor = write_descriptor_builder.build();
 
@@ -22,6 +22,7 @@ pub struct Device {
 	pub allocator: Arc<Mutex<GpuAllocator<vk::DeviceMemory>>>,
 	pub queue_family_index: u32,
 	pub graphic_queue: vk::Queue,
+	#[allow(dead_code)]
 	pub compute_queue: vk::Queue,
 	pub transfer_queue: vk::Queue,
 	pub present_queue: vk::Queue,
@@ -195,13 +196,19 @@ impl Device {
 		}
 	}
 
-	fn required_extension_names() -> Vec<*const i8> {
-		[ash::extensions::khr::Swapchain::name().as_ptr()].to_vec()
+	/// The swapchain extension isn't enabled in the headless mode.
+	fn required_extension_names(headless: bool) -> Vec<*const i8> {
+		if headless {
+			Vec::new()
+		} else {
+			[ash::extensions::khr::Swapchain::name().as_ptr()].to_vec()
+		}
 	}
 
 	fn create_logical_device(
 		instance: &ash::Instance,
 		physical_device: vk::PhysicalDevice,
+		headless: bool,
 	) -> (ash::Device, u32, vk::Queue, vk::Queue, vk::Queue, vk::Queue) {
 		let family_index = Device::find_queue_family(instance, physical_device)
 			.family_index
@@ -230,7 +237,8 @@ impl Device {
 			.push_next(&mut physical_device_vulkan_12_features)
 			.build();
 
-		let device_extension = Device::required_extension_names();
+		let device_extension = Device::required_extension_names(headless);
+		#[allow(clippy::manual_c_str_literals)]
 		let _layer_names = unsafe {
 			[CStr::from_bytes_with_nul_unchecked(
 				b"VK_LAYER_KHRONOS_validation\0",
@@ -277,7 +285,7 @@ impl Device {
 		)
 	}
 
-	pub fn new(instance: &Instance) -> Device {
+	pub fn new(instance: &Instance, headless: bool) -> Device {
 		let physical_device = Device::pick_physical_device(&instance.instance);
 
 		let (
@@ -287,7 +295,7 @@ impl Device {
 			compute_queue,
 			transfer_queue,
 			present_queue,
-		) = Device::create_logical_device(&instance.instance, physical_device);
+		) = Device::create_logical_device(&instance.instance, physical_device, headless);
 
 		let device = Arc::new(device);
 
@@ -8,6 +8,7 @@ use super::{
 	buffer::Buffer,
 	command_buffer::{self, CommandBufferBuilder},
 	device::{self, Device},
+	render_graph,
 };
 
 pub struct Image {
@@ -234,9 +235,11 @@ impl Image {
 		old_layout: vk::ImageLayout,
 		new_layout: vk::ImageLayout,
 	) {
+		let (src_stage_mask, src_access_mask) = render_graph::layout_scope(old_layout);
+		let (dst_stage_mask, dst_access_mask) = render_graph::layout_scope(new_layout);
 		let image_memory_barrier = vk::ImageMemoryBarrier::builder()
-			.src_access_mask(vk::AccessFlags::empty())
-			.dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+			.src_access_mask(src_access_mask)
+			.dst_access_mask(dst_access_mask)
 			.old_layout(old_layout)
 			.new_layout(new_layout)
 			.src_queue_family_index(device.queue_family_index)
@@ -248,8 +251,8 @@ impl Image {
 		unsafe {
 			device.device.cmd_pipeline_barrier(
 				*command_buffer,
-				vk::PipelineStageFlags::TOP_OF_PIPE,
-				vk::PipelineStageFlags::TRANSFER,
+				src_stage_mask,
+				dst_stage_mask,
 				vk::DependencyFlags::empty(),
 				&[],
 				&[],
@@ -267,9 +270,11 @@ impl Image {
 		old_layout: vk::ImageLayout,
 		new_layout: vk::ImageLayout,
 	) {
+		let (src_stage_mask, src_access_mask) = render_graph::layout_scope(old_layout);
+		let (dst_stage_mask, dst_access_mask) = render_graph::layout_scope(new_layout);
 		let image_memory_barrier = vk::ImageMemoryBarrier::builder()
-			.src_access_mask(vk::AccessFlags::empty())
-			.dst_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+			.src_access_mask(src_access_mask)
+			.dst_access_mask(dst_access_mask)
 			.old_layout(old_layout)
 			.new_layout(new_layout)
 			.src_queue_family_index(device.queue_family_index)
@@ -281,8 +286,8 @@ impl Image {
 		unsafe {
 			device.device.cmd_pipeline_barrier(
 				*command_buffer,
-				vk::PipelineStageFlags::TOP_OF_PIPE,
-				vk::PipelineStageFlags::TRANSFER,
+				src_stage_mask,
+				dst_stage_mask,
 				vk::DependencyFlags::empty(),
 				&[],
 				&[],
@@ -452,8 +457,8 @@ impl Image {
 			.level_count(1)
 			.build();
 
-		let command_buffer = if command_buffer.is_some() {
-			*command_buffer.unwrap()
+		let command_buffer = if let Some(command_buffer) = command_buffer {
+			*command_buffer
 		} else {
 			command_builder.unwrap().build()
 		};
@@ -53,19 +53,25 @@ impl Drop for Instance {
 
 impl Instance {
 	#![allow(dead_code)]
-	fn required_extension_names(window: &Window) -> Vec<*const i8> {
-		let surface_extensions = ash_window::enumerate_required_extensions(&window.window).unwrap();
-		let mut extension_names_raw = surface_extensions
-			.iter()
-			.map(|ext| ext.as_ptr())
-			.collect::<Vec<_>>();
+	/// The surface extensions are only enabled with a window.
+	fn required_extension_names(window: Option<&Window>) -> Vec<*const i8> {
+		let mut extension_names_raw = match window {
+			Some(window) => ash_window::enumerate_required_extensions(&window.window)
+				.unwrap()
+				.iter()
+				.map(|ext| ext.as_ptr())
+				.collect::<Vec<_>>(),
+			None => Vec::new(),
+		};
 		extension_names_raw.push(DebugUtils::name().as_ptr());
 		extension_names_raw
 	}
 
-	unsafe fn create_instance(entry: &ash::Entry, window: &Window) -> ash::Instance {
+	unsafe fn create_instance(entry: &ash::Entry, window: Option<&Window>) -> ash::Instance {
+		#[allow(clippy::manual_c_str_literals)]
 		let app_name = CStr::from_bytes_with_nul_unchecked(b"Vulkan App\0");
 
+		#[allow(clippy::manual_c_str_literals)]
 		let _layer_names = [CStr::from_bytes_with_nul_unchecked(
 			b"VK_LAYER_KHRONOS_validation\0",
 		)];
@@ -137,7 +143,9 @@ impl Instance {
 		(debug_utils_loader, ash::vk::DebugUtilsMessengerEXT::null())
 	}
 
-	pub fn new(window: &Window) -> Instance {
+	/// Without a window, for the headless mode, no surface extension is required and
+	/// any ICD works, software ones like lavapipe included.
+	pub fn new(window: Option<&Window>) -> Instance {
 		let (entry, instance, debug_utils_loader, debug_messager) = unsafe {
 			let entry = ash::Entry::load()
 				.expect("Failed to load vulkan functions, is Vulkan SDK installed ?");
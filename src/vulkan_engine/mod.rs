@@ -7,6 +7,8 @@ pub mod image;
 pub mod instance;
 pub mod pipeline;
 pub mod push_constant;
+pub mod query;
+pub mod render_graph;
 pub mod renderpass;
 pub mod semaphore;
 pub mod shader_module;
@@ -14,7 +16,9 @@ pub mod surface;
 pub mod swapchain;
 pub mod window;
 
+use std::cell::RefCell;
 use std::ops::Add;
+use std::rc::Rc;
 
 use ash::vk;
 
@@ -50,12 +54,36 @@ pub struct PbrParameters {
 	pub lights: [Light; 1],
 }
 
+/// A resource whose size depends on the surface resolution.
+///
+/// Registered resources are rebuilt by `VulkanEngine::window_resized`, after the
+/// swapchain, while the device is idle.
+pub trait Resizable {
+	fn resize(&mut self, engine: &VulkanEngine, extent: vk::Extent2D);
+}
+
 pub struct VulkanEngine {
+	pub resizables: Vec<Rc<RefCell<dyn Resizable>>>,
+	/// Number of frames the CPU records while the GPU renders the previous ones.
+	pub frames_in_flight: usize,
+	/// Index of the frame being recorded, in `0..frames_in_flight`. Per frame
+	/// resources are indexed by it.
+	pub current_frame: usize,
+	/// One per frame in flight, signaled when the GPU is done with the frame.
 	pub fences: fence::Fence,
+	/// Fence of the frame that last rendered to each swapchain image, null if none.
+	pub images_in_flight: Vec<vk::Fence>,
+	/// One per frame in flight.
 	pub render_finished_semaphore: semaphore::Semaphore,
+	/// One per frame in flight.
 	pub image_available_semaphore: semaphore::Semaphore,
+	/// One per frame in flight, reset when the frame is recorded again.
+	pub frame_command_pools: Vec<command_buffer::CommandPool>,
+	/// Allocated from `frame_command_pools`.
+	pub frame_command_buffers: Vec<vk::CommandBuffer>,
+	/// GPU time of the scopes of the frames, see `query::TimestampScope`.
+	pub timestamps: RefCell<query::TimestampQueryPool>,
 	pub graphics_pipelines: Vec<pipeline::GraphicsPipeline>,
-	pub compute_pipelines: Vec<pipeline::ComputePipeline>,
 	pub push_constants: Vec<push_constant::PushConstant>,
 	pub descriptors: Vec<descriptor::DescriptorSet>,
 	pub command_builder: command_buffer::CommandBufferBuilder,
@@ -70,6 +98,9 @@ pub struct VulkanEngine {
 	pub new_extent: vk::Extent2D,
 	pub resized: bool,
 	pub minimized: bool,
+	/// Set when the surface returned `ERROR_SURFACE_LOST_KHR`, it is recreated by
+	/// `window_resized`.
+	pub surface_lost: bool,
 }
 
 impl VulkanEngine {
@@ -165,15 +196,15 @@ impl VulkanEngine {
 			.assembly_state_5(vk::PrimitiveTopology::TRIANGLE_LIST, false)
 			.add_viewport_7(
 				vk::Viewport::builder()
-					.height(self.window.as_ref().unwrap().window_extent.height as f32)
-					.width(self.window.as_ref().unwrap().window_extent.width as f32)
+					.height(self.surface.surface_resolution.height as f32)
+					.width(self.surface.surface_resolution.width as f32)
 					.min_depth(0.0f32)
 					.max_depth(1.0f32)
 					.build(),
 			)
 			.add_scissor_8(
 				vk::Rect2D::builder()
-					.extent(self.window.as_ref().unwrap().window_extent)
+					.extent(self.surface.surface_resolution)
 					.build(),
 			)
 			.rasterization_state_9(
@@ -233,12 +264,37 @@ impl VulkanEngine {
 		self.graphics_pipelines.push(pipeline);
 	}
 
-	pub fn new() -> VulkanEngine {
+	pub fn new(frames_in_flight: usize) -> VulkanEngine {
 		let window = window::Window::new(1080, 720, "Bloom");
 		// let window = window::Window::new(1920, 1080, "Bloom");
-		let instance = instance::Instance::new(&window);
-		let device = device::Device::new(&instance);
-		let surface = surface::Surface::new(&instance, &window, &device);
+		let extent = window.window_extent;
+		VulkanEngine::create(frames_in_flight, Some(window), extent)
+	}
+
+	/// Renders to offscreen images of `extent` instead of a window, one per frame in
+	/// flight, read back with `read_output`. No surface or swapchain extension is
+	/// used, so it runs on servers and software ICDs like lavapipe.
+	pub fn new_headless(frames_in_flight: usize, extent: vk::Extent2D) -> VulkanEngine {
+		VulkanEngine::create(frames_in_flight, None, extent)
+	}
+
+	/// `extent` is the size of the offscreen images without a window, the surface
+	/// gives it otherwise.
+	fn create(
+		frames_in_flight: usize,
+		window: Option<window::Window>,
+		extent: vk::Extent2D,
+	) -> VulkanEngine {
+		assert!(
+			frames_in_flight > 0,
+			"At least one frame must be in flight."
+		);
+		let instance = instance::Instance::new(window.as_ref());
+		let device = device::Device::new(&instance, window.is_none());
+		let surface = match window.as_ref() {
+			Some(window) => surface::Surface::new(&instance, window, &device),
+			None => surface::Surface::headless(&instance, extent, frames_in_flight as u32),
+		};
 		let mut depth_stencil_image =
 			VulkanEngine::create_depth_image(&instance, &device, &surface);
 		let hdr_renderpass = renderpass::RenderPass::builder()
@@ -298,7 +354,7 @@ impl VulkanEngine {
 				vk::AttachmentLoadOp::DONT_CARE,
 				vk::AttachmentStoreOp::DONT_CARE,
 				vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
-				vk::ImageLayout::PRESENT_SRC_KHR,
+				surface.final_layout(),
 			)
 			.add_subpasses(
 				vk::PipelineBindPoint::GRAPHICS,
@@ -338,10 +394,7 @@ impl VulkanEngine {
 			&surface,
 			&device,
 			Some(ash::vk::PresentModeKHR::FIFO),
-			Some(
-				vk::ImageUsageFlags::COLOR_ATTACHMENT
-					| vk::ImageUsageFlags::STORAGE,
-			),
+			Some(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::STORAGE),
 			Some(depth_stencil_image),
 			&hdr_renderpass,
 			&renderpass,
@@ -351,23 +404,52 @@ impl VulkanEngine {
 		let push_constants = Vec::with_capacity(1);
 		let graphics_pipelines = Vec::<pipeline::GraphicsPipeline>::with_capacity(1);
 
-		let compute_pipelines = Vec::<pipeline::ComputePipeline>::with_capacity(1);
-
-		let fences = fence::Fence::new(&device, false, swapchain.swapchain_framebuffers.len());
-
-		let render_finished_semaphore = semaphore::Semaphore::new(&device, 1);
-		let image_available_semaphore = semaphore::Semaphore::new(&device, 1);
+		// Signaled so the first wait of every frame returns immediately
+		let fences = fence::Fence::new(&device, true, frames_in_flight);
+		let images_in_flight = vec![vk::Fence::null(); swapchain.swapchain_images.len()];
+
+		let render_finished_semaphore = semaphore::Semaphore::new(&device, frames_in_flight);
+		let image_available_semaphore = semaphore::Semaphore::new(&device, frames_in_flight);
+
+		let mut frame_command_pools = Vec::with_capacity(frames_in_flight);
+		let mut frame_command_buffers = Vec::with_capacity(frames_in_flight);
+		for _ in 0..frames_in_flight {
+			let command_pool =
+				command_buffer::CommandPool::new(&device, vk::CommandPoolCreateFlags::TRANSIENT);
+			frame_command_buffers.push(
+				command_buffer::CommandBuffer::new(
+					&device,
+					&command_pool,
+					1,
+					vk::CommandBufferLevel::PRIMARY,
+				)
+				.command_buffer[0],
+			);
+			frame_command_pools.push(command_pool);
+		}
+		let timestamps = RefCell::new(query::TimestampQueryPool::new(
+			&instance,
+			&device,
+			frames_in_flight,
+		));
 
 		VulkanEngine {
+			resizables: Vec::new(),
+			frames_in_flight,
+			current_frame: 0,
+			images_in_flight,
+			frame_command_pools,
+			frame_command_buffers,
+			timestamps,
 			old_extent: surface.surface_resolution,
 			new_extent: surface.surface_resolution,
 			resized: false,
 			minimized: false,
+			surface_lost: false,
 			render_finished_semaphore,
 			image_available_semaphore,
 			fences,
 			graphics_pipelines,
-			compute_pipelines,
 			push_constants,
 			descriptors,
 			command_builder,
@@ -377,22 +459,97 @@ impl VulkanEngine {
 			surface,
 			device,
 			instance,
-			window: Some(window),
+			window,
 		}
 	}
 
-	pub fn window_resized(&mut self, current_image: &mut u32) {
-		*current_image = 0;
-		self.surface.surface_resolution = self.new_extent;
-		self.window.as_mut().unwrap().window_extent = self.new_extent;
-		let depth_image =
-			VulkanEngine::create_depth_image(&self.instance, &self.device, &self.surface);
+	pub fn is_headless(&self) -> bool {
+		self.window.is_none()
+	}
+
+	/// Waits until the GPU is done with the previous use of `current_frame`, after
+	/// which its resources can be written.
+	pub fn wait_for_frame(&self) {
+		unsafe {
+			self.device
+				.device
+				.wait_for_fences(&[self.fences.fences[self.current_frame]], true, u64::MAX)
+				.expect("Failed to wait for fences.");
+		};
+	}
+
+	/// Resets the command pool of `current_frame` and begins its command buffer, in
+	/// which the timestamp queries of the frame are read back and reset.
+	/// `wait_for_frame` must have been called.
+	pub fn begin_frame_command_buffer(&self) -> vk::CommandBuffer {
+		let command_buffer = self.frame_command_buffers[self.current_frame];
+		unsafe {
+			self.device
+				.device
+				.reset_command_pool(
+					self.frame_command_pools[self.current_frame].command_pool,
+					vk::CommandPoolResetFlags::empty(),
+				)
+				.expect("Failed to reset a CommandPool.");
+			self.device
+				.device
+				.begin_command_buffer(
+					command_buffer,
+					&vk::CommandBufferBeginInfo::builder()
+						.flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+						.build(),
+				)
+				.expect("Failed to begin the recording of a CommandBuffer.");
+		};
+		self.timestamps
+			.borrow_mut()
+			.begin_frame(self.current_frame, &command_buffer);
+		command_buffer
+	}
+
+	/// Called when the swapchain is out of date, suboptimal or its surface is lost.
+	/// The swapchain is recreated by `window_resized` at the size of the window, like
+	/// after a resize event.
+	pub fn request_swapchain_recreation(&mut self, surface_lost: bool) {
+		self.surface_lost |= surface_lost;
+		let size = self.window.as_ref().unwrap().window.inner_size();
+		if size.width == 0 || size.height == 0 {
+			// Recreated by the resize event that restores the window
+			self.minimized = true;
+			return;
+		}
+		self.new_extent = vk::Extent2D::builder()
+			.width(size.width)
+			.height(size.height)
+			.build();
+		self.resized = true;
+	}
+
+	pub fn window_resized(&mut self) {
 		unsafe {
 			self.device
 				.device
 				.device_wait_idle()
 				.expect("Failed to wait for the device to be idle.");
 		};
+		// The old surface is destroyed after the swapchain created from it. The formats
+		// are kept, the render passes and pipelines are built for them.
+		let _lost_surface = if self.surface_lost {
+			self.surface_lost = false;
+			let mut surface =
+				surface::Surface::new(&self.instance, self.window.as_ref().unwrap(), &self.device);
+			surface.surface_format = self.surface.surface_format;
+			surface.desired_format = self.surface.desired_format;
+			Some(std::mem::replace(&mut self.surface, surface))
+		} else {
+			None
+		};
+		self.surface.surface_resolution = self.new_extent;
+		if let Some(window) = self.window.as_mut() {
+			window.window_extent = self.new_extent;
+		}
+		let depth_image =
+			VulkanEngine::create_depth_image(&self.instance, &self.device, &self.surface);
 		self.swapchain.recreate(
 			&self.device,
 			&self.surface,
@@ -400,6 +557,7 @@ impl VulkanEngine {
 			&self.renderpass,
 			Some(depth_image),
 		);
+		self.images_in_flight = vec![vk::Fence::null(); self.swapchain.swapchain_images.len()];
 		let mut pipeline_vec = Vec::with_capacity(self.graphics_pipelines.len());
 		for i in (0..self.graphics_pipelines.len()).rev() {
 			let pipeline = self.graphics_pipelines.remove(i);
@@ -412,13 +570,106 @@ impl VulkanEngine {
 			pipeline_vec.swap(i, j);
 		}
 		self.graphics_pipelines = pipeline_vec;
+
+		let extent = self.new_extent;
+		for resource in self.resizables.iter() {
+			resource.borrow_mut().resize(self, extent);
+		}
+	}
+
+	/// Copies the offscreen image `image_index` of the headless mode to the CPU, after
+	/// the frame that rendered to it. Its pixels are in `surface::HEADLESS_FORMAT`.
+	pub fn read_output(&self, image_index: usize) -> ::image::RgbaImage {
+		assert!(
+			self.is_headless(),
+			"Only the offscreen images of the headless mode can be read back."
+		);
+		let image_fence = self.images_in_flight[image_index];
+		if image_fence != vk::Fence::null() {
+			unsafe {
+				self.device
+					.device
+					.wait_for_fences(&[image_fence], true, u64::MAX)
+					.expect("Failed to wait for fences.");
+			};
+		}
+
+		let extent = self.surface.surface_resolution;
+		let mut staging_buffer = buffer::Buffer::new(
+			&self.device,
+			vk::BufferCreateFlags::empty(),
+			(extent.width * extent.height * 4) as u64,
+			vk::BufferUsageFlags::TRANSFER_DST,
+			vk::SharingMode::EXCLUSIVE,
+			gpu_alloc::UsageFlags::DOWNLOAD,
+		);
+		let copy_region = [vk::BufferImageCopy {
+			buffer_offset: 0,
+			buffer_row_length: 0,
+			buffer_image_height: 0,
+			image_subresource: vk::ImageSubresourceLayers::builder()
+				.aspect_mask(vk::ImageAspectFlags::COLOR)
+				.layer_count(1)
+				.build(),
+			image_offset: vk::Offset3D::builder().build(),
+			image_extent: vk::Extent3D::builder()
+				.width(extent.width)
+				.height(extent.height)
+				.depth(1)
+				.build(),
+		}];
+
+		// The frame left the image in TRANSFER_SRC_OPTIMAL, visible to the transfers
+		let command_buffer = self.command_builder.build();
+		unsafe {
+			self.device.device.cmd_copy_image_to_buffer(
+				command_buffer,
+				self.swapchain.swapchain_images[image_index],
+				vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+				*staging_buffer.buffer,
+				&copy_region,
+			);
+			self.device
+				.device
+				.end_command_buffer(command_buffer)
+				.expect("Failed to stop a command buffer.");
+		};
+
+		let command_buffers = [command_buffer];
+		let submit_info = [vk::SubmitInfo::builder()
+			.command_buffers(&command_buffers)
+			.build()];
+		unsafe {
+			self.device
+				.device
+				.queue_submit(self.device.graphic_queue, &submit_info, vk::Fence::null())
+				.expect("Failed to submit to the graphics queue.");
+			self.device
+				.device
+				.queue_wait_idle(self.device.graphic_queue)
+				.expect("Failed to wait queue idle");
+
+			self.device.device.free_command_buffers(
+				self.command_builder.command_pool.command_pool,
+				&[command_buffer],
+			);
+		};
+
+		let mut pixels = Vec::<u8>::new();
+		staging_buffer.read(0, &mut pixels);
+		::image::RgbaImage::from_raw(extent.width, extent.height, pixels)
+			.expect("Failed to create an image from the read back pixels.")
+	}
+
+	pub fn register_resizable(&mut self, resource: Rc<RefCell<dyn Resizable>>) {
+		self.resizables.push(resource);
 	}
 }
 
 pub fn compile_shaders() {
 	use std::fs;
 
-	let arg = std::path::Path::new(&std::env::args().into_iter().next().unwrap())
+	let arg = std::path::Path::new(&std::env::args().next().unwrap())
 		.parent()
 		.unwrap()
 		.parent()
@@ -552,3 +803,149 @@ pub fn compile_shaders() {
 
 // 	vertices
 // }
+
+#[cfg(test)]
+mod tests {
+	use std::ffi::CStr;
+
+	use super::render_graph::{
+		GraphImage, ImageState, ImageUsage, RenderGraph, TransientImagePool,
+	};
+	use super::*;
+
+	/// Whether a Vulkan loader and a device are present, with the validation layer
+	/// `instance::Instance` enables in debug. The GPU tests are skipped otherwise.
+	fn vulkan_device_available() -> bool {
+		let entry = match unsafe { ash::Entry::load() } {
+			Ok(entry) => entry,
+			Err(_) => return false,
+		};
+		if cfg!(debug_assertions) {
+			let layers = entry
+				.enumerate_instance_layer_properties()
+				.unwrap_or_default();
+			let validation = layers.iter().any(|layer| {
+				let name = unsafe { CStr::from_ptr(layer.layer_name.as_ptr()) };
+				name.to_bytes() == b"VK_LAYER_KHRONOS_validation"
+			});
+			if !validation {
+				return false;
+			}
+		}
+		let instance =
+			match unsafe { entry.create_instance(&vk::InstanceCreateInfo::default(), None) } {
+				Ok(instance) => instance,
+				Err(_) => return false,
+			};
+		let devices = unsafe { instance.enumerate_physical_devices() }.unwrap_or_default();
+		unsafe { instance.destroy_instance(None) };
+		!devices.is_empty()
+	}
+
+	/// Renders a headless frame that clears the offscreen image through the render
+	/// graph, then reads it back.
+	#[test]
+	fn headless_frame_is_read_back() {
+		if !vulkan_device_available() {
+			println!("No Vulkan device, skipped.");
+			return;
+		}
+		let extent = vk::Extent2D::builder().width(64).height(48).build();
+		let mut engine = VulkanEngine::new_headless(1, extent);
+		assert!(engine.is_headless());
+		assert_eq!(engine.surface.surface_resolution, extent);
+
+		let frame = engine.current_frame;
+		let render_area = vk::Rect2D::builder().extent(extent).build();
+		let command_buffer = engine.begin_frame_command_buffer();
+		{
+			let engine: &VulkanEngine = &engine;
+			let mut graph = RenderGraph::new();
+			let output = graph.import_image(
+				"Offscreen image",
+				GraphImage {
+					image: engine.swapchain.swapchain_images[frame],
+					view: engine.swapchain.swapchain_image_views[frame],
+					extent,
+					mip_level: 0,
+				},
+				ImageState::UNDEFINED,
+			);
+			graph.export_image(output, Some(ImageUsage::TransferRead));
+			graph.add_pass(
+				"Clear",
+				vec![(
+					output,
+					ImageUsage::ColorAttachment {
+						initial_layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+						final_layout: engine.surface.final_layout(),
+					},
+				)],
+				Vec::new(),
+				|command_buffer, _| unsafe {
+					let render_pass_begin_info = vk::RenderPassBeginInfo::builder()
+						.render_pass(engine.renderpass.renderpass)
+						.framebuffer(engine.swapchain.swapchain_framebuffers[frame])
+						.render_area(render_area)
+						.build();
+					engine.device.device.cmd_begin_render_pass(
+						*command_buffer,
+						&render_pass_begin_info,
+						vk::SubpassContents::INLINE,
+					);
+					engine.device.device.cmd_clear_attachments(
+						*command_buffer,
+						&[vk::ClearAttachment {
+							aspect_mask: vk::ImageAspectFlags::COLOR,
+							color_attachment: 0,
+							clear_value: vk::ClearValue {
+								color: vk::ClearColorValue {
+									float32: [1.0, 0.0, 1.0, 1.0],
+								},
+							},
+						}],
+						&[vk::ClearRect {
+							rect: render_area,
+							base_array_layer: 0,
+							layer_count: 1,
+						}],
+					);
+					engine.device.device.cmd_end_render_pass(*command_buffer);
+				},
+			);
+			graph.execute(engine, &command_buffer, &mut TransientImagePool::new());
+		}
+
+		engine.images_in_flight[frame] = engine.fences.fences[frame];
+		let command_buffers = [command_buffer];
+		let submit_info = vk::SubmitInfo::builder()
+			.command_buffers(&command_buffers)
+			.build();
+		unsafe {
+			engine
+				.device
+				.device
+				.end_command_buffer(command_buffer)
+				.expect("Failed to end a command Buffer.");
+			engine
+				.device
+				.device
+				.reset_fences(&[engine.fences.fences[frame]])
+				.expect("Failed to reset fences.");
+			engine
+				.device
+				.device
+				.queue_submit(
+					engine.device.graphic_queue,
+					&[submit_info],
+					engine.fences.fences[frame],
+				)
+				.expect("Failed to submit a command buffer to the graphics queue.");
+		};
+
+		let output = engine.read_output(frame);
+		assert_eq!(output.dimensions(), (extent.width, extent.height));
+		assert!(output.pixels().all(|pixel| pixel.0 == [255, 0, 255, 255]));
+		unsafe { engine.device.device.device_wait_idle() }.expect("Failed to wait for the device.");
+	}
+}
@@ -54,9 +54,7 @@ impl GraphicsPipeline {
 
 		let builder = std::mem::take(&mut self.builder);
 
-		let pipeline = builder.unwrap().build(device);
-
-		pipeline
+		builder.unwrap().build(device)
 	}
 }
 
@@ -332,8 +330,7 @@ impl GraphicsPipelineBuilder {
 
 	pub fn build(self, device: &Device) -> GraphicsPipeline {
 		let mut pipeline_shader_stage_create_info = Vec::with_capacity(2);
-		if self.vertex_module.is_some() {
-			let vertex_module = self.vertex_module.as_ref().unwrap();
+		if let Some(vertex_module) = self.vertex_module.as_ref() {
 			pipeline_shader_stage_create_info.push(
 				vk::PipelineShaderStageCreateInfo::builder()
 					.module(vertex_module.shader_module)
@@ -343,8 +340,7 @@ impl GraphicsPipelineBuilder {
 					.build(),
 			);
 		}
-		if self.fragment_module.is_some() {
-			let fragment_module = self.fragment_module.as_ref().unwrap();
+		if let Some(fragment_module) = self.fragment_module.as_ref() {
 			pipeline_shader_stage_create_info.push(
 				vk::PipelineShaderStageCreateInfo::builder()
 					.module(fragment_module.shader_module)
@@ -446,6 +442,8 @@ impl ComputePipeline {
 pub struct ComputePipelineBuilder {
 	pipeline_create_flags: vk::PipelineCreateFlags,
 	shader_stage_create_info: vk::PipelineShaderStageCreateInfo,
+	specialization_map_entries: Vec<vk::SpecializationMapEntry>,
+	specialization_data: Vec<u8>,
 	descriptor_sets: Vec<vk::DescriptorSetLayout>,
 	push_constants: Vec<vk::PushConstantRange>,
 	base_pipeline: vk::Pipeline,
@@ -474,6 +472,21 @@ impl ComputePipelineBuilder {
 		self
 	}
 
+	/// Sets the value of the specialization constant `constant_id` of the compute module.
+	pub fn add_specialization_constant<T>(mut self, constant_id: u32, value: T) -> Self {
+		let data = [value];
+		let bytes = unsafe { data.align_to::<u8>() }.1;
+		self.specialization_map_entries.push(
+			vk::SpecializationMapEntry::builder()
+				.constant_id(constant_id)
+				.offset(self.specialization_data.len() as u32)
+				.size(bytes.len())
+				.build(),
+		);
+		self.specialization_data.extend_from_slice(bytes);
+		self
+	}
+
 	pub fn add_descriptor_set(mut self, descriptor_set: &DescriptorSet, set_index: usize) -> Self {
 		self.descriptor_sets
 			.push(descriptor_set.descriptor_set_layout[set_index]);
@@ -504,9 +517,19 @@ impl ComputePipelineBuilder {
 				.expect("Failed to create a pipeline layout.")
 		};
 
+		let specialization_info = vk::SpecializationInfo::builder()
+			.map_entries(&self.specialization_map_entries)
+			.data(&self.specialization_data)
+			.build();
+
+		let mut shader_stage_create_info = self.shader_stage_create_info;
+		if !self.specialization_map_entries.is_empty() {
+			shader_stage_create_info.p_specialization_info = &specialization_info;
+		}
+
 		let pipeline_create_infos = vk::ComputePipelineCreateInfo::builder()
 			.flags(self.pipeline_create_flags)
-			.stage(self.shader_stage_create_info)
+			.stage(shader_stage_create_info)
 			.layout(pipeline_layout)
 			.base_pipeline_handle(self.base_pipeline)
 			.base_pipeline_index(self.base_pipeline_index)
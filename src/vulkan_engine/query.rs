@@ -0,0 +1,269 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use super::{device::Device, instance::Instance, VulkanEngine};
+
+/// Maximum number of scopes recorded in a frame, the others are ignored.
+pub const MAX_TIMESTAMP_SCOPES: usize = 64;
+/// Number of frames kept by `TimestampQueryPool::frame_times`.
+pub const FRAME_TIME_HISTORY: usize = 120;
+
+/// GPU time of a scope of the last read back frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScopeTiming {
+	pub name: String,
+	/// Number of scopes around it.
+	pub depth: usize,
+	pub milliseconds: f32,
+}
+
+struct Scope {
+	name: String,
+	depth: usize,
+	ended: bool,
+}
+
+/// Timestamp queries around named scopes of the frame command buffers, one query
+/// pool per frame in flight. The results of a frame are read when it's recorded
+/// again, after its fence, so reading them never stalls.
+pub struct TimestampQueryPool {
+	pub query_pools: Vec<vk::QueryPool>,
+	/// Scopes begun in each frame, their queries are `2 * index` and `2 * index + 1`.
+	scopes: Vec<Vec<Scope>>,
+	depth: usize,
+	/// Nanoseconds per timestamp tick.
+	timestamp_period: f32,
+	/// Mask of the valid bits of the timestamps, 0 if the queue doesn't support them.
+	timestamp_mask: u64,
+	timings: Vec<ScopeTiming>,
+	frame_times: Vec<f32>,
+	device: Arc<ash::Device>,
+}
+
+impl Drop for TimestampQueryPool {
+	fn drop(&mut self) {
+		for i in 0..self.query_pools.len() {
+			unsafe {
+				self.device.destroy_query_pool(self.query_pools[i], None);
+			};
+		}
+	}
+}
+
+impl TimestampQueryPool {
+	#![allow(dead_code)]
+	pub fn new(instance: &Instance, device: &Device, number: usize) -> TimestampQueryPool {
+		let properties = unsafe {
+			instance
+				.instance
+				.get_physical_device_properties(device.physical_device)
+		};
+		let queue_families = unsafe {
+			instance
+				.instance
+				.get_physical_device_queue_family_properties(device.physical_device)
+		};
+		let valid_bits = queue_families[device.queue_family_index as usize].timestamp_valid_bits;
+		let timestamp_mask = if valid_bits >= 64 {
+			u64::MAX
+		} else {
+			(1u64 << valid_bits) - 1
+		};
+
+		let query_pool_create_info = vk::QueryPoolCreateInfo::builder()
+			.query_type(vk::QueryType::TIMESTAMP)
+			.query_count(2 * MAX_TIMESTAMP_SCOPES as u32)
+			.build();
+		let mut query_pools = Vec::with_capacity(number);
+		for _ in 0..number {
+			query_pools.push(unsafe {
+				device
+					.device
+					.create_query_pool(&query_pool_create_info, None)
+					.expect("Failed to create a query pool.")
+			});
+		}
+
+		TimestampQueryPool {
+			query_pools,
+			scopes: (0..number).map(|_| Vec::new()).collect(),
+			depth: 0,
+			timestamp_period: properties.limits.timestamp_period,
+			timestamp_mask,
+			timings: Vec::new(),
+			frame_times: Vec::new(),
+			device: device.device.clone(),
+		}
+	}
+
+	pub fn is_supported(&self) -> bool {
+		self.timestamp_mask != 0
+	}
+
+	/// Reads the results of the previous use of `frame`, then resets its queries in
+	/// `command_buffer`. The fence of `frame` must have been waited.
+	pub fn begin_frame(&mut self, frame: usize, command_buffer: &vk::CommandBuffer) {
+		if !self.is_supported() {
+			return;
+		}
+		self.read_results(frame);
+		self.scopes[frame].clear();
+		self.depth = 0;
+		unsafe {
+			self.device.cmd_reset_query_pool(
+				*command_buffer,
+				self.query_pools[frame],
+				0,
+				2 * MAX_TIMESTAMP_SCOPES as u32,
+			);
+		};
+	}
+
+	fn read_results(&mut self, frame: usize) {
+		let scopes = &self.scopes[frame];
+		if scopes.is_empty() {
+			return;
+		}
+		// Value and availability of each query
+		let mut results = vec![[0u64; 2]; 2 * scopes.len()];
+		let read = unsafe {
+			self.device.get_query_pool_results(
+				self.query_pools[frame],
+				0,
+				results.len() as u32,
+				&mut results,
+				vk::QueryResultFlags::TYPE_64 | vk::QueryResultFlags::WITH_AVAILABILITY,
+			)
+		};
+		match read {
+			Ok(()) | Err(vk::Result::NOT_READY) => {}
+			Err(error) => panic!("Failed to get the timestamp query results: {}", error),
+		}
+
+		let mask = self.timestamp_mask;
+		let ticks_to_milliseconds = self.timestamp_period as f64 / 1_000_000.0;
+		let mut timings = Vec::with_capacity(scopes.len());
+		let mut frame_begin = None;
+		let mut frame_end = None;
+		for (index, scope) in scopes.iter().enumerate() {
+			let [begin, begin_available] = results[2 * index];
+			let [end, end_available] = results[2 * index + 1];
+			if !scope.ended || begin_available == 0 || end_available == 0 {
+				continue;
+			}
+			let ticks = end.wrapping_sub(begin) & mask;
+			timings.push(ScopeTiming {
+				name: scope.name.clone(),
+				depth: scope.depth,
+				milliseconds: (ticks as f64 * ticks_to_milliseconds) as f32,
+			});
+			if scope.depth == 0 {
+				frame_begin.get_or_insert(begin);
+				frame_end = Some(end);
+			}
+		}
+		if let (Some(begin), Some(end)) = (frame_begin, frame_end) {
+			let ticks = end.wrapping_sub(begin) & mask;
+			if self.frame_times.len() == FRAME_TIME_HISTORY {
+				self.frame_times.remove(0);
+			}
+			self.frame_times
+				.push((ticks as f64 * ticks_to_milliseconds) as f32);
+		}
+		self.timings = timings;
+	}
+
+	/// Writes the begin timestamp of a scope, `None` if it isn't measured.
+	pub fn begin_scope(
+		&mut self,
+		frame: usize,
+		command_buffer: &vk::CommandBuffer,
+		name: &str,
+	) -> Option<usize> {
+		if !self.is_supported() || self.scopes[frame].len() == MAX_TIMESTAMP_SCOPES {
+			return None;
+		}
+		let index = self.scopes[frame].len();
+		self.scopes[frame].push(Scope {
+			name: name.to_string(),
+			depth: self.depth,
+			ended: false,
+		});
+		self.depth += 1;
+		unsafe {
+			self.device.cmd_write_timestamp(
+				*command_buffer,
+				vk::PipelineStageFlags::TOP_OF_PIPE,
+				self.query_pools[frame],
+				2 * index as u32,
+			);
+		};
+		Some(index)
+	}
+
+	/// Writes the end timestamp of the scope `index` of `begin_scope`.
+	pub fn end_scope(&mut self, frame: usize, command_buffer: &vk::CommandBuffer, index: usize) {
+		self.depth -= 1;
+		self.scopes[frame][index].ended = true;
+		unsafe {
+			self.device.cmd_write_timestamp(
+				*command_buffer,
+				vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+				self.query_pools[frame],
+				2 * index as u32 + 1,
+			);
+		};
+	}
+
+	/// The scopes of the last read back frame, in the order they began. It's
+	/// `frames_in_flight` frames old.
+	pub fn timings(&self) -> &[ScopeTiming] {
+		&self.timings
+	}
+
+	/// GPU time of the last frames, from the begin of their first scope to the end of
+	/// their last one, the oldest first.
+	pub fn frame_times(&self) -> &[f32] {
+		&self.frame_times
+	}
+}
+
+/// A scope of `VulkanEngine::timestamps` in the command buffer of `current_frame`,
+/// ended when dropped.
+pub struct TimestampScope<'a> {
+	engine: &'a VulkanEngine,
+	command_buffer: vk::CommandBuffer,
+	index: Option<usize>,
+}
+
+impl<'a> TimestampScope<'a> {
+	pub fn begin(
+		engine: &'a VulkanEngine,
+		command_buffer: &vk::CommandBuffer,
+		name: &str,
+	) -> TimestampScope<'a> {
+		let index =
+			engine
+				.timestamps
+				.borrow_mut()
+				.begin_scope(engine.current_frame, command_buffer, name);
+		TimestampScope {
+			engine,
+			command_buffer: *command_buffer,
+			index,
+		}
+	}
+}
+
+impl<'a> Drop for TimestampScope<'a> {
+	fn drop(&mut self) {
+		if let Some(index) = self.index {
+			self.engine.timestamps.borrow_mut().end_scope(
+				self.engine.current_frame,
+				&self.command_buffer,
+				index,
+			);
+		}
+	}
+}
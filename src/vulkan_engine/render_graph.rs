@@ -0,0 +1,1253 @@
+//! The passes of a frame, with the images and buffers they use. The graph culls the
+//! passes that don't contribute to an exported resource, aliases the transient images
+//! whose lifetimes don't overlap and records the barriers and layout transitions
+//! between the passes. `RenderGraph::compile` only works on the declarations, without
+//! a device.
+
+use std::collections::HashMap;
+
+use ash::vk;
+use gpu_alloc::UsageFlags;
+
+use super::{image, query, Resizable, VulkanEngine};
+
+/// An image declared in a `RenderGraph`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ImageHandle(usize);
+
+/// A buffer declared in a `RenderGraph`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BufferHandle(usize);
+
+/// How a pass uses an image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageUsage {
+	/// Color attachment of a render pass, which transitions it from `initial_layout`
+	/// to `final_layout` itself. The content is loaded unless `initial_layout` is
+	/// `UNDEFINED`.
+	ColorAttachment {
+		initial_layout: vk::ImageLayout,
+		final_layout: vk::ImageLayout,
+	},
+	/// Sampled or loaded by a compute shader, in `GENERAL` layout.
+	ComputeRead,
+	/// Stored by a compute shader over the whole image, the previous content is
+	/// discarded.
+	ComputeWrite,
+	/// Loaded and stored by a compute shader.
+	ComputeReadWrite,
+	/// Sampled by a fragment shader.
+	FragmentRead,
+	/// Copied from, like by `VulkanEngine::read_output`.
+	TransferRead,
+	/// Copied over the whole image, the previous content is discarded.
+	TransferWrite,
+}
+
+impl ImageUsage {
+	pub fn stages(self) -> vk::PipelineStageFlags {
+		match self {
+			ImageUsage::ColorAttachment { .. } => vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+			ImageUsage::ComputeRead | ImageUsage::ComputeWrite | ImageUsage::ComputeReadWrite => {
+				vk::PipelineStageFlags::COMPUTE_SHADER
+			}
+			ImageUsage::FragmentRead => vk::PipelineStageFlags::FRAGMENT_SHADER,
+			ImageUsage::TransferRead | ImageUsage::TransferWrite => {
+				vk::PipelineStageFlags::TRANSFER
+			}
+		}
+	}
+
+	pub fn access(self) -> vk::AccessFlags {
+		match self {
+			ImageUsage::ColorAttachment { .. } if self.reads() => {
+				vk::AccessFlags::COLOR_ATTACHMENT_READ | vk::AccessFlags::COLOR_ATTACHMENT_WRITE
+			}
+			ImageUsage::ColorAttachment { .. } => vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+			ImageUsage::ComputeRead | ImageUsage::FragmentRead => vk::AccessFlags::SHADER_READ,
+			ImageUsage::ComputeWrite => vk::AccessFlags::SHADER_WRITE,
+			ImageUsage::ComputeReadWrite => {
+				vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE
+			}
+			ImageUsage::TransferRead => vk::AccessFlags::TRANSFER_READ,
+			ImageUsage::TransferWrite => vk::AccessFlags::TRANSFER_WRITE,
+		}
+	}
+
+	/// Layout the image must be in when the pass starts, `UNDEFINED` if the pass
+	/// accepts any layout.
+	pub fn layout(self) -> vk::ImageLayout {
+		match self {
+			ImageUsage::ColorAttachment { initial_layout, .. } => initial_layout,
+			ImageUsage::ComputeRead | ImageUsage::ComputeWrite | ImageUsage::ComputeReadWrite => {
+				vk::ImageLayout::GENERAL
+			}
+			ImageUsage::FragmentRead => vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+			ImageUsage::TransferRead => vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+			ImageUsage::TransferWrite => vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+		}
+	}
+
+	/// Layout of the image when the pass ends.
+	pub fn final_layout(self) -> vk::ImageLayout {
+		match self {
+			ImageUsage::ColorAttachment { final_layout, .. } => final_layout,
+			_ => self.layout(),
+		}
+	}
+
+	pub fn reads(self) -> bool {
+		match self {
+			ImageUsage::ColorAttachment { initial_layout, .. } => {
+				initial_layout != vk::ImageLayout::UNDEFINED
+			}
+			ImageUsage::ComputeRead
+			| ImageUsage::ComputeReadWrite
+			| ImageUsage::FragmentRead
+			| ImageUsage::TransferRead => true,
+			ImageUsage::ComputeWrite | ImageUsage::TransferWrite => false,
+		}
+	}
+
+	pub fn writes(self) -> bool {
+		match self {
+			ImageUsage::ColorAttachment { .. }
+			| ImageUsage::ComputeWrite
+			| ImageUsage::ComputeReadWrite
+			| ImageUsage::TransferWrite => true,
+			ImageUsage::ComputeRead | ImageUsage::FragmentRead | ImageUsage::TransferRead => false,
+		}
+	}
+}
+
+/// Stages and accesses that can use an image in `layout`, the scope of the barriers
+/// around a standalone layout transition: those of the usage that needs the layout.
+pub fn layout_scope(layout: vk::ImageLayout) -> (vk::PipelineStageFlags, vk::AccessFlags) {
+	let usage = match layout {
+		vk::ImageLayout::UNDEFINED | vk::ImageLayout::PREINITIALIZED => {
+			return (
+				vk::PipelineStageFlags::TOP_OF_PIPE,
+				vk::AccessFlags::empty(),
+			)
+		}
+		vk::ImageLayout::PRESENT_SRC_KHR => {
+			return (
+				vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+				vk::AccessFlags::empty(),
+			)
+		}
+		vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL
+		| vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL => {
+			return (
+				vk::PipelineStageFlags::EARLY_FRAGMENT_TESTS
+					| vk::PipelineStageFlags::LATE_FRAGMENT_TESTS,
+				vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ
+					| vk::AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+			)
+		}
+		// The textures are sampled by the compute passes too
+		vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL => {
+			return (
+				ImageUsage::FragmentRead.stages() | ImageUsage::ComputeRead.stages(),
+				ImageUsage::FragmentRead.access(),
+			)
+		}
+		vk::ImageLayout::TRANSFER_SRC_OPTIMAL => ImageUsage::TransferRead,
+		vk::ImageLayout::TRANSFER_DST_OPTIMAL => ImageUsage::TransferWrite,
+		vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL => ImageUsage::ColorAttachment {
+			initial_layout: layout,
+			final_layout: layout,
+		},
+		// The storage images of the compute passes
+		vk::ImageLayout::GENERAL => ImageUsage::ComputeReadWrite,
+		_ => panic!("No image usage needs the layout {:?}.", layout),
+	};
+	(usage.stages(), usage.access())
+}
+
+/// The accesses to a resource that the next barrier must wait for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessState {
+	/// The last write, or the semaphore wait of an acquired swapchain image.
+	pub write_stages: vk::PipelineStageFlags,
+	pub write_access: vk::AccessFlags,
+	/// The reads since the last write, all ordered after it.
+	pub read_stages: vk::PipelineStageFlags,
+	/// What the last write is already visible to.
+	pub visible_stages: vk::PipelineStageFlags,
+	pub visible_access: vk::AccessFlags,
+}
+
+impl AccessState {
+	/// No pending access: a new resource, or one whose last use was waited by the CPU.
+	pub const NONE: AccessState = AccessState {
+		write_stages: vk::PipelineStageFlags::empty(),
+		write_access: vk::AccessFlags::empty(),
+		read_stages: vk::PipelineStageFlags::empty(),
+		visible_stages: vk::PipelineStageFlags::empty(),
+		visible_access: vk::AccessFlags::empty(),
+	};
+
+	/// Last written with `access` at `stages` by a previous submission to the queue,
+	/// the first use of the graph waits for it.
+	pub fn written(stages: vk::PipelineStageFlags, access: vk::AccessFlags) -> AccessState {
+		AccessState {
+			write_stages: stages,
+			write_access: access,
+			..AccessState::NONE
+		}
+	}
+
+	/// Moves to the state after a use at `stages` with `access`, and returns the source
+	/// scope of the barrier it needs, if any. A layout `transition` writes the resource.
+	fn access(
+		&mut self,
+		stages: vk::PipelineStageFlags,
+		access: vk::AccessFlags,
+		reads: bool,
+		writes: bool,
+		transition: bool,
+	) -> Option<(vk::PipelineStageFlags, vk::AccessFlags)> {
+		let mut src_stages = vk::PipelineStageFlags::empty();
+		let mut src_access = vk::AccessFlags::empty();
+		if writes || transition {
+			if !self.read_stages.is_empty() {
+				// Write after read, the reads already wait for the last write
+				src_stages = self.read_stages;
+			} else {
+				// Write after write
+				src_stages = self.write_stages;
+				src_access = self.write_access;
+			}
+		}
+		let visible = self.visible_stages.contains(stages) && self.visible_access.contains(access);
+		if reads && !visible && !self.write_stages.is_empty() {
+			// Read after write
+			src_stages |= self.write_stages | self.visible_stages;
+			src_access |= self.write_access;
+		}
+		let barrier = transition || !src_stages.is_empty();
+
+		if writes {
+			self.write_stages = stages;
+			self.write_access = access;
+			self.read_stages = vk::PipelineStageFlags::empty();
+			self.visible_stages = vk::PipelineStageFlags::empty();
+			self.visible_access = vk::AccessFlags::empty();
+		} else if transition {
+			// The transition is the last write, visible to this use
+			self.write_stages = stages;
+			self.write_access = vk::AccessFlags::empty();
+			self.read_stages = stages;
+			self.visible_stages = stages;
+			self.visible_access = access;
+		} else {
+			self.read_stages |= stages;
+			if barrier {
+				self.visible_stages |= stages;
+				self.visible_access |= access;
+			}
+		}
+
+		if barrier {
+			Some((src_stages, src_access))
+		} else {
+			None
+		}
+	}
+}
+
+/// The layout of an image and the accesses the next barrier must wait for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageState {
+	pub layout: vk::ImageLayout,
+	pub accesses: AccessState,
+}
+
+impl ImageState {
+	/// An image without pending accesses and whose content can be discarded.
+	pub const UNDEFINED: ImageState = ImageState {
+		layout: vk::ImageLayout::UNDEFINED,
+		accesses: AccessState::NONE,
+	};
+
+	/// A swapchain image acquired with a semaphore waited at `wait_stage` by the
+	/// submit, its first use is chained after the wait.
+	pub fn acquired(wait_stage: vk::PipelineStageFlags) -> ImageState {
+		ImageState {
+			layout: vk::ImageLayout::UNDEFINED,
+			accesses: AccessState::written(wait_stage, vk::AccessFlags::empty()),
+		}
+	}
+
+	/// An image in `layout` whose last use was waited by the CPU, like the resources of
+	/// a frame in flight after its fence.
+	pub fn idle(layout: vk::ImageLayout) -> ImageState {
+		ImageState {
+			layout,
+			accesses: AccessState::NONE,
+		}
+	}
+
+	/// Moves to the state after `usage` and returns the barrier it needs, if any.
+	fn access(&mut self, image: ImageHandle, usage: ImageUsage) -> Option<ImageBarrier> {
+		let layout = usage.layout();
+		let transition = layout != vk::ImageLayout::UNDEFINED && layout != self.layout;
+		let barrier = self
+			.accesses
+			.access(
+				usage.stages(),
+				usage.access(),
+				usage.reads(),
+				usage.writes(),
+				transition,
+			)
+			.map(|(src_stages, src_access)| {
+				let (old_layout, new_layout) = match (transition, usage.reads()) {
+					(true, true) => (self.layout, layout),
+					// The content is discarded
+					(true, false) => (vk::ImageLayout::UNDEFINED, layout),
+					(false, _) => (self.layout, self.layout),
+				};
+				ImageBarrier {
+					image,
+					src_stages,
+					dst_stages: usage.stages(),
+					src_access,
+					dst_access: usage.access(),
+					old_layout,
+					new_layout,
+				}
+			});
+		self.layout = usage.final_layout();
+		barrier
+	}
+}
+
+/// A barrier on a graph image, without the Vulkan handles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageBarrier {
+	pub image: ImageHandle,
+	/// Empty for a layout transition of an image without pending access.
+	pub src_stages: vk::PipelineStageFlags,
+	pub dst_stages: vk::PipelineStageFlags,
+	pub src_access: vk::AccessFlags,
+	pub dst_access: vk::AccessFlags,
+	pub old_layout: vk::ImageLayout,
+	pub new_layout: vk::ImageLayout,
+}
+
+/// How the compute shader of a pass uses a buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BufferUsage {
+	Read,
+	/// The previous content is discarded.
+	Write,
+	ReadWrite,
+}
+
+impl BufferUsage {
+	pub fn stages(self) -> vk::PipelineStageFlags {
+		vk::PipelineStageFlags::COMPUTE_SHADER
+	}
+
+	pub fn access(self) -> vk::AccessFlags {
+		match self {
+			BufferUsage::Read => vk::AccessFlags::SHADER_READ,
+			BufferUsage::Write => vk::AccessFlags::SHADER_WRITE,
+			BufferUsage::ReadWrite => vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE,
+		}
+	}
+
+	pub fn reads(self) -> bool {
+		self != BufferUsage::Write
+	}
+
+	pub fn writes(self) -> bool {
+		self != BufferUsage::Read
+	}
+}
+
+/// A barrier on the whole of a graph buffer, without the Vulkan handles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BufferBarrier {
+	pub buffer: BufferHandle,
+	pub src_stages: vk::PipelineStageFlags,
+	pub dst_stages: vk::PipelineStageFlags,
+	pub src_access: vk::AccessFlags,
+	pub dst_access: vk::AccessFlags,
+}
+
+/// A single mip, single layer color image created by the graph. Images with the same
+/// description can be aliased.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TransientImageDesc {
+	pub format: vk::Format,
+	pub extent: vk::Extent2D,
+	pub usage: vk::ImageUsageFlags,
+}
+
+/// A physical transient image: the `n`th image of its description.
+pub type TransientImageKey = (TransientImageDesc, usize);
+
+/// States of the physical transient images at the end of the last graph, so the next
+/// one waits for the previous frames.
+pub type TransientHistory = HashMap<TransientImageKey, ImageState>;
+
+enum GraphImageKind {
+	Imported {
+		image: GraphImage,
+		initial_state: ImageState,
+	},
+	Transient(TransientImageDesc),
+}
+
+struct GraphImageDecl {
+	name: String,
+	kind: GraphImageKind,
+	/// Usage after the graph, `None` to leave it as the last pass does. Only exported
+	/// images keep their writers alive.
+	export: Option<Option<ImageUsage>>,
+}
+
+/// Buffers are always owned outside of the graph.
+struct GraphBufferDecl {
+	name: String,
+	buffer: GraphBuffer,
+	initial_state: AccessState,
+	/// Only exported buffers keep their writers alive.
+	export: bool,
+}
+
+/// Records a pass, with the images of the graph.
+pub type RecordPass<'a> = Box<dyn FnOnce(&vk::CommandBuffer, &PassResources) + 'a>;
+
+struct Pass<'a> {
+	name: String,
+	/// Names of the timestamp scopes around the pass, the outermost first, ending with
+	/// its own.
+	scopes: Vec<String>,
+	images: Vec<(ImageHandle, ImageUsage)>,
+	buffers: Vec<(BufferHandle, BufferUsage)>,
+	record: RecordPass<'a>,
+}
+
+/// The Vulkan handles of a graph image.
+#[derive(Debug, Clone, Copy)]
+pub struct GraphImage {
+	pub image: vk::Image,
+	/// Only covers `mip_level`.
+	pub view: vk::ImageView,
+	pub extent: vk::Extent2D,
+	/// The mip of `image` the graph image is, the other mips can be other graph images.
+	pub mip_level: u32,
+}
+
+/// The Vulkan handle of a graph buffer.
+#[derive(Debug, Clone, Copy)]
+pub struct GraphBuffer {
+	pub buffer: vk::Buffer,
+	pub size: vk::DeviceSize,
+}
+
+/// Images and buffers of the graph, given to the passes when they are recorded.
+pub struct PassResources {
+	images: Vec<Option<GraphImage>>,
+	buffers: Vec<GraphBuffer>,
+}
+
+impl PassResources {
+	pub fn image(&self, handle: ImageHandle) -> GraphImage {
+		self.images[handle.0].expect("The image isn't used by any pass of the graph.")
+	}
+
+	pub fn buffer(&self, handle: BufferHandle) -> GraphBuffer {
+		self.buffers[handle.0]
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompiledPass {
+	/// Index of the pass, in the order of `RenderGraph::add_pass`.
+	pub index: usize,
+	/// Recorded before the pass.
+	pub barriers: Vec<ImageBarrier>,
+	pub buffer_barriers: Vec<BufferBarrier>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompiledGraph {
+	/// The passes that weren't culled, in order.
+	pub passes: Vec<CompiledPass>,
+	/// To the usages of the exported images, after the last pass.
+	pub final_barriers: Vec<ImageBarrier>,
+	/// Physical image of each graph image, `None` for the imported images and the
+	/// transient images of culled passes.
+	pub transient_images: Vec<Option<TransientImageKey>>,
+	/// The history for the next graph.
+	pub transient_history: TransientHistory,
+}
+
+pub struct RenderGraph<'a> {
+	images: Vec<GraphImageDecl>,
+	buffers: Vec<GraphBufferDecl>,
+	passes: Vec<Pass<'a>>,
+	/// Scopes of `push_scope` not popped yet.
+	scopes: Vec<String>,
+}
+
+impl<'a> RenderGraph<'a> {
+	pub fn new() -> RenderGraph<'a> {
+		RenderGraph {
+			images: Vec::new(),
+			buffers: Vec::new(),
+			passes: Vec::new(),
+			scopes: Vec::new(),
+		}
+	}
+
+	/// An image owned outside of the graph, in `initial_state` before the first pass.
+	pub fn import_image(
+		&mut self,
+		name: &str,
+		image: GraphImage,
+		initial_state: ImageState,
+	) -> ImageHandle {
+		self.images.push(GraphImageDecl {
+			name: name.to_string(),
+			kind: GraphImageKind::Imported {
+				image,
+				initial_state,
+			},
+			export: None,
+		});
+		ImageHandle(self.images.len() - 1)
+	}
+
+	/// An image that only lives during the graph, its content is undefined before its
+	/// first write.
+	pub fn create_image(&mut self, name: &str, desc: TransientImageDesc) -> ImageHandle {
+		self.images.push(GraphImageDecl {
+			name: name.to_string(),
+			kind: GraphImageKind::Transient(desc),
+			export: None,
+		});
+		ImageHandle(self.images.len() - 1)
+	}
+
+	/// Keeps the passes that write `image`, which is made ready for `final_usage` after
+	/// the graph if given.
+	pub fn export_image(&mut self, image: ImageHandle, final_usage: Option<ImageUsage>) {
+		let decl = &mut self.images[image.0];
+		assert!(
+			matches!(decl.kind, GraphImageKind::Imported { .. }),
+			"The transient image {} can't be exported.",
+			decl.name
+		);
+		decl.export = Some(final_usage);
+	}
+
+	/// A buffer owned outside of the graph, in `initial_state` before the first pass.
+	pub fn import_buffer(
+		&mut self,
+		name: &str,
+		buffer: GraphBuffer,
+		initial_state: AccessState,
+	) -> BufferHandle {
+		self.buffers.push(GraphBufferDecl {
+			name: name.to_string(),
+			buffer,
+			initial_state,
+			export: false,
+		});
+		BufferHandle(self.buffers.len() - 1)
+	}
+
+	/// Keeps the passes that write `buffer`, which is read after the graph.
+	pub fn export_buffer(&mut self, buffer: BufferHandle) {
+		self.buffers[buffer.0].export = true;
+	}
+
+	/// The passes added until the matching `pop_scope` are timed together in a
+	/// timestamp scope named `name`, around their own scopes.
+	pub fn push_scope(&mut self, name: &str) {
+		self.scopes.push(name.to_string());
+	}
+
+	pub fn pop_scope(&mut self) {
+		self.scopes.pop().expect("No timestamp scope to pop.");
+	}
+
+	/// Adds a pass using each of `images` and `buffers` once, recorded by `record` in
+	/// the order of the calls.
+	pub fn add_pass(
+		&mut self,
+		name: &str,
+		images: Vec<(ImageHandle, ImageUsage)>,
+		buffers: Vec<(BufferHandle, BufferUsage)>,
+		record: impl FnOnce(&vk::CommandBuffer, &PassResources) + 'a,
+	) {
+		for (i, (image, _)) in images.iter().enumerate() {
+			assert!(
+				images[..i].iter().all(|(other, _)| other != image),
+				"The pass {} uses the image {} twice.",
+				name,
+				self.images[image.0].name
+			);
+		}
+		for (i, (buffer, _)) in buffers.iter().enumerate() {
+			assert!(
+				buffers[..i].iter().all(|(other, _)| other != buffer),
+				"The pass {} uses the buffer {} twice.",
+				name,
+				self.buffers[buffer.0].name
+			);
+		}
+		let mut scopes = self.scopes.clone();
+		scopes.push(name.to_string());
+		self.passes.push(Pass {
+			name: name.to_string(),
+			scopes,
+			images,
+			buffers,
+			record: Box::new(record),
+		});
+	}
+
+	/// Only read by the tests, to check the order of the passes.
+	#[allow(dead_code)]
+	pub fn pass_name(&self, index: usize) -> &str {
+		&self.passes[index].name
+	}
+
+	/// Whether each pass contributes to an exported resource, walking back from the
+	/// exports.
+	fn live_passes(&self) -> Vec<bool> {
+		let mut needed: Vec<bool> = self
+			.images
+			.iter()
+			.map(|image| image.export.is_some())
+			.collect();
+		let mut needed_buffers: Vec<bool> =
+			self.buffers.iter().map(|buffer| buffer.export).collect();
+		let mut live = vec![false; self.passes.len()];
+		for (index, pass) in self.passes.iter().enumerate().rev() {
+			live[index] = pass
+				.images
+				.iter()
+				.any(|(image, usage)| usage.writes() && needed[image.0])
+				|| pass
+					.buffers
+					.iter()
+					.any(|(buffer, usage)| usage.writes() && needed_buffers[buffer.0]);
+			if !live[index] {
+				continue;
+			}
+			// The content before a pass that discards it isn't needed, unless the pass
+			// reads it
+			for (image, usage) in pass.images.iter() {
+				needed[image.0] = usage.reads();
+			}
+			for (buffer, usage) in pass.buffers.iter() {
+				needed_buffers[buffer.0] = usage.reads();
+			}
+		}
+		live
+	}
+
+	/// Culls the passes, assigns the physical transient images and computes the
+	/// barriers. `history` is the `transient_history` of the previous graph.
+	pub fn compile(&self, history: &TransientHistory) -> CompiledGraph {
+		let live = self.live_passes();
+		let live_passes: Vec<usize> = (0..self.passes.len()).filter(|i| live[*i]).collect();
+
+		// Lifetimes of the transient images, in positions of `live_passes`
+		let mut lifetimes: Vec<Option<(usize, usize)>> = vec![None; self.images.len()];
+		for (position, index) in live_passes.iter().enumerate() {
+			for (image, usage) in self.passes[*index].images.iter() {
+				let decl = &self.images[image.0];
+				if let GraphImageKind::Transient(_) = decl.kind {
+					lifetimes[image.0] = match lifetimes[image.0] {
+						Some((first, _)) => Some((first, position)),
+						None => {
+							assert!(
+								!usage.reads(),
+								"The pass {} reads the transient image {} before it is written.",
+								self.passes[*index].name,
+								decl.name
+							);
+							Some((position, position))
+						}
+					};
+				}
+			}
+		}
+
+		// Aliasing: the first physical image of the same description that is free
+		// before the first use
+		let mut transient_images: Vec<Option<TransientImageKey>> = vec![None; self.images.len()];
+		let mut physical_last_use: HashMap<TransientImageKey, usize> = HashMap::new();
+		let mut by_first_use: Vec<usize> = (0..self.images.len())
+			.filter(|i| lifetimes[*i].is_some())
+			.collect();
+		by_first_use.sort_by_key(|i| lifetimes[*i].unwrap().0);
+		for image in by_first_use {
+			let desc = match self.images[image].kind {
+				GraphImageKind::Transient(desc) => desc,
+				GraphImageKind::Imported { .. } => unreachable!(),
+			};
+			let (first, last) = lifetimes[image].unwrap();
+			let mut n = 0;
+			while physical_last_use
+				.get(&(desc, n))
+				.is_some_and(|last_use| *last_use >= first)
+			{
+				n += 1;
+			}
+			physical_last_use.insert((desc, n), last);
+			transient_images[image] = Some((desc, n));
+		}
+
+		// States of the imported images and of the physical transient images
+		let mut imported_states: Vec<ImageState> = self
+			.images
+			.iter()
+			.map(|image| match image.kind {
+				GraphImageKind::Imported { initial_state, .. } => initial_state,
+				GraphImageKind::Transient(_) => ImageState::UNDEFINED,
+			})
+			.collect();
+		let mut buffer_states: Vec<AccessState> = self
+			.buffers
+			.iter()
+			.map(|buffer| buffer.initial_state)
+			.collect();
+		let mut transient_history: TransientHistory = physical_last_use
+			.keys()
+			.map(|key| {
+				let state = history.get(key).copied().unwrap_or(ImageState::UNDEFINED);
+				(*key, state)
+			})
+			.collect();
+
+		let mut passes = Vec::with_capacity(live_passes.len());
+		for index in live_passes {
+			let mut barriers = Vec::new();
+			for (image, usage) in self.passes[index].images.iter() {
+				let state = match transient_images[image.0] {
+					Some(key) => transient_history.get_mut(&key).unwrap(),
+					None => &mut imported_states[image.0],
+				};
+				barriers.extend(state.access(*image, *usage));
+			}
+			let mut buffer_barriers = Vec::new();
+			for (buffer, usage) in self.passes[index].buffers.iter() {
+				let barrier = buffer_states[buffer.0].access(
+					usage.stages(),
+					usage.access(),
+					usage.reads(),
+					usage.writes(),
+					false,
+				);
+				buffer_barriers.extend(barrier.map(|(src_stages, src_access)| BufferBarrier {
+					buffer: *buffer,
+					src_stages,
+					dst_stages: usage.stages(),
+					src_access,
+					dst_access: usage.access(),
+				}));
+			}
+			passes.push(CompiledPass {
+				index,
+				barriers,
+				buffer_barriers,
+			});
+		}
+
+		let mut final_barriers = Vec::new();
+		for (index, image) in self.images.iter().enumerate() {
+			if let Some(Some(final_usage)) = image.export {
+				// As if the commands after the graph were one more pass
+				final_barriers
+					.extend(imported_states[index].access(ImageHandle(index), final_usage));
+			}
+		}
+
+		// Physical images unused by this graph keep their state
+		for (key, state) in history.iter() {
+			transient_history.entry(*key).or_insert(*state);
+		}
+
+		CompiledGraph {
+			passes,
+			final_barriers,
+			transient_images,
+			transient_history,
+		}
+	}
+
+	/// Compiles the graph with the history of `pool`, then records the barriers and
+	/// the passes that weren't culled, each in a timestamp scope named after it.
+	/// Consecutive passes share the scopes they have in common, the same name
+	/// included.
+	pub fn execute(
+		self,
+		engine: &VulkanEngine,
+		command_buffer: &vk::CommandBuffer,
+		pool: &mut TransientImagePool,
+	) {
+		let compiled = self.compile(&pool.history);
+
+		let resources = PassResources {
+			images: self
+				.images
+				.iter()
+				.enumerate()
+				.map(
+					|(index, image)| match (&image.kind, compiled.transient_images[index]) {
+						(GraphImageKind::Imported { image, .. }, _) => Some(*image),
+						(GraphImageKind::Transient(_), Some(key)) => Some(pool.image(engine, key)),
+						(GraphImageKind::Transient(_), None) => None,
+					},
+				)
+				.collect(),
+			buffers: self.buffers.iter().map(|buffer| buffer.buffer).collect(),
+		};
+
+		let mut passes: Vec<Option<Pass>> = self.passes.into_iter().map(Some).collect();
+		let mut scopes: Vec<(String, query::TimestampScope)> = Vec::new();
+		for compiled_pass in compiled.passes.iter() {
+			let pass = passes[compiled_pass.index].take().unwrap();
+			let shared = scopes
+				.iter()
+				.zip(pass.scopes.iter())
+				.take_while(|((open, _), name)| open == *name)
+				.count();
+			// The innermost scope ends first
+			while scopes.len() > shared {
+				scopes.pop();
+			}
+			record_barriers(
+				engine,
+				command_buffer,
+				&compiled_pass.barriers,
+				&compiled_pass.buffer_barriers,
+				&resources,
+			);
+			for name in pass.scopes[shared..].iter() {
+				let scope = query::TimestampScope::begin(engine, command_buffer, name);
+				scopes.push((name.clone(), scope));
+			}
+			(pass.record)(command_buffer, &resources);
+		}
+		while scopes.pop().is_some() {}
+		record_barriers(
+			engine,
+			command_buffer,
+			&compiled.final_barriers,
+			&[],
+			&resources,
+		);
+
+		pool.history = compiled.transient_history;
+	}
+}
+
+/// Records `barriers` and `buffer_barriers` with a single `cmd_pipeline_barrier`.
+fn record_barriers(
+	engine: &VulkanEngine,
+	command_buffer: &vk::CommandBuffer,
+	barriers: &[ImageBarrier],
+	buffer_barriers: &[BufferBarrier],
+	resources: &PassResources,
+) {
+	if barriers.is_empty() && buffer_barriers.is_empty() {
+		return;
+	}
+	let mut src_stages = vk::PipelineStageFlags::empty();
+	let mut dst_stages = vk::PipelineStageFlags::empty();
+	let image_memory_barriers: Vec<vk::ImageMemoryBarrier> = barriers
+		.iter()
+		.map(|barrier| {
+			src_stages |= barrier.src_stages;
+			dst_stages |= barrier.dst_stages;
+			let image = resources.image(barrier.image);
+			vk::ImageMemoryBarrier::builder()
+				.src_access_mask(barrier.src_access)
+				.dst_access_mask(barrier.dst_access)
+				.subresource_range(
+					vk::ImageSubresourceRange::builder()
+						.aspect_mask(vk::ImageAspectFlags::COLOR)
+						.base_mip_level(image.mip_level)
+						.level_count(1)
+						.layer_count(1)
+						.build(),
+				)
+				.image(image.image)
+				.src_queue_family_index(engine.device.queue_family_index)
+				.dst_queue_family_index(engine.device.queue_family_index)
+				.old_layout(barrier.old_layout)
+				.new_layout(barrier.new_layout)
+				.build()
+		})
+		.collect();
+	let buffer_memory_barriers: Vec<vk::BufferMemoryBarrier> = buffer_barriers
+		.iter()
+		.map(|barrier| {
+			src_stages |= barrier.src_stages;
+			dst_stages |= barrier.dst_stages;
+			let buffer = resources.buffer(barrier.buffer);
+			vk::BufferMemoryBarrier::builder()
+				.src_access_mask(barrier.src_access)
+				.dst_access_mask(barrier.dst_access)
+				.src_queue_family_index(engine.device.queue_family_index)
+				.dst_queue_family_index(engine.device.queue_family_index)
+				.buffer(buffer.buffer)
+				.offset(0)
+				.size(buffer.size)
+				.build()
+		})
+		.collect();
+	if src_stages.is_empty() {
+		src_stages = vk::PipelineStageFlags::TOP_OF_PIPE;
+	}
+	unsafe {
+		engine.device.device.cmd_pipeline_barrier(
+			*command_buffer,
+			src_stages,
+			dst_stages,
+			vk::DependencyFlags::empty(),
+			&[],
+			&buffer_memory_barriers,
+			&image_memory_barriers,
+		);
+	};
+}
+
+/// The physical transient images, kept from one graph to the next.
+pub struct TransientImagePool {
+	images: HashMap<TransientImageKey, image::Image>,
+	history: TransientHistory,
+}
+
+impl TransientImagePool {
+	pub fn new() -> TransientImagePool {
+		TransientImagePool {
+			images: HashMap::new(),
+			history: TransientHistory::new(),
+		}
+	}
+
+	/// Drops the images, the device must be idle.
+	pub fn clear(&mut self) {
+		self.images.clear();
+		self.history.clear();
+	}
+
+	/// Creates the image on its first use, in `UNDEFINED` layout.
+	fn image(&mut self, engine: &VulkanEngine, key: TransientImageKey) -> GraphImage {
+		let (desc, _) = key;
+		let image = self.images.entry(key).or_insert_with(|| {
+			image::Image::new(
+				&engine.device,
+				vk::ImageCreateFlags::empty(),
+				vk::ImageType::TYPE_2D,
+				desc.format,
+				vk::Extent3D::builder()
+					.width(desc.extent.width)
+					.height(desc.extent.height)
+					.depth(1)
+					.build(),
+				1,
+				1,
+				vk::ImageTiling::OPTIMAL,
+				desc.usage,
+				engine.device.queue_family_index,
+				vk::ImageLayout::UNDEFINED,
+				vk::ImageLayout::UNDEFINED,
+				vk::ImageViewType::TYPE_2D,
+				vk::ImageAspectFlags::COLOR,
+				UsageFlags::FAST_DEVICE_ACCESS,
+			)
+		});
+		GraphImage {
+			image: image.image,
+			view: image.image_view,
+			extent: desc.extent,
+			mip_level: 0,
+		}
+	}
+}
+
+impl Resizable for TransientImagePool {
+	/// The images are recreated at the new size by the next graphs.
+	fn resize(&mut self, _engine: &VulkanEngine, _extent: vk::Extent2D) {
+		self.clear();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const COMPUTE: vk::PipelineStageFlags = vk::PipelineStageFlags::COMPUTE_SHADER;
+
+	fn desc(width: u32) -> TransientImageDesc {
+		TransientImageDesc {
+			format: vk::Format::R16G16B16A16_SFLOAT,
+			extent: vk::Extent2D::builder().width(width).height(64).build(),
+			usage: vk::ImageUsageFlags::STORAGE | vk::ImageUsageFlags::SAMPLED,
+		}
+	}
+
+	fn import(graph: &mut RenderGraph, name: &str) -> ImageHandle {
+		graph.import_image(
+			name,
+			GraphImage {
+				image: vk::Image::null(),
+				view: vk::ImageView::null(),
+				extent: desc(64).extent,
+				mip_level: 0,
+			},
+			ImageState::UNDEFINED,
+		)
+	}
+
+	fn import_buffer(graph: &mut RenderGraph, name: &str, state: AccessState) -> BufferHandle {
+		graph.import_buffer(
+			name,
+			GraphBuffer {
+				buffer: vk::Buffer::null(),
+				size: 4,
+			},
+			state,
+		)
+	}
+
+	#[test]
+	fn compile_emits_the_barriers_between_the_passes() {
+		let mut graph = RenderGraph::new();
+		let output = import(&mut graph, "Output");
+		graph.export_image(output, Some(ImageUsage::TransferRead));
+		let intermediate = graph.create_image("Intermediate", desc(64));
+		let buffer = import_buffer(
+			&mut graph,
+			"Buffer",
+			AccessState::written(COMPUTE, vk::AccessFlags::SHADER_WRITE),
+		);
+		graph.add_pass(
+			"Write",
+			vec![(intermediate, ImageUsage::ComputeWrite)],
+			vec![(buffer, BufferUsage::Write)],
+			|_, _| {},
+		);
+		graph.add_pass(
+			"Read",
+			vec![
+				(intermediate, ImageUsage::ComputeRead),
+				(output, ImageUsage::ComputeWrite),
+			],
+			vec![(buffer, BufferUsage::Read)],
+			|_, _| {},
+		);
+		let compiled = graph.compile(&TransientHistory::new());
+		assert_eq!(compiled.passes.len(), 2);
+
+		// The transient image is discarded into GENERAL, the buffer waits for the
+		// previous submission
+		assert_eq!(
+			compiled.passes[0].barriers,
+			vec![ImageBarrier {
+				image: intermediate,
+				src_stages: vk::PipelineStageFlags::empty(),
+				dst_stages: COMPUTE,
+				src_access: vk::AccessFlags::empty(),
+				dst_access: vk::AccessFlags::SHADER_WRITE,
+				old_layout: vk::ImageLayout::UNDEFINED,
+				new_layout: vk::ImageLayout::GENERAL,
+			}]
+		);
+		assert_eq!(
+			compiled.passes[0].buffer_barriers,
+			vec![BufferBarrier {
+				buffer,
+				src_stages: COMPUTE,
+				dst_stages: COMPUTE,
+				src_access: vk::AccessFlags::SHADER_WRITE,
+				dst_access: vk::AccessFlags::SHADER_WRITE,
+			}]
+		);
+
+		assert_eq!(
+			compiled.passes[1].barriers,
+			vec![
+				ImageBarrier {
+					image: intermediate,
+					src_stages: COMPUTE,
+					dst_stages: COMPUTE,
+					src_access: vk::AccessFlags::SHADER_WRITE,
+					dst_access: vk::AccessFlags::SHADER_READ,
+					old_layout: vk::ImageLayout::GENERAL,
+					new_layout: vk::ImageLayout::GENERAL,
+				},
+				ImageBarrier {
+					image: output,
+					src_stages: vk::PipelineStageFlags::empty(),
+					dst_stages: COMPUTE,
+					src_access: vk::AccessFlags::empty(),
+					dst_access: vk::AccessFlags::SHADER_WRITE,
+					old_layout: vk::ImageLayout::UNDEFINED,
+					new_layout: vk::ImageLayout::GENERAL,
+				},
+			]
+		);
+		assert_eq!(
+			compiled.passes[1].buffer_barriers,
+			vec![BufferBarrier {
+				buffer,
+				src_stages: COMPUTE,
+				dst_stages: COMPUTE,
+				src_access: vk::AccessFlags::SHADER_WRITE,
+				dst_access: vk::AccessFlags::SHADER_READ,
+			}]
+		);
+
+		// To the copy after the graph
+		assert_eq!(
+			compiled.final_barriers,
+			vec![ImageBarrier {
+				image: output,
+				src_stages: COMPUTE,
+				dst_stages: vk::PipelineStageFlags::TRANSFER,
+				src_access: vk::AccessFlags::SHADER_WRITE,
+				dst_access: vk::AccessFlags::TRANSFER_READ,
+				old_layout: vk::ImageLayout::GENERAL,
+				new_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+			}]
+		);
+	}
+
+	#[test]
+	fn compile_culls_the_passes_that_dont_reach_an_export() {
+		let mut graph = RenderGraph::new();
+		let output = import(&mut graph, "Output");
+		graph.export_image(output, None);
+		let buffer = import_buffer(&mut graph, "Buffer", AccessState::NONE);
+		graph.export_buffer(buffer);
+		let used = graph.create_image("Used", desc(64));
+		let unused = graph.create_image("Unused", desc(64));
+
+		graph.add_pass(
+			"Used",
+			vec![(used, ImageUsage::ComputeWrite)],
+			Vec::new(),
+			|_, _| {},
+		);
+		graph.add_pass(
+			"Unused",
+			vec![(unused, ImageUsage::ComputeWrite)],
+			Vec::new(),
+			|_, _| {},
+		);
+		graph.add_pass(
+			"Overwritten",
+			vec![(output, ImageUsage::ComputeWrite)],
+			Vec::new(),
+			|_, _| {},
+		);
+		graph.add_pass(
+			"Output",
+			vec![
+				(used, ImageUsage::ComputeRead),
+				(output, ImageUsage::ComputeWrite),
+			],
+			Vec::new(),
+			|_, _| {},
+		);
+		graph.add_pass(
+			"Buffer",
+			Vec::new(),
+			vec![(buffer, BufferUsage::ReadWrite)],
+			|_, _| {},
+		);
+		graph.add_pass(
+			"Read only",
+			vec![(output, ImageUsage::ComputeRead)],
+			Vec::new(),
+			|_, _| {},
+		);
+		let compiled = graph.compile(&TransientHistory::new());
+
+		let live: Vec<&str> = compiled
+			.passes
+			.iter()
+			.map(|pass| graph.pass_name(pass.index))
+			.collect();
+		assert_eq!(live, vec!["Used", "Output", "Buffer"]);
+		assert!(compiled.transient_images[used.0].is_some());
+		assert_eq!(compiled.transient_images[unused.0], None);
+	}
+
+	#[test]
+	fn compile_aliases_the_transient_images_whose_lifetimes_dont_overlap() {
+		let mut graph = RenderGraph::new();
+		let output = import(&mut graph, "Output");
+		graph.export_image(output, None);
+		let a = graph.create_image("A", desc(64));
+		let b = graph.create_image("B", desc(64));
+		let c = graph.create_image("C", desc(64));
+		let d = graph.create_image("D", desc(32));
+
+		graph.add_pass(
+			"A",
+			vec![(a, ImageUsage::ComputeWrite)],
+			Vec::new(),
+			|_, _| {},
+		);
+		for (name, input, output) in [("B", a, b), ("C", b, c), ("D", c, d), ("Output", d, output)]
+		{
+			graph.add_pass(
+				name,
+				vec![
+					(input, ImageUsage::ComputeRead),
+					(output, ImageUsage::ComputeWrite),
+				],
+				Vec::new(),
+				|_, _| {},
+			);
+		}
+		let compiled = graph.compile(&TransientHistory::new());
+
+		// C starts after the last read of A, B overlaps both
+		assert_eq!(compiled.transient_images[a.0], Some((desc(64), 0)));
+		assert_eq!(compiled.transient_images[b.0], Some((desc(64), 1)));
+		assert_eq!(compiled.transient_images[c.0], Some((desc(64), 0)));
+		assert_eq!(compiled.transient_images[d.0], Some((desc(32), 0)));
+		assert_eq!(compiled.transient_history.len(), 3);
+
+		// C waits for the read of A before overwriting their image
+		assert_eq!(
+			compiled.passes[2].barriers[1],
+			ImageBarrier {
+				image: c,
+				src_stages: COMPUTE,
+				dst_stages: COMPUTE,
+				src_access: vk::AccessFlags::empty(),
+				dst_access: vk::AccessFlags::SHADER_WRITE,
+				old_layout: vk::ImageLayout::GENERAL,
+				new_layout: vk::ImageLayout::GENERAL,
+			}
+		);
+	}
+
+	#[test]
+	fn layout_scope_of_general_is_the_compute_storage_scope() {
+		assert_eq!(
+			layout_scope(vk::ImageLayout::GENERAL),
+			(
+				COMPUTE,
+				vk::AccessFlags::SHADER_READ | vk::AccessFlags::SHADER_WRITE
+			)
+		);
+	}
+}
@@ -35,7 +35,7 @@ impl ShaderModule {
 				"input length not divisible by 4",
 			));
 		}
-		if size > usize::max_value() as u64 {
+		if size > usize::MAX as u64 {
 			return Err(io::Error::new(io::ErrorKind::InvalidData, "input too long"));
 		}
 		let words = (size / 4) as usize;
@@ -60,7 +60,7 @@ impl ShaderModule {
 	}
 
 	pub fn new(device: &Device, shader_path: &str, entry_point: &str) -> ShaderModule {
-		let arg = std::path::Path::new(&std::env::args().into_iter().next().unwrap())
+		let arg = std::path::Path::new(&std::env::args().next().unwrap())
 			.parent()
 			.unwrap()
 			.parent()
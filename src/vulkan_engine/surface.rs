@@ -5,6 +5,10 @@ use super::device::Device;
 use super::instance::Instance;
 use super::window::Window;
 
+/// Format of the offscreen images of the headless mode, which the compute shaders can
+/// store to on every device.
+pub const HEADLESS_FORMAT: vk::Format = vk::Format::R8G8B8A8_UNORM;
+
 pub struct Surface {
 	pub surface: vk::SurfaceKHR,
 	pub surface_loader: khr::Surface,
@@ -17,6 +21,9 @@ pub struct Surface {
 
 impl Drop for Surface {
 	fn drop(&mut self) {
+		if self.is_headless() {
+			return;
+		}
 		unsafe {
 			self.surface_loader.destroy_surface(self.surface, None);
 		};
@@ -83,4 +90,39 @@ impl Surface {
 			desired_image_count,
 		}
 	}
+
+	/// A null surface for the headless mode, the swapchain renders to
+	/// `desired_image_count` offscreen images of `extent` instead.
+	pub fn headless(
+		instance: &Instance,
+		extent: vk::Extent2D,
+		desired_image_count: u32,
+	) -> Surface {
+		Surface {
+			surface: vk::SurfaceKHR::null(),
+			surface_loader: khr::Surface::new(&instance.entry, &instance.instance),
+			surface_format: vk::SurfaceFormatKHR {
+				format: HEADLESS_FORMAT,
+				color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+			},
+			desired_format: HEADLESS_FORMAT,
+			surface_resolution: extent,
+			pre_transform: vk::SurfaceTransformFlagsKHR::IDENTITY,
+			desired_image_count,
+		}
+	}
+
+	pub fn is_headless(&self) -> bool {
+		self.surface == vk::SurfaceKHR::null()
+	}
+
+	/// Layout of the swapchain images after the ui render pass. In the headless mode,
+	/// the render graph then moves them to `TRANSFER_SRC_OPTIMAL` to be read back.
+	pub fn final_layout(&self) -> vk::ImageLayout {
+		if self.is_headless() {
+			vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+		} else {
+			vk::ImageLayout::PRESENT_SRC_KHR
+		}
+	}
 }
@@ -14,13 +14,18 @@ pub struct Swapchain {
 	pub swapchain_loader: khr::Swapchain,
 	pub swapchain: vk::SwapchainKHR,
 	pub swapchain_extent: vk::Extent2D,
+	/// The images of the swapchain, or of `offscreen_images` in the headless mode.
 	pub swapchain_images: Vec<vk::Image>,
+	/// Rendered to instead of the swapchain in the headless mode, left in
+	/// `TRANSFER_SRC_OPTIMAL` by the frames.
+	pub offscreen_images: Vec<Image>,
 	pub swapchain_image_views: Vec<vk::ImageView>,
 	pub swapchain_hdr_images: Vec<Image>,
 	pub swapchain_image_sampler: vk::Sampler,
 	pub depth_stencil_image: Option<Image>,
 	pub swapchain_hdr_framebuffers: Vec<vk::Framebuffer>,
 	pub swapchain_framebuffers: Vec<vk::Framebuffer>,
+	#[allow(dead_code)]
 	pub max_image_in_flight: usize,
 	swapchain_create_info: vk::SwapchainCreateInfoKHR,
 	device: Arc<ash::Device>,
@@ -39,8 +44,10 @@ impl Drop for Swapchain {
 				self.device
 					.destroy_framebuffer(self.swapchain_framebuffers[i], None);
 			}
-			self.swapchain_loader
-				.destroy_swapchain(self.swapchain, None);
+			if self.swapchain != vk::SwapchainKHR::null() {
+				self.swapchain_loader
+					.destroy_swapchain(self.swapchain, None);
+			}
 		};
 	}
 }
@@ -56,6 +63,10 @@ impl Swapchain {
 	/// image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT
 	///
 	/// sharing_mode: vk::SharingMode::EXCLUSIVE
+	///
+	/// With a headless `surface`, no swapchain is created: the images are
+	/// `offscreen_images` and `present_mode` is ignored.
+	#[allow(clippy::too_many_arguments)]
 	pub fn new(
 		instance: &Instance,
 		surface: &Surface,
@@ -66,17 +77,24 @@ impl Swapchain {
 		hdr_renderpass: &RenderPass,
 		renderpass: &RenderPass,
 	) -> Swapchain {
-		let present_modes = unsafe {
-			surface
-				.surface_loader
-				.get_physical_device_surface_present_modes(device.physical_device, surface.surface)
-				.unwrap()
+		let chosen_present_mode = if surface.is_headless() {
+			vk::PresentModeKHR::FIFO
+		} else {
+			let present_modes = unsafe {
+				surface
+					.surface_loader
+					.get_physical_device_surface_present_modes(
+						device.physical_device,
+						surface.surface,
+					)
+					.unwrap()
+			};
+			present_modes
+				.iter()
+				.cloned()
+				.find(|&mode| mode == present_mode.unwrap_or(vk::PresentModeKHR::MAILBOX))
+				.unwrap_or_else(|| panic!("Couldn't find {:?} as present mode.", present_mode))
 		};
-		let chosen_present_mode = present_modes
-			.iter()
-			.cloned()
-			.find(|&mode| mode == present_mode.unwrap_or(vk::PresentModeKHR::MAILBOX))
-			.unwrap_or_else(|| panic!("Couldn't find {:?} as present mode.", present_mode));
 
 		let swapchain_loader = khr::Swapchain::new(&instance.instance, &device.device);
 
@@ -103,25 +121,36 @@ impl Swapchain {
 			.image_array_layers(1)
 			.build();
 
-		let swapchain = unsafe {
-			swapchain_loader
-				.create_swapchain(&swapchain_create_info, None)
-				.expect("Failed to create the Swapchain.")
-		};
+		let (swapchain, swapchain_images, offscreen_images) = if surface.is_headless() {
+			let offscreen_images = Swapchain::create_offscreen_images(
+				device,
+				surface,
+				swapchain_create_info.image_usage,
+			);
+			let swapchain_images = offscreen_images.iter().map(|image| image.image).collect();
+			(vk::SwapchainKHR::null(), swapchain_images, offscreen_images)
+		} else {
+			let swapchain = unsafe {
+				swapchain_loader
+					.create_swapchain(&swapchain_create_info, None)
+					.expect("Failed to create the Swapchain.")
+			};
 
-		let swapchain_images = unsafe {
-			swapchain_loader
-				.get_swapchain_images(swapchain)
-				.expect("Failed to get Swapchain Images.")
+			let swapchain_images = unsafe {
+				swapchain_loader
+					.get_swapchain_images(swapchain)
+					.expect("Failed to get Swapchain Images.")
+			};
+			(swapchain, swapchain_images, Vec::new())
 		};
 
 		let swapchain_hdr_images =
 			Swapchain::create_hdr_images(device, surface, swapchain_images.len());
 
 		let mut hdr_attachments = Vec::with_capacity(2);
-		if depth_stencil_image.is_some() {
+		if let Some(depth_stencil_image) = depth_stencil_image.as_ref() {
 			hdr_attachments.resize(2, Default::default());
-			hdr_attachments[1] = depth_stencil_image.as_ref().unwrap().image_view;
+			hdr_attachments[1] = depth_stencil_image.image_view;
 		} else {
 			hdr_attachments.resize(1, Default::default());
 		}
@@ -230,6 +259,7 @@ impl Swapchain {
 			swapchain,
 			swapchain_extent,
 			swapchain_images,
+			offscreen_images,
 			swapchain_image_views,
 			swapchain_image_sampler,
 			depth_stencil_image,
@@ -274,6 +304,41 @@ impl Swapchain {
 		images
 	}
 
+	/// `surface.desired_image_count` images of the surface format, read back with
+	/// transfers.
+	fn create_offscreen_images(
+		device: &Device,
+		surface: &Surface,
+		usage: vk::ImageUsageFlags,
+	) -> Vec<Image> {
+		let extent = vk::Extent3D::builder()
+			.width(surface.surface_resolution.width)
+			.height(surface.surface_resolution.height)
+			.depth(1)
+			.build();
+		(0..surface.desired_image_count)
+			.map(|_| {
+				Image::new(
+					device,
+					vk::ImageCreateFlags::empty(),
+					vk::ImageType::TYPE_2D,
+					surface.desired_format,
+					extent,
+					1,
+					1,
+					vk::ImageTiling::OPTIMAL,
+					usage | vk::ImageUsageFlags::TRANSFER_SRC,
+					device.queue_family_index,
+					vk::ImageLayout::UNDEFINED,
+					vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+					vk::ImageViewType::TYPE_2D,
+					vk::ImageAspectFlags::COLOR,
+					UsageFlags::FAST_DEVICE_ACCESS,
+				)
+			})
+			.collect()
+	}
+
 	pub fn recreate(
 		&mut self,
 		device: &Device,
@@ -295,26 +360,44 @@ impl Swapchain {
 					.destroy_framebuffer(self.swapchain_framebuffers[i], None);
 				// drop(self.swapchain_hdr_images[i]);
 			}
-			self.swapchain_loader
-				.destroy_swapchain(self.swapchain, None);
+			if self.swapchain != vk::SwapchainKHR::null() {
+				self.swapchain_loader
+					.destroy_swapchain(self.swapchain, None);
+			}
 		}
+		self.offscreen_images.clear();
 		self.swapchain_hdr_images.clear();
 		self.swapchain_hdr_framebuffers.clear();
 		self.swapchain_framebuffers.clear();
 		self.swapchain_image_views.clear();
 
+		self.swapchain_create_info.surface = surface.surface;
+		self.swapchain_create_info.pre_transform = surface.pre_transform;
 		self.swapchain_create_info.image_extent = surface.surface_resolution;
-		self.swapchain = unsafe {
-			self.swapchain_loader
-				.create_swapchain(&self.swapchain_create_info, None)
-				.expect("Failed to create the Swapchain.")
-		};
+		if surface.is_headless() {
+			self.offscreen_images = Swapchain::create_offscreen_images(
+				device,
+				surface,
+				self.swapchain_create_info.image_usage,
+			);
+			self.swapchain_images = self
+				.offscreen_images
+				.iter()
+				.map(|image| image.image)
+				.collect();
+		} else {
+			self.swapchain = unsafe {
+				self.swapchain_loader
+					.create_swapchain(&self.swapchain_create_info, None)
+					.expect("Failed to create the Swapchain.")
+			};
 
-		self.swapchain_images = unsafe {
-			self.swapchain_loader
-				.get_swapchain_images(self.swapchain)
-				.expect("Failed to get Swapchain Images.")
-		};
+			self.swapchain_images = unsafe {
+				self.swapchain_loader
+					.get_swapchain_images(self.swapchain)
+					.expect("Failed to get Swapchain Images.")
+			};
+		}
 
 		self.swapchain_hdr_images =
 			Swapchain::create_hdr_images(device, surface, self.swapchain_images.len());
@@ -345,7 +428,6 @@ impl Swapchain {
 
 		let mut hdr_attachments = Vec::with_capacity(2);
 		if self.depth_stencil_image.is_some() {
-			drop(self.depth_stencil_image.as_ref().unwrap());
 			self.depth_stencil_image = depth_image;
 			hdr_attachments.resize(2, Default::default());
 			hdr_attachments[1] = self.depth_stencil_image.as_ref().unwrap().image_view;
@@ -4,7 +4,8 @@ use winit::event_loop::EventLoop;
 use winit::window::WindowBuilder;
 
 pub struct Window {
-	pub event_loop: EventLoop<()>,
+	/// Taken by the main loop, which runs it while the engine keeps the window.
+	pub event_loop: Option<EventLoop<()>>,
 	pub window: winit::window::Window,
 	pub window_extent: vk::Extent2D,
 }
@@ -20,12 +21,12 @@ impl Window {
 			.expect("Failed to build the window.");
 
 		let window_extent = vk::Extent2D::builder()
-			.height(window.inner_size().height as u32)
-			.width(window.inner_size().width as u32)
+			.height(window.inner_size().height)
+			.width(window.inner_size().width)
 			.build();
 
 		Window {
-			event_loop,
+			event_loop: Some(event_loop),
 			window,
 			window_extent,
 		}